@@ -0,0 +1,221 @@
+use crate::{mode::Mode, register::Register};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithmeticOp {
+    Add,
+    Or,
+    Adc,
+    Sbb,
+    And,
+    Sub,
+    Xor,
+    Cmp,
+    Test,
+}
+
+impl ArithmeticOp {
+    pub fn decode(bits: u8) -> Self {
+        match bits {
+            0b000 => Self::Add,
+            0b001 => Self::Or,
+            0b010 => Self::Adc,
+            0b011 => Self::Sbb,
+            0b100 => Self::And,
+            0b101 => Self::Sub,
+            0b110 => Self::Xor,
+            0b111 => Self::Cmp,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            ArithmeticOp::Add => "add",
+            ArithmeticOp::Or => "or",
+            ArithmeticOp::Adc => "adc",
+            ArithmeticOp::Sbb => "sbb",
+            ArithmeticOp::And => "and",
+            ArithmeticOp::Sub => "sub",
+            ArithmeticOp::Xor => "xor",
+            ArithmeticOp::Cmp => "cmp",
+            ArithmeticOp::Test => "test",
+        }
+    }
+
+    /// Whether the result is only used for the flags and never written back.
+    pub fn discards_result(&self) -> bool {
+        matches!(self, ArithmeticOp::Cmp | ArithmeticOp::Test)
+    }
+}
+
+#[derive(Debug)]
+pub struct RegisterMemoryWithRegister {
+    pub(crate) op: ArithmeticOp,
+    pub(crate) d: bool,
+    pub(crate) w: bool,
+    pub(crate) mode: Mode,
+    pub(crate) reg: Register,
+    pub(crate) rm: Register,
+    pub(crate) disp_lo: Option<u8>,
+    pub(crate) disp_hi: Option<u8>,
+}
+
+impl RegisterMemoryWithRegister {
+    pub(crate) fn decode(bytes: &[u8], op: ArithmeticOp) -> Self {
+        let d = (bytes[0] & 0b0000_0010) == 0b0000_0010;
+        let w = (bytes[0] & 0b0000_0001) == 0b0000_0001;
+
+        let (mode, rm, disp_lo, disp_hi) = Mode::decode_rm(bytes, w);
+        let reg = Register::decode_reg((bytes[1] & 0b0011_1000) >> 3, w);
+
+        Self {
+            op,
+            d,
+            w,
+            mode,
+            reg,
+            rm,
+            disp_lo,
+            disp_hi,
+        }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        2 + self.mode.displacement_len()
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        let rm = self.rm.rm_to_string(self.mode, self.disp_lo, self.disp_hi);
+        let reg = self.reg.register_mode_to_string();
+
+        let (dst, src) = if self.d { (reg, rm) } else { (rm, reg) };
+
+        res.push_str(&format!("{} {}, {}\r\n", self.op.mnemonic(), dst, src));
+        res
+    }
+}
+
+#[derive(Debug)]
+pub struct ImmediateToRegisterMemory {
+    pub(crate) op: ArithmeticOp,
+    pub(crate) s: bool,
+    pub(crate) w: bool,
+    pub(crate) mode: Mode,
+    pub(crate) rm: Register,
+    pub(crate) disp_lo: Option<u8>,
+    pub(crate) disp_hi: Option<u8>,
+    pub(crate) data: u16,
+}
+
+impl ImmediateToRegisterMemory {
+    /// Decodes both the `100000sw` group, where the operation sits in the reg
+    /// field, and `test` (`1111011w`), which has no sign-extension bit.
+    pub(crate) fn decode(bytes: &[u8], op: ArithmeticOp) -> Self {
+        let s = op != ArithmeticOp::Test && (bytes[0] & 0b0000_0010) == 0b0000_0010;
+        let w = (bytes[0] & 0b0000_0001) == 0b0000_0001;
+
+        let (mode, rm, disp_lo, disp_hi) = Mode::decode_rm(bytes, w);
+
+        let data_offset = 2 + mode.displacement_len();
+        let data = if w && !s {
+            u16::from_le_bytes([bytes[data_offset], bytes[data_offset + 1]])
+        } else {
+            let res = bytes[data_offset] as i8;
+            let res = res as i16;
+            res as u16
+        };
+
+        Self {
+            op,
+            s,
+            w,
+            mode,
+            rm,
+            disp_lo,
+            disp_hi,
+            data,
+        }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        let mut res = 3 + self.mode.displacement_len();
+
+        if self.w && !self.s {
+            res += 1;
+        }
+
+        res
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        let dst = match self.mode {
+            Mode::Reg => self.rm.register_mode_to_string(),
+            _ => format!(
+                "{} {}",
+                if self.w { "word" } else { "byte" },
+                self.rm
+                    .memory_mode_to_string(self.mode, self.disp_lo, self.disp_hi)
+            ),
+        };
+
+        let src = if self.s {
+            (self.data as i16).to_string()
+        } else if self.w {
+            self.data.to_string()
+        } else {
+            (self.data as u8).to_string()
+        };
+
+        res.push_str(&format!("{} {}, {}\r\n", self.op.mnemonic(), dst, src));
+        res
+    }
+}
+
+#[derive(Debug)]
+pub struct ImmediateToAccumulator {
+    pub(crate) op: ArithmeticOp,
+    pub(crate) w: bool,
+    pub(crate) data: u16,
+}
+
+impl ImmediateToAccumulator {
+    pub(crate) fn decode(bytes: &[u8], op: ArithmeticOp) -> Self {
+        let w = (bytes[0] & 0b0000_0001) == 0b0000_0001;
+
+        let data = if w {
+            u16::from_le_bytes([bytes[1], bytes[2]])
+        } else {
+            bytes[1] as u16
+        };
+
+        Self { op, w, data }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        let mut res = 2;
+
+        if self.w {
+            res += 1;
+        }
+
+        res
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        let dst = if self.w { Register::AX } else { Register::AL }.register_mode_to_string();
+
+        res.push_str(&format!(
+            "{} {}, {}\r\n",
+            self.op.mnemonic(),
+            dst,
+            self.data
+        ));
+        res
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::{clocks::CpuModel, memory::Memory};
+
+/// T-states of a bus cycle without wait states.
+const CYCLE_STATES: u32 = 4;
+
+/// What a bus cycle does, as the status lines S2-S0 report it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusStatus {
+    Code,
+    MemoryRead,
+    MemoryWrite,
+    IoRead,
+    IoWrite,
+}
+
+impl BusStatus {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            BusStatus::Code => "CODE",
+            BusStatus::MemoryRead => "MEMR",
+            BusStatus::MemoryWrite => "MEMW",
+            BusStatus::IoRead => "IOR",
+            BusStatus::IoWrite => "IOW",
+        }
+    }
+
+    fn is_io(&self) -> bool {
+        matches!(self, BusStatus::IoRead | BusStatus::IoWrite)
+    }
+}
+
+/// A transfer the execution unit made while executing an instruction, for
+/// the bus unit to replay with its timing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BusAccess {
+    pub(crate) status: BusStatus,
+    /// Segment and offset, or 0 and the port for I/O.
+    pub(crate) segment: u16,
+    pub(crate) offset: u16,
+    pub(crate) w: bool,
+    /// Bytes a memory write replaced, which prefetches made before the
+    /// write still see.
+    pub(crate) old: [u8; 2],
+}
+
+/// Settings of the bus model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusConfig {
+    /// Wait states added to every memory and every I/O bus cycle. The IBM PC
+    /// adds none to memory and one to I/O.
+    pub wait_states: u32,
+    pub io_wait_states: u32,
+    /// Keep a line per T-state for [`Bus::take_trace`].
+    pub trace: bool,
+}
+
+impl Default for BusConfig {
+    fn default() -> Self {
+        Self {
+            wait_states: 0,
+            io_wait_states: 1,
+            trace: false,
+        }
+    }
+}
+
+/// A bus cycle in progress.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cycle {
+    status: BusStatus,
+    address: usize,
+    /// Bytes transferred: 1, or 2 for an 8086 word at an even address.
+    len: u8,
+    /// T-states done so far, and in all.
+    done: u32,
+    states: u32,
+    /// Whether the queue was flushed while this prefetch was under way, so
+    /// its bytes are thrown away.
+    discard: bool,
+}
+
+/// The bus interface unit of an 8088 or 8086: the prefetch queue, 4 bytes
+/// on the 8088 and 6 on the 8086, and the bus cycles that fill it and carry
+/// the execution unit's transfers, T-state by T-state.
+///
+/// Instructions are decoded from the queue, so code that modifies bytes
+/// already prefetched runs the old bytes, as on the real processors. The
+/// execution unit's time is the clock estimate less the bus cycles it
+/// contains; its transfers take the bus as soon as the cycle in progress
+/// ends, and the queue is filled whenever the bus is free and the queue has
+/// room: one free byte on the 8088, two on the 8086, which fetches words.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bus {
+    config: BusConfig,
+    model: CpuModel,
+    queue: VecDeque<u8>,
+    /// CS:IP of the first byte in the queue, and of the next byte to fetch.
+    head: (u16, u16),
+    fetch: (u16, u16),
+    cycle: Option<Cycle>,
+    /// T-states since reset.
+    cycles: u64,
+    trace: Vec<String>,
+}
+
+impl Bus {
+    pub fn new(config: BusConfig, model: CpuModel) -> Self {
+        Self {
+            config,
+            model,
+            queue: VecDeque::new(),
+            head: (0, 0),
+            fetch: (0, 0),
+            cycle: None,
+            cycles: 0,
+            trace: Vec::new(),
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Bytes in the prefetch queue, first to be executed first.
+    pub fn queue(&self) -> Vec<u8> {
+        self.queue.iter().copied().collect()
+    }
+
+    /// Lines for each T-state since the last call, if tracing.
+    pub fn take_trace(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.trace)
+    }
+
+    /// The bytes the execution unit would decode at `cs:ip`: the queue,
+    /// then memory past it. A queue that doesn't start at `cs:ip`, as after
+    /// an interrupt, is flushed first.
+    pub(crate) fn upcoming(&mut self, memory: &Memory, cs: u16, ip: u16, len: usize) -> Vec<u8> {
+        if self.head != (cs, ip) {
+            self.flush(cs, ip);
+        }
+
+        let (segment, offset) = self.fetch;
+        let mut bytes = self.queue();
+        let fetched = bytes.len();
+        bytes.extend(
+            (0..len.saturating_sub(fetched))
+                .map(|i| memory.read_byte(segment, offset.wrapping_add(i as u16))),
+        );
+
+        bytes
+    }
+
+    /// Replays an instruction of `len` bytes that has executed: takes its
+    /// bytes from the queue, spends `ea` internal clocks, then the rest of
+    /// `clocks` spread around its transfers, and continues at `next`,
+    /// flushing the queue if the instruction jumped.
+    pub(crate) fn execute(
+        &mut self,
+        memory: &Memory,
+        len: usize,
+        ea: u32,
+        clocks: u32,
+        accesses: &[BusAccess],
+        next: (u16, u16),
+    ) {
+        // Prefetches before a write see the bytes it replaced.
+        let mut pending = BTreeMap::new();
+        for access in accesses {
+            if access.status == BusStatus::MemoryWrite {
+                for (i, address) in self.addresses(access).into_iter().enumerate() {
+                    pending.entry(address).or_insert(access.old[i]);
+                }
+            }
+        }
+
+        for _ in 0..len {
+            while self.queue.is_empty() {
+                self.tick(memory, &pending);
+            }
+            self.queue.pop_front();
+            self.head.1 = self.head.1.wrapping_add(1);
+        }
+
+        let transfers: u32 = accesses
+            .iter()
+            .map(|access| self.split(access).len() as u32)
+            .sum();
+        let internal = clocks.saturating_sub(CYCLE_STATES * transfers);
+        let ea = ea.min(internal);
+        let rest = internal - ea;
+        let gaps = accesses.len() as u32 + 1;
+
+        self.idle(memory, &pending, ea + rest / gaps + rest % gaps);
+        for access in accesses {
+            for (address, len) in self.split(access) {
+                self.transfer(memory, &pending, access.status, address, len);
+            }
+            if access.status == BusStatus::MemoryWrite {
+                for address in self.addresses(access) {
+                    pending.remove(&address);
+                }
+            }
+            self.idle(memory, &pending, rest / gaps);
+        }
+
+        if self.head != next {
+            self.flush(next.0, next.1);
+        }
+    }
+
+    /// Empties the queue and restarts prefetching at `cs:ip`. A prefetch
+    /// under way completes, but its bytes are dropped.
+    fn flush(&mut self, cs: u16, ip: u16) {
+        self.queue.clear();
+        self.head = (cs, ip);
+        self.fetch = (cs, ip);
+
+        if let Some(cycle) = &mut self.cycle {
+            if cycle.status == BusStatus::Code {
+                cycle.discard = true;
+            }
+        }
+    }
+
+    fn idle(&mut self, memory: &Memory, pending: &BTreeMap<usize, u8>, clocks: u32) {
+        for _ in 0..clocks {
+            self.tick(memory, pending);
+        }
+    }
+
+    /// Waits for the bus, then runs a bus cycle for the execution unit.
+    fn transfer(
+        &mut self,
+        memory: &Memory,
+        pending: &BTreeMap<usize, u8>,
+        status: BusStatus,
+        address: usize,
+        len: u8,
+    ) {
+        while self.cycle.is_some() {
+            self.tick(memory, pending);
+        }
+
+        self.cycle = Some(self.start(status, address, len));
+        while self.cycle.is_some() {
+            self.tick(memory, pending);
+        }
+    }
+
+    fn start(&self, status: BusStatus, address: usize, len: u8) -> Cycle {
+        let waits = if status.is_io() {
+            self.config.io_wait_states
+        } else {
+            self.config.wait_states
+        };
+
+        Cycle {
+            status,
+            address,
+            len,
+            done: 0,
+            states: CYCLE_STATES + waits,
+            discard: false,
+        }
+    }
+
+    /// One T-state: starts a prefetch if the bus is free and the queue has
+    /// room, then advances the cycle on the bus.
+    fn tick(&mut self, memory: &Memory, pending: &BTreeMap<usize, u8>) {
+        let size = self.queue_size();
+        let room = size - self.queue.len();
+
+        if self.cycle.is_none() && room >= self.fetch_width() {
+            let (segment, offset) = self.fetch;
+            let len = match self.model {
+                CpuModel::I8086 if offset & 1 == 0 => 2,
+                _ => 1,
+            };
+            let address = Memory::physical_address(segment, offset);
+            self.cycle = Some(self.start(BusStatus::Code, address, len));
+            self.fetch.1 = offset.wrapping_add(len as u16);
+        }
+
+        let read = |address: usize| {
+            pending
+                .get(&address)
+                .copied()
+                .unwrap_or_else(|| memory.read_physical(address))
+        };
+
+        let Some(cycle) = &mut self.cycle else {
+            if self.config.trace {
+                self.trace.push(self.line("Ti", "", "", ""));
+            }
+            self.cycles += 1;
+            return;
+        };
+
+        cycle.done += 1;
+        let state = match cycle.done {
+            1 => "T1".to_string(),
+            done if done == cycle.states => "T4".to_string(),
+            2 => "T2".to_string(),
+            3 => "T3".to_string(),
+            _ => "Tw".to_string(),
+        };
+        let finished = cycle.done == cycle.states;
+        let cycle = cycle.clone();
+
+        let bytes: Vec<u8> = (0..cycle.len as usize)
+            .filter_map(|i| match cycle.status {
+                BusStatus::Code | BusStatus::MemoryRead => Some(read(cycle.address + i)),
+                BusStatus::MemoryWrite => Some(memory.read_physical(cycle.address + i)),
+                BusStatus::IoRead | BusStatus::IoWrite => None,
+            })
+            .collect();
+        if finished {
+            if cycle.status == BusStatus::Code && !cycle.discard {
+                self.queue.extend(&bytes);
+            }
+            self.cycle = None;
+        }
+
+        if self.config.trace {
+            let address = if cycle.status.is_io() {
+                format!("{:04X}", cycle.address)
+            } else {
+                format!("{:05X}", cycle.address)
+            };
+            let data: String = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+            let line = self.line(
+                &state,
+                cycle.status.mnemonic(),
+                if cycle.done == 1 { &address } else { "" },
+                if finished { &data } else { "" },
+            );
+            self.trace.push(line);
+        }
+        self.cycles += 1;
+    }
+
+    fn line(&self, state: &str, status: &str, address: &str, data: &str) -> String {
+        let queue: String = self
+            .queue
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect();
+
+        format!(
+            "{:>8} {state} {status:<4} {address:<5} {data:<4} q:{queue}",
+            self.cycles
+        )
+    }
+
+    fn queue_size(&self) -> usize {
+        match self.model {
+            CpuModel::I8086 => 6,
+            CpuModel::I8088 => 4,
+        }
+    }
+
+    /// Free bytes the queue needs before a prefetch starts.
+    fn fetch_width(&self) -> usize {
+        match self.model {
+            CpuModel::I8086 => 2,
+            CpuModel::I8088 => 1,
+        }
+    }
+
+    /// Physical addresses, or ports, of the bytes of a transfer.
+    fn addresses(&self, access: &BusAccess) -> Vec<usize> {
+        let count = if access.w { 2 } else { 1 };
+
+        (0..count)
+            .map(|i| {
+                let offset = access.offset.wrapping_add(i);
+                if access.status.is_io() {
+                    offset as usize
+                } else {
+                    Memory::physical_address(access.segment, offset)
+                }
+            })
+            .collect()
+    }
+
+    /// The bus cycles of a transfer as `(address, bytes)`: one per byte on
+    /// the 8088, and on the 8086 one per word unless the word is at an odd
+    /// address.
+    fn split(&self, access: &BusAccess) -> Vec<(usize, u8)> {
+        let addresses = self.addresses(access);
+
+        match self.model {
+            CpuModel::I8086 if access.w && access.offset & 1 == 0 => vec![(addresses[0], 2)],
+            _ => addresses.into_iter().map(|address| (address, 1)).collect(),
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::instruction::Instruction;
+
+/// How control gets from one block to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeKind {
+    /// A jump, or a conditional branch when its condition holds.
+    Taken,
+    /// On to the next instruction: past a branch not taken, a call that
+    /// returned or an interrupt, or into a block that starts at a target.
+    Fallthrough,
+    Call,
+    /// From a block ending in `ret` to the instruction after each call of
+    /// the routine it belongs to.
+    Return,
+}
+
+impl EdgeKind {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            EdgeKind::Taken => "taken",
+            EdgeKind::Fallthrough => "fallthrough",
+            EdgeKind::Call => "call",
+            EdgeKind::Return => "return",
+        }
+    }
+
+    fn style(&self) -> &'static str {
+        match self {
+            EdgeKind::Taken => "solid",
+            EdgeKind::Fallthrough => "dashed",
+            EdgeKind::Call => "bold",
+            EdgeKind::Return => "dotted",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+/// A run of instructions entered only at the top and left only at the
+/// bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    /// Address past the last instruction.
+    pub end: usize,
+    /// Each instruction's address and disassembly.
+    pub instructions: Vec<(usize, String)>,
+}
+
+/// Control-flow graph of decoded instructions, blocks keyed by their start
+/// address. Only branches encoded relative to IP have known targets; edges
+/// to addresses that don't start a decoded instruction are left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Graph {
+    pub blocks: BTreeMap<usize, Block>,
+    pub edges: BTreeSet<Edge>,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `instructions`, given with their offsets in a region loaded at
+    /// `address`. Blocks start at the first instruction, at branch targets
+    /// and after branches and calls.
+    pub(crate) fn add(&mut self, instructions: &[(usize, Instruction)], address: usize) {
+        let Some((first, _)) = instructions.first() else {
+            return;
+        };
+
+        let mut leaders = BTreeSet::from([*first]);
+        for (offset, instruction) in instructions {
+            if let Some(target) = instruction.relative_target(*offset) {
+                leaders.insert(target);
+            }
+            if instruction.is_branch() || instruction.is_call() {
+                leaders.insert(offset + instruction.offset());
+            }
+        }
+
+        let starts: BTreeSet<usize> = instructions.iter().map(|(offset, _)| *offset).collect();
+        let mut edges = Vec::new();
+        // Call sites as (target, return address).
+        let mut calls = Vec::new();
+        // Blocks that end in a return.
+        let mut returns = BTreeSet::new();
+
+        let mut iter = instructions.iter().peekable();
+        while let Some((start, _)) = iter.peek() {
+            let start = *start;
+            let mut block = Block {
+                start: address + start,
+                end: address + start,
+                instructions: Vec::new(),
+            };
+
+            let mut last = None;
+            while let Some((offset, instruction)) =
+                iter.next_if(|(offset, _)| *offset == start || !leaders.contains(offset))
+            {
+                block.instructions.push((
+                    address + offset,
+                    instruction.to_string().trim_end().to_string(),
+                ));
+                block.end = address + offset + instruction.offset();
+                last = Some((*offset, instruction));
+            }
+
+            let Some((offset, instruction)) = last else {
+                break;
+            };
+            let next = offset + instruction.offset();
+            let target = instruction
+                .relative_target(offset)
+                .filter(|target| starts.contains(target));
+
+            let mut edge = |to: usize, kind| {
+                if starts.contains(&to) {
+                    edges.push((start, to, kind));
+                }
+            };
+
+            if instruction.is_call() {
+                if let Some(target) = target {
+                    edge(target, EdgeKind::Call);
+                    calls.push((target, next));
+                }
+                edge(next, EdgeKind::Fallthrough);
+            } else if instruction.is_return() {
+                // Added below, once the routines are known.
+                returns.insert(start);
+            } else if instruction.is_branch() {
+                if let Some(target) = target {
+                    edge(target, EdgeKind::Taken);
+                }
+                if instruction.falls_through() {
+                    edge(next, EdgeKind::Fallthrough);
+                }
+            } else {
+                edge(next, EdgeKind::Fallthrough);
+            }
+
+            self.blocks.insert(block.start, block);
+        }
+
+        // A routine is every block reachable from its entry without calls;
+        // each of its returns goes back after each call.
+        for (target, next) in calls {
+            let mut seen = BTreeSet::new();
+            let mut stack = vec![target];
+            while let Some(start) = stack.pop() {
+                if !seen.insert(start) {
+                    continue;
+                }
+                stack.extend(
+                    edges
+                        .iter()
+                        .filter(|(from, _, kind)| *from == start && *kind != EdgeKind::Call)
+                        .map(|(_, to, _)| *to),
+                );
+            }
+
+            for start in seen.intersection(&returns) {
+                edges.push((*start, next, EdgeKind::Return));
+            }
+        }
+
+        self.edges
+            .extend(edges.into_iter().map(|(from, to, kind)| Edge {
+                from: address + from,
+                to: address + to,
+                kind,
+            }));
+    }
+
+    /// The graph in Graphviz DOT, each node listing its block's disassembly
+    /// and each edge labelled with its kind.
+    pub fn to_dot(&self) -> String {
+        let mut res = String::new();
+        res.push_str("digraph cfg {\r\n");
+        res.push_str("    node [shape=box, fontname=\"monospace\"];\r\n");
+
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for (address, text) in &block.instructions {
+                label.push_str(&format!("{address:#06x}  {}\\l", escape(text)));
+            }
+            res.push_str(&format!(
+                "    {} [label=\"{label}\"];\r\n",
+                node(block.start)
+            ));
+        }
+
+        for edge in &self.edges {
+            res.push_str(&format!(
+                "    {} -> {} [label=\"{}\", style={}];\r\n",
+                node(edge.from),
+                node(edge.to),
+                edge.kind.mnemonic(),
+                edge.kind.style()
+            ));
+        }
+
+        res.push_str("}\r\n");
+        res
+    }
+}
+
+fn node(address: usize) -> String {
+    format!("b_{address:04x}")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,467 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    arithmetic::ArithmeticOp,
+    instruction::Instruction,
+    jump::{ConditionalOp, Transfer},
+    mode::Mode,
+    register::Register,
+    simple::SimpleOp,
+    stack::StackOp,
+    string::StringOp,
+    transfer::LoadOp,
+    unary::UnaryOp,
+};
+
+/// Which processor's bus timing to estimate for. The 8086 pays 4 extra clocks
+/// for each word transferred to or from an odd address, the 8088 for every
+/// word transferred since its bus is only 8 bits wide.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CpuModel {
+    #[default]
+    I8086,
+    I8088,
+}
+
+impl CpuModel {
+    /// Extra clocks for transferring a word at address `addr`.
+    pub fn transfer_penalty(&self, addr: u16) -> u32 {
+        match self {
+            CpuModel::I8086 if addr & 1 == 0 => 0,
+            CpuModel::I8086 | CpuModel::I8088 => 4,
+        }
+    }
+}
+
+/// Clock estimate for one instruction, split the way the 8086 manual's timing
+/// tables are: a base cost, the effective-address cost and transfer penalties.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Clocks {
+    pub base: u32,
+    pub ea: u32,
+    pub penalty: u32,
+}
+
+impl Clocks {
+    pub fn total(&self) -> u32 {
+        self.base + self.ea + self.penalty
+    }
+
+    /// Best-case estimate for `instruction` without transfer penalties.
+    /// `taken` selects the cost of a conditional branch.
+    pub fn estimate(instruction: &Instruction, taken: bool) -> Self {
+        let (base, ea) = match instruction {
+            Instruction::RegisterMemoryToFromRegister(i) => match (i.mode, i.d) {
+                (Mode::Reg, _) => (2, 0),
+                (_, true) => (8, ea_clocks(i.mode, i.rm)),
+                (_, false) => (9, ea_clocks(i.mode, i.rm)),
+            },
+            Instruction::ImmediateToRegisterMemory(i) => match i.mode {
+                Mode::Reg => (4, 0),
+                _ => (10, ea_clocks(i.mode, i.rm)),
+            },
+            Instruction::ImmediateToRegister(_) => (4, 0),
+            Instruction::MemoryToAccumulator(_) | Instruction::AccumulatorToMemory(_) => (10, 0),
+            Instruction::RegisterMemoryToSegmentRegister(i) => match i.mode {
+                Mode::Reg => (2, 0),
+                _ => (8, ea_clocks(i.mode, i.rm)),
+            },
+            Instruction::SegmentRegisterToRegisterMemory(i) => match i.mode {
+                Mode::Reg => (2, 0),
+                _ => (9, ea_clocks(i.mode, i.rm)),
+            },
+            Instruction::ArithmeticRegisterMemoryWithRegister(i) => match (i.mode, i.d, i.op) {
+                (Mode::Reg, _, _) => (3, 0),
+                (_, true, _) | (_, false, ArithmeticOp::Cmp | ArithmeticOp::Test) => {
+                    (9, ea_clocks(i.mode, i.rm))
+                }
+                (_, false, _) => (16, ea_clocks(i.mode, i.rm)),
+            },
+            Instruction::ArithmeticImmediateToRegisterMemory(i) => match (i.mode, i.op) {
+                (Mode::Reg, ArithmeticOp::Test) => (5, 0),
+                (Mode::Reg, _) => (4, 0),
+                (_, ArithmeticOp::Cmp) => (10, ea_clocks(i.mode, i.rm)),
+                (_, ArithmeticOp::Test) => (11, ea_clocks(i.mode, i.rm)),
+                (_, _) => (17, ea_clocks(i.mode, i.rm)),
+            },
+            Instruction::ArithmeticImmediateToAccumulator(_) => (4, 0),
+            Instruction::ConditionalJump(i) => {
+                let (taken_clocks, not_taken_clocks) = match i.op {
+                    ConditionalOp::Loop => (17, 5),
+                    ConditionalOp::Loopz => (18, 6),
+                    ConditionalOp::Loopnz => (19, 5),
+                    ConditionalOp::Jcxz => (18, 6),
+                    _ => (16, 4),
+                };
+
+                if taken {
+                    (taken_clocks, 0)
+                } else {
+                    (not_taken_clocks, 0)
+                }
+            }
+            Instruction::DirectWithinSegment(i) => match i.transfer {
+                Transfer::Jmp => (15, 0),
+                Transfer::Call => (19, 0),
+            },
+            Instruction::DirectIntersegment(i) => match i.transfer {
+                Transfer::Jmp => (15, 0),
+                Transfer::Call => (28, 0),
+            },
+            Instruction::Indirect(i) => match (i.transfer, i.far, i.mode) {
+                (Transfer::Jmp, _, Mode::Reg) => (11, 0),
+                (Transfer::Jmp, false, _) => (18, ea_clocks(i.mode, i.rm)),
+                (Transfer::Jmp, true, _) => (24, ea_clocks(i.mode, i.rm)),
+                (Transfer::Call, _, Mode::Reg) => (16, 0),
+                (Transfer::Call, false, _) => (21, ea_clocks(i.mode, i.rm)),
+                (Transfer::Call, true, _) => (37, ea_clocks(i.mode, i.rm)),
+            },
+            Instruction::Return(i) => match (i.far, i.data) {
+                (false, None) => (8, 0),
+                (false, Some(_)) => (12, 0),
+                (true, None) => (18, 0),
+                (true, Some(_)) => (17, 0),
+            },
+            Instruction::Halt => (2, 0),
+            Instruction::StackRegisterMemory(i) => match (i.op, i.mode) {
+                (StackOp::Push, Mode::Reg) => (11, 0),
+                (StackOp::Pop, Mode::Reg) => (8, 0),
+                (StackOp::Push, _) => (16, ea_clocks(i.mode, i.rm)),
+                (StackOp::Pop, _) => (17, ea_clocks(i.mode, i.rm)),
+            },
+            Instruction::StackRegister(i) => match i.op {
+                StackOp::Push => (11, 0),
+                StackOp::Pop => (8, 0),
+            },
+            Instruction::StackSegmentRegister(i) => match i.op {
+                StackOp::Push => (10, 0),
+                StackOp::Pop => (8, 0),
+            },
+            Instruction::Exchange(i) => match i.mode {
+                Mode::Reg => (4, 0),
+                _ => (17, ea_clocks(i.mode, i.rm)),
+            },
+            Instruction::ExchangeAccumulator(_) => (3, 0),
+            Instruction::Port(i) => match i.port {
+                Some(_) => (10, 0),
+                None => (8, 0),
+            },
+            Instruction::LoadAddress(i) => match i.op {
+                LoadOp::Lea => (2, ea_clocks(i.mode, i.rm)),
+                LoadOp::Lds | LoadOp::Les => (16, ea_clocks(i.mode, i.rm)),
+            },
+            // Multiplication and division take a range of clocks depending on
+            // the operands; the best case is used here.
+            Instruction::UnaryRegisterMemory(i) => {
+                let base = match (i.op, i.mode != Mode::Reg, i.w) {
+                    (UnaryOp::Inc | UnaryOp::Dec, false, true) => 2,
+                    (UnaryOp::Inc | UnaryOp::Dec, false, false) => 3,
+                    (UnaryOp::Inc | UnaryOp::Dec, true, _) => 15,
+                    (UnaryOp::Not | UnaryOp::Neg, false, _) => 3,
+                    (UnaryOp::Not | UnaryOp::Neg, true, _) => 16,
+                    (UnaryOp::Mul, false, false) => 70,
+                    (UnaryOp::Mul, false, true) => 118,
+                    (UnaryOp::Mul, true, false) => 76,
+                    (UnaryOp::Mul, true, true) => 124,
+                    (UnaryOp::Imul, false, false) => 80,
+                    (UnaryOp::Imul, false, true) => 128,
+                    (UnaryOp::Imul, true, false) => 86,
+                    (UnaryOp::Imul, true, true) => 134,
+                    (UnaryOp::Div, false, false) => 80,
+                    (UnaryOp::Div, false, true) => 144,
+                    (UnaryOp::Div, true, false) => 86,
+                    (UnaryOp::Div, true, true) => 150,
+                    (UnaryOp::Idiv, false, false) => 101,
+                    (UnaryOp::Idiv, false, true) => 165,
+                    (UnaryOp::Idiv, true, false) => 107,
+                    (UnaryOp::Idiv, true, true) => 171,
+                };
+
+                (base, ea_clocks(i.mode, i.rm))
+            }
+            Instruction::UnaryRegister(_) => (2, 0),
+            Instruction::AsciiAdjust(i) => {
+                if i.multiply {
+                    (83, 0)
+                } else {
+                    (60, 0)
+                }
+            }
+            // Shifts by CL also take 4 clocks per bit, which only execution
+            // knows.
+            Instruction::Shift(i) => match (i.mode, i.v) {
+                (Mode::Reg, false) => (2, 0),
+                (Mode::Reg, true) => (8, 0),
+                (_, false) => (15, ea_clocks(i.mode, i.rm)),
+                (_, true) => (20, ea_clocks(i.mode, i.rm)),
+            },
+            Instruction::String(i) => match i.op {
+                StringOp::Movs => (18, 0),
+                StringOp::Cmps => (22, 0),
+                StringOp::Stos => (11, 0),
+                StringOp::Lods => (12, 0),
+                StringOp::Scas => (15, 0),
+            },
+            Instruction::Interrupt(_) => (51, 0),
+            Instruction::Simple(op) => match op {
+                SimpleOp::Nop | SimpleOp::Wait => (3, 0),
+                SimpleOp::Xlat => (11, 0),
+                SimpleOp::Lahf | SimpleOp::Sahf => (4, 0),
+                SimpleOp::Pushf => (10, 0),
+                SimpleOp::Popf => (8, 0),
+                SimpleOp::Cbw => (2, 0),
+                SimpleOp::Cwd => (5, 0),
+                SimpleOp::Aaa | SimpleOp::Daa | SimpleOp::Aas | SimpleOp::Das => (4, 0),
+                SimpleOp::Int3 => (52, 0),
+                SimpleOp::Into if taken => (53, 0),
+                SimpleOp::Into => (4, 0),
+                SimpleOp::Iret => (24, 0),
+                SimpleOp::Clc
+                | SimpleOp::Cmc
+                | SimpleOp::Stc
+                | SimpleOp::Cld
+                | SimpleOp::Std
+                | SimpleOp::Cli
+                | SimpleOp::Sti => (2, 0),
+            },
+            Instruction::SegmentOverride(_, i) => {
+                let res = Self::estimate(i, taken);
+
+                // The override costs 2 clocks on top of the address calculation.
+                let ea = if res.ea > 0 { res.ea + 2 } else { 0 };
+                (res.base, ea)
+            }
+            // Repeated string instructions cost 9 clocks plus a cost per
+            // repetition that only execution knows. Any other instruction just
+            // pays for the prefix byte.
+            Instruction::Repeat(_, i) => match i.as_ref() {
+                Instruction::String(_) => (9, 0),
+                Instruction::SegmentOverride(_, s)
+                    if matches!(s.as_ref(), Instruction::String(_)) =>
+                {
+                    (9, 0)
+                }
+                _ => {
+                    let res = Self::estimate(i, taken);
+                    (res.base + 2, res.ea)
+                }
+            },
+            Instruction::Lock(i) => {
+                let res = Self::estimate(i, taken);
+                (res.base + 2, res.ea)
+            }
+        };
+
+        Self {
+            base,
+            ea,
+            penalty: 0,
+        }
+    }
+
+    /// Best-case estimate for `instruction` on `model` without executing it:
+    /// word transfers are assumed to be aligned, which only the 8088 cannot
+    /// benefit from.
+    pub fn estimate_static(instruction: &Instruction, taken: bool, model: CpuModel) -> Self {
+        let mut res = Self::estimate(instruction, taken);
+
+        if model == CpuModel::I8088 {
+            res.penalty = 4 * word_transfers(instruction);
+        }
+
+        res
+    }
+
+    /// Breakdown shown after the running total in traces, e.g. ` (8 + 5ea)`,
+    /// or nothing when there is only a base cost.
+    pub fn breakdown(&self) -> String {
+        if self.ea == 0 && self.penalty == 0 {
+            return String::new();
+        }
+
+        let mut res = format!(" ({}", self.base);
+        if self.ea > 0 {
+            res.push_str(&format!(" + {}ea", self.ea));
+        }
+        if self.penalty > 0 {
+            res.push_str(&format!(" + {}p", self.penalty));
+        }
+        res.push(')');
+
+        res
+    }
+}
+
+/// Comment appended to an instruction in annotated disassembly, e.g.
+/// `clocks: 13 (8 + 5ea)` or `clocks: 16 taken, 4 not taken`.
+pub(crate) fn annotation(instruction: &Instruction, model: CpuModel) -> String {
+    let taken = Clocks::estimate_static(instruction, true, model);
+
+    if instruction.is_conditional() {
+        let not_taken = Clocks::estimate_static(instruction, false, model);
+
+        format!(
+            "clocks: {}{} taken, {}{} not taken",
+            taken.total(),
+            taken.breakdown(),
+            not_taken.total(),
+            not_taken.breakdown()
+        )
+    } else {
+        format!("clocks: {}{}", taken.total(), taken.breakdown())
+    }
+}
+
+/// Summary of best-case clocks per basic block of a linear disassembly, given
+/// every instruction with its offset. Blocks start at offset 0, at branch
+/// targets and after branches.
+pub(crate) fn basic_block_summary(
+    instructions: &[(usize, Instruction)],
+    model: CpuModel,
+) -> String {
+    let mut leaders = BTreeSet::from([0]);
+    for (offset, instruction) in instructions {
+        if let Some(target) = instruction.relative_target(*offset) {
+            leaders.insert(target);
+        }
+        if instruction.is_branch() {
+            leaders.insert(offset + instruction.offset());
+        }
+    }
+
+    let mut res = String::new();
+    res.push_str("\r\n; Static clocks per basic block (best case):\r\n");
+
+    let mut blocks = instructions.iter().peekable();
+    while let Some((start, _)) = blocks.peek() {
+        let start = *start;
+
+        let mut total = 0;
+        let mut end = start;
+        let mut taken = None;
+        while let Some((offset, instruction)) =
+            blocks.next_if(|(offset, _)| *offset == start || !leaders.contains(offset))
+        {
+            total += Clocks::estimate_static(instruction, false, model).total();
+            end = offset + instruction.offset();
+
+            if instruction.is_conditional() {
+                let difference = Clocks::estimate_static(instruction, true, model).total()
+                    - Clocks::estimate_static(instruction, false, model).total();
+                taken = Some(total + difference);
+            }
+        }
+
+        match taken {
+            Some(taken) => res.push_str(&format!(
+                "; {start:#06x}-{end:#06x}: {total} clocks, {taken} if the branch is taken\r\n"
+            )),
+            None => res.push_str(&format!("; {start:#06x}-{end:#06x}: {total} clocks\r\n")),
+        }
+    }
+
+    res
+}
+
+/// Effective-address calculation time from the 8086 manual for a memory
+/// operand encoded with `mode` and `rm`.
+///
+/// The manual's table is followed on purpose where other sources differ:
+/// `[bp + di + disp]` takes 11 clocks like `[bx + si + disp]`, and only the
+/// `bp + si` and `bx + di` pairs take 12.
+pub fn ea_clocks(mode: Mode, rm: Register) -> u32 {
+    match mode {
+        Mode::Reg => 0,
+        Mode::DirectAddress => 6,
+        Mode::Mem => match rm {
+            Register::AL | Register::AX => 7,
+            Register::CL | Register::CX => 8,
+            Register::DL | Register::DX => 8,
+            Register::BL | Register::BX => 7,
+            _ => 5,
+        },
+        Mode::Mem8 | Mode::Mem16 => match rm {
+            Register::AL | Register::AX => 11,
+            Register::CL | Register::CX => 12,
+            Register::DL | Register::DX => 12,
+            Register::BL | Register::BX => 11,
+            _ => 9,
+        },
+    }
+}
+
+/// Number of words `instruction` moves over the bus for its operands,
+/// including stack pushes and pops.
+pub fn word_transfers(instruction: &Instruction) -> u32 {
+    let memory = |mode, w, transfers| if mode != Mode::Reg && w { transfers } else { 0 };
+
+    match instruction {
+        Instruction::RegisterMemoryToFromRegister(i) => memory(i.mode, i.w, 1),
+        Instruction::ImmediateToRegisterMemory(i) => memory(i.mode, i.w, 1),
+        Instruction::ImmediateToRegister(_) => 0,
+        Instruction::MemoryToAccumulator(i) => i.w as u32,
+        Instruction::AccumulatorToMemory(i) => i.w as u32,
+        Instruction::RegisterMemoryToSegmentRegister(i) => memory(i.mode, true, 1),
+        Instruction::SegmentRegisterToRegisterMemory(i) => memory(i.mode, true, 1),
+        Instruction::ArithmeticRegisterMemoryWithRegister(i) => {
+            let writes = !i.d && !i.op.discards_result();
+            memory(i.mode, i.w, 1 + writes as u32)
+        }
+        Instruction::ArithmeticImmediateToRegisterMemory(i) => {
+            memory(i.mode, i.w, 1 + !i.op.discards_result() as u32)
+        }
+        Instruction::ArithmeticImmediateToAccumulator(_) => 0,
+        Instruction::ConditionalJump(_) => 0,
+        Instruction::DirectWithinSegment(i) => (i.transfer == Transfer::Call) as u32,
+        Instruction::DirectIntersegment(i) => 2 * (i.transfer == Transfer::Call) as u32,
+        Instruction::Indirect(i) => {
+            let (reads, pushes) = if i.far { (2, 2) } else { (1, 1) };
+            let pushes = if i.transfer == Transfer::Call {
+                pushes
+            } else {
+                0
+            };
+
+            memory(i.mode, true, reads) + pushes
+        }
+        Instruction::Return(i) => {
+            if i.far {
+                2
+            } else {
+                1
+            }
+        }
+        Instruction::Halt => 0,
+        Instruction::StackRegisterMemory(i) => 1 + memory(i.mode, true, 1),
+        Instruction::StackRegister(_) | Instruction::StackSegmentRegister(_) => 1,
+        Instruction::Exchange(i) => memory(i.mode, i.w, 2),
+        Instruction::ExchangeAccumulator(_) => 0,
+        Instruction::Port(i) => i.w as u32,
+        Instruction::LoadAddress(i) => match i.op {
+            LoadOp::Lea => 0,
+            LoadOp::Lds | LoadOp::Les => 2,
+        },
+        Instruction::UnaryRegisterMemory(i) => match i.op {
+            UnaryOp::Inc | UnaryOp::Dec | UnaryOp::Not | UnaryOp::Neg => memory(i.mode, i.w, 2),
+            _ => memory(i.mode, i.w, 1),
+        },
+        Instruction::UnaryRegister(_) | Instruction::AsciiAdjust(_) => 0,
+        Instruction::Shift(i) => memory(i.mode, i.w, 2),
+        Instruction::String(i) => match i.op {
+            StringOp::Movs | StringOp::Cmps => 2 * i.w as u32,
+            StringOp::Stos | StringOp::Lods | StringOp::Scas => i.w as u32,
+        },
+        // FLAGS, CS and IP are pushed and the new CS:IP read from the vector
+        // table.
+        Instruction::Interrupt(_) => 5,
+        Instruction::Simple(op) => match op {
+            SimpleOp::Pushf | SimpleOp::Popf => 1,
+            SimpleOp::Int3 | SimpleOp::Into => 5,
+            SimpleOp::Iret => 3,
+            _ => 0,
+        },
+        Instruction::SegmentOverride(_, i) | Instruction::Repeat(_, i) | Instruction::Lock(i) => {
+            word_transfers(i)
+        }
+    }
+}
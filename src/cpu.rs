@@ -0,0 +1,1294 @@
+use std::{
+    cell::{Cell, RefCell},
+    error::Error,
+    rc::Rc,
+};
+
+use crate::{
+    arithmetic::ArithmeticOp,
+    bus::{Bus, BusAccess, BusStatus},
+    clocks::{Clocks, CpuModel},
+    disk::Disk,
+    dos::Dos,
+    flags::Flags,
+    handlers::Handlers,
+    instruction::Instruction,
+    io::IoBus,
+    jump::{ConditionalOp, Transfer},
+    memory::Memory,
+    mode::Mode,
+    pic::Pic,
+    pit::{Pit, CLOCKS_PER_TICK},
+    register::{Register, SegmentRegister},
+    simple::SimpleOp,
+    stack::StackOp,
+    string::{StringInstruction, StringOp},
+    transfer::LoadOp,
+    unary::UnaryOp,
+    video::Video,
+};
+
+/// Bytes fetched when decoding from memory: the longest 8086 instruction is
+/// six bytes, plus room for prefixes.
+pub(crate) const MAX_INSTRUCTION_LEN: usize = 16;
+
+/// Clocks the 8086 manual gives for taking an external interrupt, an NMI,
+/// a single-step trap and a divide error.
+const INTR_CLOCKS: u32 = 61;
+const NMI_CLOCKS: u32 = 50;
+const TRAP_CLOCKS: u32 = 50;
+const DIVIDE_ERROR_CLOCKS: u32 = 51;
+
+/// Interrupts held off until after the next instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Shadow {
+    None,
+    /// After `sti`: maskable interrupts.
+    Maskable,
+    /// After loading SS: every interrupt and the single-step trap, so that
+    /// SP can be loaded before anything is pushed.
+    All,
+}
+
+/// Order in which registers are reported in traces and register dumps.
+const REGISTER_ORDER: [Register; 8] = [
+    Register::AX,
+    Register::BX,
+    Register::CX,
+    Register::DX,
+    Register::SP,
+    Register::BP,
+    Register::SI,
+    Register::DI,
+];
+
+const SEGMENT_ORDER: [SegmentRegister; 4] = [
+    SegmentRegister::ES,
+    SegmentRegister::CS,
+    SegmentRegister::SS,
+    SegmentRegister::DS,
+];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    /// ax, cx, dx, bx, sp, bp, si, di, in encoding order.
+    pub general: [u16; 8],
+    /// es, cs, ss, ds, in encoding order.
+    pub segments: [u16; 4],
+    pub ip: u16,
+    pub flags: Flags,
+}
+
+impl Registers {
+    pub fn get(&self, reg: Register) -> u16 {
+        let value = self.general[reg.index()];
+
+        match reg {
+            Register::AL | Register::CL | Register::DL | Register::BL => value & 0x00FF,
+            Register::AH | Register::CH | Register::DH | Register::BH => value >> 8,
+            _ => value,
+        }
+    }
+
+    pub fn set(&mut self, reg: Register, value: u16) {
+        let old = &mut self.general[reg.index()];
+
+        *old = match reg {
+            Register::AL | Register::CL | Register::DL | Register::BL => {
+                (*old & 0xFF00) | (value & 0x00FF)
+            }
+            Register::AH | Register::CH | Register::DH | Register::BH => {
+                (*old & 0x00FF) | (value << 8)
+            }
+            _ => value,
+        };
+    }
+
+    pub fn get_segment(&self, sr: SegmentRegister) -> u16 {
+        self.segments[sr as usize]
+    }
+
+    pub fn set_segment(&mut self, sr: SegmentRegister, value: u16) {
+        self.segments[sr as usize] = value;
+    }
+
+    /// Describes every register that differs between `self` and `after`, e.g.
+    /// `cx:0x0->0x2`.
+    pub fn changes(&self, after: &Registers) -> String {
+        let mut res = Vec::new();
+
+        for reg in REGISTER_ORDER {
+            let (old, new) = (self.get(reg), after.get(reg));
+            if old != new {
+                let name = reg.register_mode_to_string();
+                res.push(format!("{name}:{old:#x}->{new:#x}"));
+            }
+        }
+
+        for sr in SEGMENT_ORDER {
+            let (old, new) = (self.get_segment(sr), after.get_segment(sr));
+            if old != new {
+                let name = sr.register_mode_to_string();
+                res.push(format!("{name}:{old:#x}->{new:#x}"));
+            }
+        }
+
+        if self.ip != after.ip {
+            res.push(format!("ip:{:#x}->{:#x}", self.ip, after.ip));
+        }
+
+        if self.flags != after.flags {
+            res.push(format!(
+                "flags:{}->{}",
+                self.flags.to_string(),
+                after.flags.to_string()
+            ));
+        }
+
+        res.join(" ")
+    }
+
+    pub fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        for reg in REGISTER_ORDER {
+            let (name, value) = (reg.register_mode_to_string(), self.get(reg));
+            res.push_str(&format!("      {name}: {value:#06x} ({value})\r\n"));
+        }
+
+        for sr in SEGMENT_ORDER {
+            let (name, value) = (sr.register_mode_to_string(), self.get_segment(sr));
+            res.push_str(&format!("      {name}: {value:#06x} ({value})\r\n"));
+        }
+
+        res.push_str(&format!("      ip: {:#06x} ({})\r\n", self.ip, self.ip));
+        res.push_str(&format!("   flags: {}\r\n", self.flags.to_string()));
+
+        res
+    }
+
+    /// Same as [`Registers::to_string`], but leaving out registers that are
+    /// zero and flags when none are set, the way sim86 prints its final
+    /// registers.
+    pub fn to_sim86_string(&self) -> String {
+        self.to_string()
+            .lines()
+            .filter(|line| !line.ends_with(" (0)") && !line.ends_with("flags: "))
+            .map(|line| format!("{line}\r\n"))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cpu {
+    pub registers: Registers,
+    pub memory: Memory,
+    pub halted: bool,
+    pub model: CpuModel,
+    /// Estimated clocks of every instruction executed so far.
+    pub clocks: u64,
+    /// Estimated clocks of the last instruction executed by [`Cpu::step`].
+    pub instruction_clocks: Clocks,
+    /// DOS services, called when execution reaches their entry stubs. Shared
+    /// between copies of the CPU, since files and console I/O can't be
+    /// copied.
+    pub dos: Option<Rc<RefCell<Dos>>>,
+    /// Disk served through INT 13h, shared the same way.
+    pub disk: Option<Rc<RefCell<Disk>>>,
+    /// Display adapter served through INT 10h. Its state is kept in
+    /// simulated memory, so nothing needs sharing.
+    pub video: Option<Video>,
+    /// Devices `in` and `out` talk to. Copies of the CPU share them.
+    pub io: IoBus,
+    /// Timer and interrupt controller, also on [`Cpu::io`]. The timer counts
+    /// with the clock estimate, and the controller interrupts the program
+    /// between instructions while IF is set.
+    pub pit: Option<Rc<RefCell<Pit>>>,
+    pub pic: Option<Rc<RefCell<Pic>>>,
+    /// Timer ticks delivered to [`Cpu::pit`] so far.
+    pub(crate) pit_ticks: u64,
+    /// Vectors serviced in Rust, see [`crate::handlers::InterruptHandler`].
+    pub handlers: Handlers,
+    /// Calls made to DOS, the disk and host interrupt handlers so far, whose
+    /// effects outside the CPU can't be taken back.
+    pub(crate) host_calls: u64,
+    /// Whether an NMI has been requested and not yet taken.
+    pub(crate) nmi: bool,
+    pub(crate) shadow: Shadow,
+    /// Prefetch queue and bus cycle model. With it, instructions are
+    /// decoded from the queue and [`Cpu::clocks`] counts bus T-states.
+    pub bus: Option<Bus>,
+    /// Transfers of the instruction being executed, for [`Cpu::bus`].
+    accesses: RefCell<Vec<BusAccess>>,
+    /// Segment override prefix of the instruction being executed.
+    segment_override: Option<SegmentRegister>,
+    /// Repeat prefix of the instruction being executed: `Some(true)` for
+    /// `rep`/`repe`, `Some(false)` for `repne`.
+    repeat: Option<bool>,
+    /// Clocks of the instruction being executed that depend on a count only
+    /// known while executing: bits shifted by CL and string repetitions.
+    variable_clocks: u32,
+    /// Transfer penalty clocks accumulated by the instruction being executed.
+    penalty: Cell<u32>,
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cpu {
+    pub fn new() -> Self {
+        Self {
+            registers: Registers::default(),
+            memory: Memory::new(),
+            halted: false,
+            model: CpuModel::default(),
+            clocks: 0,
+            instruction_clocks: Clocks::default(),
+            dos: None,
+            disk: None,
+            video: None,
+            io: IoBus::new(),
+            pit: None,
+            pic: None,
+            pit_ticks: 0,
+            handlers: Handlers::new(),
+            host_calls: 0,
+            nmi: false,
+            shadow: Shadow::None,
+            bus: None,
+            accesses: RefCell::new(Vec::new()),
+            segment_override: None,
+            repeat: None,
+            variable_clocks: 0,
+            penalty: Cell::new(0),
+        }
+    }
+
+    /// Physical address of the next instruction, CS:IP.
+    pub fn code_address(&self) -> usize {
+        let cs = self.registers.get_segment(SegmentRegister::CS);
+
+        Memory::physical_address(cs, self.registers.ip)
+    }
+
+    /// Decodes the instruction at CS:IP without executing it.
+    pub fn fetch(&self) -> Result<Instruction, Box<dyn Error>> {
+        let cs = self.registers.get_segment(SegmentRegister::CS);
+
+        self.decode_at(cs, self.registers.ip)
+    }
+
+    /// Decodes the instruction at `segment:offset`.
+    pub fn decode_at(&self, segment: u16, offset: u16) -> Result<Instruction, Box<dyn Error>> {
+        let bytes: Vec<u8> = (0..MAX_INSTRUCTION_LEN as u16)
+            .map(|i| self.memory.read_byte(segment, offset.wrapping_add(i)))
+            .collect();
+
+        Instruction::decode(&bytes)
+    }
+
+    /// Fetches the instruction at CS:IP, advances IP past it, executes it and
+    /// accounts for its estimated clocks. The entry stub of DOS, the disk or
+    /// the display first performs their call.
+    ///
+    /// A requested NMI, then an interrupt from the PIC, is taken before the
+    /// instruction, and the single-step trap after it if TF was set.
+    pub fn step(&mut self) -> Result<Instruction, Box<dyn Error>> {
+        let shadow = std::mem::replace(&mut self.shadow, Shadow::None);
+        let mut interrupt_clocks = 0;
+
+        if self.nmi && shadow != Shadow::All {
+            self.nmi = false;
+            self.interrupt(2)?;
+            interrupt_clocks += NMI_CLOCKS;
+        }
+        if shadow == Shadow::None {
+            interrupt_clocks += self.accept_irq()?;
+        }
+
+        let trap = self.registers.flags.get(Flags::TF);
+        let (cs, ip) = (
+            self.registers.get_segment(SegmentRegister::CS),
+            self.registers.ip,
+        );
+        let instruction = match &mut self.bus {
+            Some(bus) => {
+                Instruction::decode(&bus.upcoming(&self.memory, cs, ip, MAX_INSTRUCTION_LEN))?
+            }
+            None => self.fetch()?,
+        };
+
+        if let Some(dos) = self.dos.clone() {
+            if dos.borrow().is_entry(self.code_address()) {
+                self.host_calls += 1;
+                dos.borrow_mut().service(self)?;
+            }
+        }
+        if let Some(disk) = self.disk.clone() {
+            if disk.borrow().is_entry(self.code_address()) {
+                self.host_calls += 1;
+                disk.borrow_mut().service(self)?;
+            }
+        }
+        if let Some(video) = self.video {
+            if video.is_entry(self.code_address()) {
+                video.service(self);
+            }
+        }
+
+        let next_ip = self.registers.ip.wrapping_add(instruction.offset() as u16);
+        self.registers.ip = next_ip;
+
+        self.penalty.set(0);
+        self.variable_clocks = 0;
+        self.execute(&instruction)?;
+
+        let mut clocks = Clocks::estimate(&instruction, self.registers.ip != next_ip);
+        if trap && self.shadow != Shadow::All {
+            self.interrupt(1)?;
+            interrupt_clocks += TRAP_CLOCKS;
+        }
+        clocks.base += self.variable_clocks + interrupt_clocks;
+        clocks.penalty = self.penalty.get();
+
+        match &mut self.bus {
+            Some(bus) => {
+                let next = (
+                    self.registers.get_segment(SegmentRegister::CS),
+                    self.registers.ip,
+                );
+                bus.execute(
+                    &self.memory,
+                    instruction.offset(),
+                    clocks.ea,
+                    clocks.total(),
+                    &self.accesses.take(),
+                    next,
+                );
+                self.clocks = bus.cycles();
+            }
+            None => self.clocks += clocks.total() as u64,
+        }
+        self.instruction_clocks = clocks;
+        self.advance_timer();
+
+        Ok(instruction)
+    }
+
+    /// Executes `instruction`, with IP already pointing past it.
+    pub fn execute(&mut self, instruction: &Instruction) -> Result<(), Box<dyn Error>> {
+        match instruction {
+            Instruction::RegisterMemoryToFromRegister(i) => {
+                if i.d {
+                    let value = self.read_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, i.w);
+                    self.registers.set(i.reg, value);
+                } else {
+                    let value = self.registers.get(i.reg);
+                    self.write_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, i.w, value);
+                }
+            }
+            Instruction::ImmediateToRegisterMemory(i) => {
+                self.write_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, i.w, i.data);
+            }
+            Instruction::ImmediateToRegister(i) => {
+                self.registers.set(i.reg, i.data);
+            }
+            Instruction::MemoryToAccumulator(i) => {
+                let addr = u16::from_le_bytes([i.addr_lo, i.addr_hi]);
+                let sr = self.segment_override.unwrap_or(SegmentRegister::DS);
+                let value = self.read_memory(sr, addr, i.w);
+                let dst = if i.w { Register::AX } else { Register::AL };
+                self.registers.set(dst, value);
+            }
+            Instruction::AccumulatorToMemory(i) => {
+                let addr = u16::from_le_bytes([i.addr_lo, i.addr_hi]);
+                let sr = self.segment_override.unwrap_or(SegmentRegister::DS);
+                let src = if i.w { Register::AX } else { Register::AL };
+                self.write_memory(sr, addr, i.w, self.registers.get(src));
+            }
+            Instruction::RegisterMemoryToSegmentRegister(i) => {
+                let value = self.read_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, true);
+                self.load_segment(i.sr, value);
+            }
+            Instruction::SegmentRegisterToRegisterMemory(i) => {
+                let value = self.registers.get_segment(i.sr);
+                self.write_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, true, value);
+            }
+            Instruction::ArithmeticRegisterMemoryWithRegister(i) => {
+                let rm = self.read_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, i.w);
+                let reg = self.registers.get(i.reg);
+
+                if i.d {
+                    let res = self.registers.flags.arithmetic(i.op, reg, rm, i.w);
+                    if !i.op.discards_result() {
+                        self.registers.set(i.reg, res);
+                    }
+                } else {
+                    let res = self.registers.flags.arithmetic(i.op, rm, reg, i.w);
+                    if !i.op.discards_result() {
+                        self.write_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, i.w, res);
+                    }
+                }
+            }
+            Instruction::ArithmeticImmediateToRegisterMemory(i) => {
+                let rm = self.read_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, i.w);
+
+                let res = self.registers.flags.arithmetic(i.op, rm, i.data, i.w);
+                if !i.op.discards_result() {
+                    self.write_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, i.w, res);
+                }
+            }
+            Instruction::ArithmeticImmediateToAccumulator(i) => {
+                let dst = if i.w { Register::AX } else { Register::AL };
+
+                let res =
+                    self.registers
+                        .flags
+                        .arithmetic(i.op, self.registers.get(dst), i.data, i.w);
+                if !i.op.discards_result() {
+                    self.registers.set(dst, res);
+                }
+            }
+            Instruction::ConditionalJump(i) => {
+                if self.condition(i.op) {
+                    self.registers.ip = self.registers.ip.wrapping_add(i.ip_inc8 as u16);
+                }
+            }
+            Instruction::DirectWithinSegment(i) => {
+                if i.transfer == Transfer::Call {
+                    self.push(self.registers.ip);
+                }
+
+                self.registers.ip = self.registers.ip.wrapping_add(i.ip_inc as u16);
+            }
+            Instruction::DirectIntersegment(i) => {
+                if i.transfer == Transfer::Call {
+                    self.push(self.registers.get_segment(SegmentRegister::CS));
+                    self.push(self.registers.ip);
+                }
+
+                self.registers.set_segment(SegmentRegister::CS, i.cs);
+                self.registers.ip = i.ip;
+            }
+            Instruction::Indirect(i) => {
+                let (ip, cs) = if i.far {
+                    let (sr, addr) = self.effective_address(i.mode, i.rm, i.disp_lo, i.disp_hi);
+                    let ip = self.read_memory(sr, addr, true);
+                    let cs = self.read_memory(sr, addr.wrapping_add(2), true);
+
+                    (ip, Some(cs))
+                } else {
+                    (self.read_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, true), None)
+                };
+
+                if i.transfer == Transfer::Call {
+                    if i.far {
+                        self.push(self.registers.get_segment(SegmentRegister::CS));
+                    }
+                    self.push(self.registers.ip);
+                }
+
+                if let Some(cs) = cs {
+                    self.registers.set_segment(SegmentRegister::CS, cs);
+                }
+                self.registers.ip = ip;
+            }
+            Instruction::Return(i) => {
+                self.registers.ip = self.pop();
+
+                if i.far {
+                    let cs = self.pop();
+                    self.registers.set_segment(SegmentRegister::CS, cs);
+                }
+
+                if let Some(data) = i.data {
+                    let sp = self.registers.get(Register::SP);
+                    self.registers.set(Register::SP, sp.wrapping_add(data));
+                }
+            }
+            // Halting with an interrupt to come waits for it instead, so
+            // that idle loops run on.
+            Instruction::Halt => match self.until_irq() {
+                Some(ticks) => self.variable_clocks += (ticks * CLOCKS_PER_TICK) as u32,
+                None => self.halted = true,
+            },
+            Instruction::StackRegisterMemory(i) => match i.op {
+                StackOp::Push => {
+                    let value = self.read_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, true);
+                    self.push(value);
+                }
+                StackOp::Pop => {
+                    let value = self.pop();
+                    self.write_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, true, value);
+                }
+            },
+            Instruction::StackRegister(i) => match i.op {
+                // The 8086 pushes SP as it is after the decrement.
+                StackOp::Push if i.reg == Register::SP => {
+                    let sp = self.registers.get(Register::SP).wrapping_sub(2);
+                    self.push(sp);
+                }
+                StackOp::Push => self.push(self.registers.get(i.reg)),
+                StackOp::Pop => {
+                    let value = self.pop();
+                    self.registers.set(i.reg, value);
+                }
+            },
+            Instruction::StackSegmentRegister(i) => match i.op {
+                StackOp::Push => self.push(self.registers.get_segment(i.sr)),
+                StackOp::Pop => {
+                    let value = self.pop();
+                    self.load_segment(i.sr, value);
+                }
+            },
+            Instruction::Exchange(i) => {
+                let rm = self.read_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, i.w);
+                let reg = self.registers.get(i.reg);
+
+                self.write_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, i.w, reg);
+                self.registers.set(i.reg, rm);
+            }
+            Instruction::ExchangeAccumulator(i) => {
+                let ax = self.registers.get(Register::AX);
+
+                self.registers.set(Register::AX, self.registers.get(i.reg));
+                self.registers.set(i.reg, ax);
+            }
+            Instruction::Port(i) => {
+                let port = i
+                    .port
+                    .map_or(self.registers.get(Register::DX), |port| port as u16);
+                let acc = if i.w { Register::AX } else { Register::AL };
+
+                self.record(
+                    if i.out {
+                        BusStatus::IoWrite
+                    } else {
+                        BusStatus::IoRead
+                    },
+                    0,
+                    port,
+                    i.w,
+                );
+                if i.out {
+                    self.io.write(port, i.w, self.registers.get(acc));
+                } else {
+                    let value = self.io.read(port, i.w);
+                    self.registers.set(acc, value);
+                }
+            }
+            Instruction::LoadAddress(i) => {
+                let (sr, addr) = self.effective_address(i.mode, i.rm, i.disp_lo, i.disp_hi);
+
+                match i.op {
+                    LoadOp::Lea => self.registers.set(i.reg, addr),
+                    LoadOp::Lds | LoadOp::Les => {
+                        let offset = self.read_memory(sr, addr, true);
+                        let segment = self.read_memory(sr, addr.wrapping_add(2), true);
+
+                        self.registers.set(i.reg, offset);
+                        let dst = if i.op == LoadOp::Lds {
+                            SegmentRegister::DS
+                        } else {
+                            SegmentRegister::ES
+                        };
+                        self.registers.set_segment(dst, segment);
+                    }
+                }
+            }
+            Instruction::UnaryRegisterMemory(i) => {
+                let value = self.read_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, i.w);
+
+                match i.op {
+                    UnaryOp::Inc | UnaryOp::Dec => {
+                        let res = self
+                            .registers
+                            .flags
+                            .inc_dec(i.op == UnaryOp::Dec, value, i.w);
+                        self.write_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, i.w, res);
+                    }
+                    UnaryOp::Not => {
+                        self.write_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, i.w, !value);
+                    }
+                    UnaryOp::Neg => {
+                        let res = self
+                            .registers
+                            .flags
+                            .arithmetic(ArithmeticOp::Sub, 0, value, i.w);
+                        self.write_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, i.w, res);
+                    }
+                    UnaryOp::Mul | UnaryOp::Imul => {
+                        self.multiply(i.op == UnaryOp::Imul, value, i.w)
+                    }
+                    UnaryOp::Div | UnaryOp::Idiv => {
+                        self.divide(i.op == UnaryOp::Idiv, value, i.w)?
+                    }
+                }
+            }
+            Instruction::UnaryRegister(i) => {
+                let res = self.registers.flags.inc_dec(
+                    i.op == UnaryOp::Dec,
+                    self.registers.get(i.reg),
+                    true,
+                );
+                self.registers.set(i.reg, res);
+            }
+            Instruction::AsciiAdjust(i) => {
+                let (al, ah) = (
+                    self.registers.get(Register::AL),
+                    self.registers.get(Register::AH),
+                );
+                let base = i.base as u16;
+
+                let al = if i.multiply {
+                    if base == 0 {
+                        return self.divide_error();
+                    }
+
+                    self.registers.set(Register::AH, al / base);
+                    al % base
+                } else {
+                    self.registers.set(Register::AH, 0);
+                    al.wrapping_add(ah.wrapping_mul(base)) & 0x00FF
+                };
+
+                self.registers.set(Register::AL, al);
+                self.registers.flags.set_result(al, false);
+            }
+            Instruction::Shift(i) => {
+                let count = if i.v {
+                    self.registers.get(Register::CL) as u8
+                } else {
+                    1
+                };
+                if i.v {
+                    self.variable_clocks += 4 * count as u32;
+                }
+
+                let value = self.read_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, i.w);
+                let res = self.registers.flags.shift(i.op, value, count, i.w);
+                self.write_rm(i.mode, i.rm, i.disp_lo, i.disp_hi, i.w, res);
+            }
+            Instruction::String(i) => self.string(i),
+            Instruction::Interrupt(i) => self.interrupt(i.vector)?,
+            Instruction::Simple(op) => self.simple(*op)?,
+            Instruction::SegmentOverride(sr, instruction) => {
+                self.segment_override = Some(*sr);
+                let res = self.execute(instruction);
+                self.segment_override = None;
+
+                res?;
+            }
+            Instruction::Repeat(z, instruction) => {
+                self.repeat = Some(*z);
+                let res = self.execute(instruction);
+                self.repeat = None;
+
+                res?;
+            }
+            Instruction::Lock(instruction) => self.execute(instruction)?,
+        }
+
+        Ok(())
+    }
+
+    /// Runs the interrupt sequence for `vector`: pushes FLAGS, CS and IP,
+    /// clears IF and TF, and continues at the address in the vector table.
+    /// A host handler for the vector services it instead.
+    pub fn interrupt(&mut self, vector: u8) -> Result<(), Box<dyn Error>> {
+        if let Some(handler) = self.handlers.get(vector) {
+            self.host_calls += 1;
+            if handler.borrow_mut().interrupt(self, vector)? {
+                return Ok(());
+            }
+        }
+
+        self.push(self.registers.flags.0 | Flags::RESERVED);
+        self.push(self.registers.get_segment(SegmentRegister::CS));
+        self.push(self.registers.ip);
+
+        self.registers.flags.set(Flags::IF, false);
+        self.registers.flags.set(Flags::TF, false);
+
+        let addr = 4 * vector as u16;
+        let ip = self.memory.read_word(0, addr);
+        let cs = self.memory.read_word(0, addr.wrapping_add(2));
+
+        self.registers.set_segment(SegmentRegister::CS, cs);
+        self.registers.ip = ip;
+
+        Ok(())
+    }
+
+    /// Requests a non-maskable interrupt, taken before the next instruction
+    /// regardless of IF.
+    pub fn request_nmi(&mut self) {
+        self.nmi = true;
+    }
+
+    /// Takes the interrupt the PIC requests, if IF is set, as the processor
+    /// does between instructions. Returns the clocks the acknowledge took.
+    fn accept_irq(&mut self) -> Result<u32, Box<dyn Error>> {
+        let Some(pic) = self.pic.clone() else {
+            return Ok(0);
+        };
+        if !self.registers.flags.get(Flags::IF) {
+            return Ok(0);
+        }
+
+        let vector = pic.borrow_mut().acknowledge();
+        match vector {
+            Some(vector) => {
+                self.interrupt(vector)?;
+                Ok(INTR_CLOCKS)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// INT 0, which the 8086 raises with IP past the faulting instruction.
+    fn divide_error(&mut self) -> Result<(), Box<dyn Error>> {
+        self.variable_clocks += DIVIDE_ERROR_CLOCKS;
+        self.interrupt(0)
+    }
+
+    fn load_segment(&mut self, sr: SegmentRegister, value: u16) {
+        self.registers.set_segment(sr, value);
+        if sr == SegmentRegister::SS {
+            self.shadow = Shadow::All;
+        }
+    }
+
+    /// Counts the timer up to the clock estimate, and requests IRQ0 if its
+    /// output rose.
+    fn advance_timer(&mut self) {
+        let Some(pit) = self.pit.clone() else {
+            return;
+        };
+
+        let ticks = self.clocks / CLOCKS_PER_TICK - self.pit_ticks;
+        self.pit_ticks += ticks;
+
+        if pit.borrow_mut().advance(ticks) {
+            if let Some(pic) = &self.pic {
+                pic.borrow_mut().request(0);
+            }
+        }
+    }
+
+    /// Timer ticks until an interrupt will be taken, if one will.
+    fn until_irq(&self) -> Option<u64> {
+        if self.nmi {
+            return Some(0);
+        }
+
+        let pic = self.pic.as_ref()?.borrow();
+        if !self.registers.flags.get(Flags::IF) {
+            return None;
+        }
+        if pic.pending().is_some() {
+            return Some(0);
+        }
+        if pic.masked(0) {
+            return None;
+        }
+
+        self.pit.as_ref()?.borrow().until_irq()
+    }
+
+    fn simple(&mut self, op: SimpleOp) -> Result<(), Box<dyn Error>> {
+        match op {
+            SimpleOp::Nop | SimpleOp::Wait => {}
+            SimpleOp::Xlat => {
+                let sr = self.segment_override.unwrap_or(SegmentRegister::DS);
+                let addr = self
+                    .registers
+                    .get(Register::BX)
+                    .wrapping_add(self.registers.get(Register::AL));
+
+                let value = self.read_memory(sr, addr, false);
+                self.registers.set(Register::AL, value);
+            }
+            SimpleOp::Lahf => {
+                let value = (self.registers.flags.0 | Flags::RESERVED) & 0x00FF;
+                self.registers.set(Register::AH, value);
+            }
+            SimpleOp::Sahf => {
+                let ah = self.registers.get(Register::AH) & Flags::DEFINED & 0x00FF;
+                self.registers.flags.0 = (self.registers.flags.0 & 0xFF00) | ah;
+            }
+            SimpleOp::Pushf => self.push(self.registers.flags.0 | Flags::RESERVED),
+            SimpleOp::Popf => {
+                let value = self.pop();
+                self.registers.flags.0 = value & Flags::DEFINED;
+            }
+            SimpleOp::Cbw => {
+                let al = self.registers.get(Register::AL) as u8 as i8;
+                self.registers.set(Register::AX, al as i16 as u16);
+            }
+            SimpleOp::Cwd => {
+                let sign = self.registers.get(Register::AX) & 0x8000 != 0;
+                self.registers
+                    .set(Register::DX, if sign { 0xFFFF } else { 0 });
+            }
+            SimpleOp::Aaa | SimpleOp::Aas => {
+                let al = self.registers.get(Register::AL);
+                let ah = self.registers.get(Register::AH);
+                let adjust = al & 0x0F > 9 || self.registers.flags.get(Flags::AF);
+
+                if adjust {
+                    let (al, ah) = if op == SimpleOp::Aaa {
+                        (al.wrapping_add(6), ah.wrapping_add(1))
+                    } else {
+                        (al.wrapping_sub(6), ah.wrapping_sub(1))
+                    };
+                    self.registers.set(Register::AL, al);
+                    self.registers.set(Register::AH, ah);
+                }
+
+                let al = self.registers.get(Register::AL) & 0x0F;
+                self.registers.set(Register::AL, al);
+                self.registers.flags.set(Flags::AF, adjust);
+                self.registers.flags.set(Flags::CF, adjust);
+            }
+            SimpleOp::Daa | SimpleOp::Das => {
+                let old = self.registers.get(Register::AL);
+                let (cf, af) = (
+                    self.registers.flags.get(Flags::CF),
+                    self.registers.flags.get(Flags::AF),
+                );
+                let mut al = old;
+
+                let adjust = |al: u16, by: u16| {
+                    if op == SimpleOp::Daa {
+                        al.wrapping_add(by)
+                    } else {
+                        al.wrapping_sub(by)
+                    }
+                };
+
+                if old & 0x0F > 9 || af {
+                    al = adjust(al, 0x06);
+                    self.registers.flags.set(Flags::AF, true);
+                } else {
+                    self.registers.flags.set(Flags::AF, false);
+                }
+                if old > 0x99 || cf {
+                    al = adjust(al, 0x60);
+                    self.registers.flags.set(Flags::CF, true);
+                } else {
+                    self.registers.flags.set(Flags::CF, false);
+                }
+
+                let al = al & 0x00FF;
+                self.registers.flags.set_result(al, false);
+                self.registers.set(Register::AL, al);
+            }
+            SimpleOp::Int3 => self.interrupt(3)?,
+            SimpleOp::Into => {
+                if self.registers.flags.get(Flags::OF) {
+                    self.interrupt(4)?;
+                }
+            }
+            SimpleOp::Iret => {
+                self.registers.ip = self.pop();
+                let cs = self.pop();
+                self.registers.set_segment(SegmentRegister::CS, cs);
+                self.registers.flags.0 = self.pop() & Flags::DEFINED;
+            }
+            SimpleOp::Clc => self.registers.flags.set(Flags::CF, false),
+            SimpleOp::Cmc => self
+                .registers
+                .flags
+                .set(Flags::CF, !self.registers.flags.get(Flags::CF)),
+            SimpleOp::Stc => self.registers.flags.set(Flags::CF, true),
+            SimpleOp::Cld => self.registers.flags.set(Flags::DF, false),
+            SimpleOp::Std => self.registers.flags.set(Flags::DF, true),
+            SimpleOp::Cli => self.registers.flags.set(Flags::IF, false),
+            SimpleOp::Sti => {
+                self.registers.flags.set(Flags::IF, true);
+                self.shadow = Shadow::Maskable;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `mul`/`imul` of AL or AX by `value`, into AX or DX:AX. CF and OF are
+    /// set when the upper half of the result is significant.
+    fn multiply(&mut self, signed: bool, value: u16, w: bool) {
+        let upper_significant = if w {
+            let ax = self.registers.get(Register::AX);
+            let res = if signed {
+                (ax as i16 as i32 * value as i16 as i32) as u32
+            } else {
+                ax as u32 * value as u32
+            };
+            let (lo, hi) = (res as u16, (res >> 16) as u16);
+
+            self.registers.set(Register::AX, lo);
+            self.registers.set(Register::DX, hi);
+
+            if signed {
+                hi != if lo & 0x8000 != 0 { 0xFFFF } else { 0 }
+            } else {
+                hi != 0
+            }
+        } else {
+            let al = self.registers.get(Register::AL);
+            let res = if signed {
+                (al as u8 as i8 as i16 * value as u8 as i8 as i16) as u16
+            } else {
+                al * (value & 0x00FF)
+            };
+
+            self.registers.set(Register::AX, res);
+
+            if signed {
+                res != res as u8 as i8 as i16 as u16
+            } else {
+                res > 0x00FF
+            }
+        };
+
+        self.registers.flags.set(Flags::CF, upper_significant);
+        self.registers.flags.set(Flags::OF, upper_significant);
+    }
+
+    /// `div`/`idiv` of AX or DX:AX by `value`, leaving the quotient in AL or
+    /// AX and the remainder in AH or DX.
+    fn divide(&mut self, signed: bool, value: u16, w: bool) -> Result<(), Box<dyn Error>> {
+        let (dividend, divisor, bits) = if w {
+            let dividend = ((self.registers.get(Register::DX) as u32) << 16)
+                | self.registers.get(Register::AX) as u32;
+            let dividend = if signed {
+                dividend as i32 as i64
+            } else {
+                dividend as i64
+            };
+            let divisor = if signed {
+                value as i16 as i64
+            } else {
+                value as i64
+            };
+
+            (dividend, divisor, 16)
+        } else {
+            let dividend = self.registers.get(Register::AX);
+            let dividend = if signed {
+                dividend as i16 as i64
+            } else {
+                dividend as i64
+            };
+            let divisor = if signed {
+                value as u8 as i8 as i64
+            } else {
+                (value & 0x00FF) as i64
+            };
+
+            (dividend, divisor, 8)
+        };
+
+        if divisor == 0 {
+            return self.divide_error();
+        }
+
+        let (quotient, remainder) = (dividend / divisor, dividend % divisor);
+
+        // The 8086 raises the error for the most negative quotient too.
+        let limit = 1i64 << (bits - 1);
+        let fits = if signed {
+            -limit < quotient && quotient < limit
+        } else {
+            quotient < 2 * limit
+        };
+        if !fits {
+            return self.divide_error();
+        }
+
+        if w {
+            self.registers.set(Register::AX, quotient as u16);
+            self.registers.set(Register::DX, remainder as u16);
+        } else {
+            self.registers.set(Register::AL, quotient as u16 & 0x00FF);
+            self.registers.set(Register::AH, remainder as u16 & 0x00FF);
+        }
+
+        Ok(())
+    }
+
+    /// Executes a string instruction once, or with a repeat prefix as long
+    /// as CX is not zero and, for comparisons, ZF matches the prefix.
+    fn string(&mut self, instruction: &StringInstruction) {
+        let Some(z) = self.repeat else {
+            self.string_element(instruction);
+            return;
+        };
+
+        let per_repetition = match instruction.op {
+            StringOp::Movs => 17,
+            StringOp::Cmps => 22,
+            StringOp::Stos => 10,
+            StringOp::Lods => 13,
+            StringOp::Scas => 15,
+        };
+
+        while self.registers.get(Register::CX) != 0 {
+            self.string_element(instruction);
+            self.variable_clocks += per_repetition;
+
+            let cx = self.registers.get(Register::CX).wrapping_sub(1);
+            self.registers.set(Register::CX, cx);
+
+            if instruction.op.compares() && self.registers.flags.get(Flags::ZF) != z {
+                break;
+            }
+        }
+    }
+
+    fn string_element(&mut self, instruction: &StringInstruction) {
+        let w = instruction.w;
+        let source = self.segment_override.unwrap_or(SegmentRegister::DS);
+        let (si, di) = (
+            self.registers.get(Register::SI),
+            self.registers.get(Register::DI),
+        );
+
+        match instruction.op {
+            StringOp::Movs => {
+                let value = self.read_memory(source, si, w);
+                self.write_memory(SegmentRegister::ES, di, w, value);
+            }
+            StringOp::Cmps => {
+                let (a, b) = (
+                    self.read_memory(source, si, w),
+                    self.read_memory(SegmentRegister::ES, di, w),
+                );
+                self.registers.flags.arithmetic(ArithmeticOp::Cmp, a, b, w);
+            }
+            StringOp::Stos => {
+                let acc = if w { Register::AX } else { Register::AL };
+                self.write_memory(SegmentRegister::ES, di, w, self.registers.get(acc));
+            }
+            StringOp::Lods => {
+                let value = self.read_memory(source, si, w);
+                let acc = if w { Register::AX } else { Register::AL };
+                self.registers.set(acc, value);
+            }
+            StringOp::Scas => {
+                let acc = if w { Register::AX } else { Register::AL };
+                let value = self.read_memory(SegmentRegister::ES, di, w);
+                self.registers.flags.arithmetic(
+                    ArithmeticOp::Cmp,
+                    self.registers.get(acc),
+                    value,
+                    w,
+                );
+            }
+        }
+
+        let size = if w { 2 } else { 1 };
+        let step = |value: u16, flags: Flags| {
+            if flags.get(Flags::DF) {
+                value.wrapping_sub(size)
+            } else {
+                value.wrapping_add(size)
+            }
+        };
+
+        let flags = self.registers.flags;
+        if instruction.op.uses_source() {
+            self.registers.set(Register::SI, step(si, flags));
+        }
+        if instruction.op.uses_destination() {
+            self.registers.set(Register::DI, step(di, flags));
+        }
+    }
+
+    /// Evaluates a conditional jump or loop, decrementing CX for the loops.
+    fn condition(&mut self, op: ConditionalOp) -> bool {
+        let flags = self.registers.flags;
+        let (cf, pf, zf) = (
+            flags.get(Flags::CF),
+            flags.get(Flags::PF),
+            flags.get(Flags::ZF),
+        );
+        let (sf, of) = (flags.get(Flags::SF), flags.get(Flags::OF));
+
+        match op {
+            ConditionalOp::Jo => of,
+            ConditionalOp::Jno => !of,
+            ConditionalOp::Jb => cf,
+            ConditionalOp::Jnb => !cf,
+            ConditionalOp::Je => zf,
+            ConditionalOp::Jne => !zf,
+            ConditionalOp::Jbe => cf || zf,
+            ConditionalOp::Ja => !(cf || zf),
+            ConditionalOp::Js => sf,
+            ConditionalOp::Jns => !sf,
+            ConditionalOp::Jp => pf,
+            ConditionalOp::Jnp => !pf,
+            ConditionalOp::Jl => sf != of,
+            ConditionalOp::Jnl => sf == of,
+            ConditionalOp::Jle => zf || sf != of,
+            ConditionalOp::Jg => !zf && sf == of,
+            ConditionalOp::Jcxz => self.registers.get(Register::CX) == 0,
+            ConditionalOp::Loop | ConditionalOp::Loopz | ConditionalOp::Loopnz => {
+                let cx = self.registers.get(Register::CX).wrapping_sub(1);
+                self.registers.set(Register::CX, cx);
+
+                match op {
+                    ConditionalOp::Loopz => cx != 0 && zf,
+                    ConditionalOp::Loopnz => cx != 0 && !zf,
+                    _ => cx != 0,
+                }
+            }
+        }
+    }
+
+    pub fn push(&mut self, value: u16) {
+        let sp = self.registers.get(Register::SP).wrapping_sub(2);
+        self.registers.set(Register::SP, sp);
+        self.write_memory(SegmentRegister::SS, sp, true, value);
+    }
+
+    pub fn pop(&mut self) -> u16 {
+        let sp = self.registers.get(Register::SP);
+        self.registers.set(Register::SP, sp.wrapping_add(2));
+        self.read_memory(SegmentRegister::SS, sp, true)
+    }
+
+    /// Computes the `segment:offset` a memory operand refers to. BP-based
+    /// addresses default to SS and everything else to DS, unless the
+    /// instruction carries a segment override prefix.
+    pub(crate) fn effective_address(
+        &self,
+        mode: Mode,
+        rm: Register,
+        disp_lo: Option<u8>,
+        disp_hi: Option<u8>,
+    ) -> (SegmentRegister, u16) {
+        let disp = match mode {
+            Mode::Mem | Mode::Reg => 0,
+            Mode::Mem8 => disp_lo.unwrap() as i8 as u16,
+            Mode::Mem16 | Mode::DirectAddress => {
+                u16::from_le_bytes([disp_lo.unwrap(), disp_hi.unwrap()])
+            }
+        };
+
+        if mode == Mode::DirectAddress {
+            return (self.segment_override.unwrap_or(SegmentRegister::DS), disp);
+        }
+
+        let r = |reg| self.registers.get(reg);
+        let (base, default) = match rm {
+            Register::AL | Register::AX => (
+                r(Register::BX).wrapping_add(r(Register::SI)),
+                SegmentRegister::DS,
+            ),
+            Register::CL | Register::CX => (
+                r(Register::BX).wrapping_add(r(Register::DI)),
+                SegmentRegister::DS,
+            ),
+            Register::DL | Register::DX => (
+                r(Register::BP).wrapping_add(r(Register::SI)),
+                SegmentRegister::SS,
+            ),
+            Register::BL | Register::BX => (
+                r(Register::BP).wrapping_add(r(Register::DI)),
+                SegmentRegister::SS,
+            ),
+            Register::AH | Register::SP => (r(Register::SI), SegmentRegister::DS),
+            Register::CH | Register::BP => (r(Register::DI), SegmentRegister::DS),
+            Register::DH | Register::SI => (r(Register::BP), SegmentRegister::SS),
+            Register::BH | Register::DI => (r(Register::BX), SegmentRegister::DS),
+        };
+
+        (
+            self.segment_override.unwrap_or(default),
+            base.wrapping_add(disp),
+        )
+    }
+
+    pub(crate) fn read_rm(
+        &self,
+        mode: Mode,
+        rm: Register,
+        disp_lo: Option<u8>,
+        disp_hi: Option<u8>,
+        w: bool,
+    ) -> u16 {
+        match mode {
+            Mode::Reg => self.registers.get(rm),
+            _ => {
+                let (sr, addr) = self.effective_address(mode, rm, disp_lo, disp_hi);
+                self.read_memory(sr, addr, w)
+            }
+        }
+    }
+
+    pub(crate) fn write_rm(
+        &mut self,
+        mode: Mode,
+        rm: Register,
+        disp_lo: Option<u8>,
+        disp_hi: Option<u8>,
+        w: bool,
+        value: u16,
+    ) {
+        match mode {
+            Mode::Reg => self.registers.set(rm, value),
+            _ => {
+                let (sr, addr) = self.effective_address(mode, rm, disp_lo, disp_hi);
+                self.write_memory(sr, addr, w, value);
+            }
+        }
+    }
+
+    /// Reads `sr:offset` using the current value of the segment register.
+    pub fn read_memory(&self, sr: SegmentRegister, offset: u16, w: bool) -> u16 {
+        if w {
+            self.penalty
+                .set(self.penalty.get() + self.model.transfer_penalty(offset));
+        }
+
+        let segment = self.registers.get_segment(sr);
+        self.record(BusStatus::MemoryRead, segment, offset, w);
+
+        self.memory.read(segment, offset, w)
+    }
+
+    pub fn write_memory(&mut self, sr: SegmentRegister, offset: u16, w: bool, value: u16) {
+        if w {
+            self.penalty
+                .set(self.penalty.get() + self.model.transfer_penalty(offset));
+        }
+
+        let segment = self.registers.get_segment(sr);
+        self.record(BusStatus::MemoryWrite, segment, offset, w);
+
+        self.memory.write(segment, offset, w, value);
+    }
+
+    /// Notes a transfer for the bus model, if there is one.
+    fn record(&self, status: BusStatus, segment: u16, offset: u16, w: bool) {
+        if self.bus.is_none() {
+            return;
+        }
+
+        let old = [
+            self.memory.read_byte(segment, offset),
+            self.memory.read_byte(segment, offset.wrapping_add(1)),
+        ];
+        self.accesses.borrow_mut().push(BusAccess {
+            status,
+            segment,
+            offset,
+            w,
+            old,
+        });
+    }
+}
@@ -0,0 +1,456 @@
+use std::{collections::BTreeSet, error::Error};
+
+use crate::{
+    bus::Bus,
+    cpu::Cpu,
+    flags::Flags,
+    history::History,
+    loader,
+    memory::Memory,
+    parse_number,
+    register::{Register, SegmentRegister},
+    video, SimulateOptions,
+};
+
+/// Instructions shown before IP by `disasm`.
+const DISASM_CONTEXT: usize = 3;
+
+/// Instructions kept in the undo log, and how often, and how many times, the
+/// whole CPU is copied to step back further than that.
+const HISTORY_CAPACITY: usize = 100_000;
+const SNAPSHOT_INTERVAL: u64 = 10_000;
+const MAX_SNAPSHOTS: usize = 16;
+
+pub const HELP: &str = "commands:
+    s, step [count]           execute one or more instructions
+    c, continue               run until a breakpoint, hlt or the end of the program
+    finish                    run until the current procedure returns
+    sb, step-back [count]     take back one or more instructions
+    rc, reverse-continue      step back to the previous breakpoint or watchpoint hit
+    b, break [address]        set a breakpoint, or list breakpoints
+    d, delete <address>       remove a breakpoint
+    w, watch [address]        stop when a byte changes, or list watchpoints
+    unwatch <address>         remove a watchpoint
+    r, regs                   print registers and flags
+    set <register> <value>    modify a register, e.g. `set ax 0x10` or `set ip 6`
+    set flags <letters>       replace all flags, e.g. `set flags CZ` or `set flags -`
+    set <flag>f <0|1>         modify one flag, e.g. `set zf 1`
+    x <address> [length]      hexdump memory (default segment ds)
+    u, disasm [count]         disassemble around IP
+    screen                    print the text mode screen
+    q, quit                   exit the debugger
+
+addresses are `segment:offset` or an offset, in decimal or 0x-prefixed hex";
+
+/// Why execution handed control back to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stop {
+    Breakpoint,
+    Watchpoint(usize),
+    StartOfHistory,
+    Returned,
+    Halted,
+    EndOfProgram,
+    InstructionLimit,
+}
+
+/// Drives a [`Cpu`] one command line at a time, e.g. `step 3` or
+/// `break 0:6`, returning what should be shown to the user.
+pub struct Debugger {
+    pub cpu: Cpu,
+    history: History,
+    /// Physical addresses execution stops at before executing.
+    breakpoints: BTreeSet<usize>,
+    /// Physical addresses of bytes execution stops at when they change.
+    watchpoints: BTreeSet<usize>,
+    /// Physical address just past a raw program.
+    end: usize,
+    instruction_limit: usize,
+}
+
+impl Debugger {
+    /// Loads `bytes` the same way [`crate::run`] does.
+    pub fn new(bytes: &[u8], options: &SimulateOptions) -> Result<Self, Box<dyn Error>> {
+        let mut cpu = Cpu::new();
+        cpu.model = options.model;
+        cpu.dos = options.dos.clone();
+        cpu.disk = options.disk.clone();
+        cpu.video = options.video;
+        cpu.io = options.io.clone();
+        cpu.pit = options.pit.clone();
+        cpu.pic = options.pic.clone();
+        cpu.handlers = options.handlers.clone();
+        cpu.bus = options.bus.map(|config| Bus::new(config, options.model));
+        let end = loader::load(&mut cpu, bytes, &options.load)?;
+
+        Ok(Self {
+            history: History::new(&cpu, HISTORY_CAPACITY, SNAPSHOT_INTERVAL, MAX_SNAPSHOTS),
+            cpu,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            end,
+            instruction_limit: options.instruction_limit,
+        })
+    }
+
+    /// Executes one command line and returns its output.
+    pub fn command(&mut self, line: &str) -> Result<String, Box<dyn Error>> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+
+        match args.as_slice() {
+            [] => Ok(String::new()),
+            ["help" | "h" | "?"] => Ok(format!("{HELP}\r\n")),
+            ["s" | "step"] => Ok(self.step(1)),
+            ["s" | "step", count] => Ok(self.step(parse(count)?)),
+            ["c" | "continue"] => Ok(self.resume(false)),
+            ["finish"] => Ok(self.resume(true)),
+            ["sb" | "step-back"] => self.step_back(1),
+            ["sb" | "step-back", count] => self.step_back(parse(count)? as u64),
+            ["rc" | "reverse-continue"] => self.reverse_continue(),
+            ["b" | "break"] => Ok(self
+                .breakpoints
+                .iter()
+                .map(|addr| format!("{addr:#07x}\r\n"))
+                .collect()),
+            ["b" | "break", address] => {
+                let addr = self.parse_address(address, SegmentRegister::CS)?;
+                self.breakpoints.insert(addr);
+
+                Ok(format!("breakpoint at {addr:#07x}\r\n"))
+            }
+            ["d" | "delete", address] => {
+                let addr = self.parse_address(address, SegmentRegister::CS)?;
+                if !self.breakpoints.remove(&addr) {
+                    return Err(format!("no breakpoint at {addr:#07x}").into());
+                }
+
+                Ok(String::new())
+            }
+            ["w" | "watch"] => Ok(self
+                .watchpoints
+                .iter()
+                .map(|addr| format!("{addr:#07x}\r\n"))
+                .collect()),
+            ["w" | "watch", address] => {
+                let addr = self.parse_address(address, SegmentRegister::DS)?;
+                self.watchpoints.insert(addr);
+
+                Ok(format!("watchpoint at {addr:#07x}\r\n"))
+            }
+            ["unwatch", address] => {
+                let addr = self.parse_address(address, SegmentRegister::DS)?;
+                if !self.watchpoints.remove(&addr) {
+                    return Err(format!("no watchpoint at {addr:#07x}").into());
+                }
+
+                Ok(String::new())
+            }
+            ["r" | "regs"] => Ok(self.cpu.registers.to_string()),
+            ["set", "flags", letters] => {
+                let mut flags = Flags::default();
+                for letter in letters.chars().filter(|letter| *letter != '-') {
+                    let flag =
+                        Flags::from_letter(letter).ok_or(format!("unknown flag `{letter}`"))?;
+                    flags.set(flag, true);
+                }
+                self.cpu.registers.flags = flags;
+
+                Ok(String::new())
+            }
+            ["set", name, value] => {
+                self.set(name, parse(value)?)?;
+
+                Ok(String::new())
+            }
+            ["screen"] => Ok(video::screen_text(&self.cpu.memory)),
+            ["x", address] => self.hexdump(address, 64),
+            ["x", address, len] => self.hexdump(address, parse(len)?),
+            ["u" | "disasm"] => Ok(self.disassemble(8)),
+            ["u" | "disasm", count] => Ok(self.disassemble(parse(count)?)),
+            _ => Err(format!("unknown command `{line}`, try `help`").into()),
+        }
+    }
+
+    /// `cs:ip` and the instruction about to be executed.
+    pub fn location(&self) -> String {
+        let instruction = match self.cpu.fetch() {
+            Ok(instruction) => instruction.to_string().trim_end().to_string(),
+            Err(e) => e.to_string(),
+        };
+
+        format!("{}  {instruction}\r\n", self.cs_ip())
+    }
+
+    fn cs_ip(&self) -> String {
+        let cs = self.cpu.registers.get_segment(SegmentRegister::CS);
+
+        format!("{cs:04x}:{:04x}", self.cpu.registers.ip)
+    }
+
+    /// Reason execution cannot continue, if any.
+    fn stopped(&self) -> Option<Stop> {
+        if self.cpu.halted {
+            Some(Stop::Halted)
+        } else if self.cpu.code_address() >= self.end {
+            Some(Stop::EndOfProgram)
+        } else {
+            None
+        }
+    }
+
+    /// Executes `count` instructions, printing each with its changes the way
+    /// traces do.
+    fn step(&mut self, count: usize) -> String {
+        let mut res = String::new();
+
+        for _ in 0..count {
+            if let Some(stop) = self.stopped() {
+                res.push_str(&self.describe(stop));
+                return res;
+            }
+
+            let (location, before) = (self.cs_ip(), self.cpu.registers);
+            match self.history.step(&mut self.cpu) {
+                Ok(instruction) => res.push_str(&format!(
+                    "{location}  {} ; {}\r\n",
+                    instruction.to_string().trim_end(),
+                    before.changes(&self.cpu.registers)
+                )),
+                Err(e) => {
+                    res.push_str(&format!("{location}  {e}\r\n"));
+                    return res;
+                }
+            }
+
+            for access in self.cpu.io.take_unclaimed() {
+                res.push_str(&format!("; {}\r\n", access.to_string()));
+            }
+        }
+
+        res
+    }
+
+    /// Runs until a breakpoint, the end of the program, or, with
+    /// `until_return`, until the procedure being executed returns.
+    fn resume(&mut self, until_return: bool) -> String {
+        let mut depth = 0usize;
+        let mut count = 0;
+
+        loop {
+            if let Some(stop) = self.stopped() {
+                return self.describe(stop);
+            }
+            if count > 0 && self.breakpoints.contains(&self.cpu.code_address()) {
+                return self.describe(Stop::Breakpoint);
+            }
+            if count == self.instruction_limit {
+                return self.describe(Stop::InstructionLimit);
+            }
+
+            let watched = self.watched();
+            let instruction = match self.history.step(&mut self.cpu) {
+                Ok(instruction) => instruction,
+                Err(e) => return format!("{e}\r\n{}", self.location()),
+            };
+
+            if let Some(addr) = self.changed(&watched) {
+                return self.describe(Stop::Watchpoint(addr));
+            }
+
+            if until_return && instruction.is_call() {
+                depth += 1;
+            } else if until_return && instruction.is_return() {
+                if depth == 0 {
+                    return self.describe(Stop::Returned);
+                }
+                depth -= 1;
+            }
+
+            count += 1;
+        }
+    }
+
+    /// Takes back `count` instructions, as far as the history reaches.
+    fn step_back(&mut self, count: u64) -> Result<String, Box<dyn Error>> {
+        let res = self.history.step_back(&mut self.cpu, count)?;
+        if res < count {
+            return Ok(self.describe(Stop::StartOfHistory));
+        }
+
+        Ok(self.location())
+    }
+
+    /// Steps back until the instruction about to be executed has a
+    /// breakpoint, or the instruction just taken back changed a watched byte.
+    fn reverse_continue(&mut self) -> Result<String, Box<dyn Error>> {
+        loop {
+            let watched = self.watched();
+            if self.history.step_back(&mut self.cpu, 1)? == 0 {
+                return Ok(self.describe(Stop::StartOfHistory));
+            }
+
+            if let Some(addr) = self.changed(&watched) {
+                return Ok(self.describe(Stop::Watchpoint(addr)));
+            }
+            if self.breakpoints.contains(&self.cpu.code_address()) {
+                return Ok(self.describe(Stop::Breakpoint));
+            }
+        }
+    }
+
+    /// Current value of every watched byte.
+    fn watched(&self) -> Vec<(usize, u8)> {
+        self.watchpoints
+            .iter()
+            .map(|addr| (*addr, self.cpu.memory.read_physical(*addr)))
+            .collect()
+    }
+
+    /// First watched byte whose value differs from `watched`.
+    fn changed(&self, watched: &[(usize, u8)]) -> Option<usize> {
+        watched
+            .iter()
+            .find(|(addr, value)| self.cpu.memory.read_physical(*addr) != *value)
+            .map(|(addr, _)| *addr)
+    }
+
+    fn describe(&self, stop: Stop) -> String {
+        let reason = match stop {
+            Stop::Breakpoint => "breakpoint\r\n",
+            Stop::Watchpoint(addr) => &format!("watchpoint {addr:#07x} changed\r\n"),
+            Stop::StartOfHistory => "start of history\r\n",
+            Stop::Returned => "returned\r\n",
+            Stop::Halted => return "halted\r\n".to_string(),
+            Stop::EndOfProgram => return "end of program\r\n".to_string(),
+            Stop::InstructionLimit => "instruction limit reached\r\n",
+        };
+
+        format!("{reason}{}", self.location())
+    }
+
+    fn set(&mut self, name: &str, value: usize) -> Result<(), Box<dyn Error>> {
+        let registers = &mut self.cpu.registers;
+
+        if let Some(reg) = Register::from_name(name) {
+            registers.set(reg, value as u16);
+        } else if let Some(sr) = SegmentRegister::from_name(name) {
+            registers.set_segment(sr, value as u16);
+        } else if name == "ip" {
+            registers.ip = value as u16;
+        } else if let Some(flag) = name
+            .strip_suffix('f')
+            .and_then(|letter| letter.parse().ok())
+            .and_then(Flags::from_letter)
+        {
+            registers.flags.set(flag, value != 0);
+        } else {
+            return Err(format!("unknown register `{name}`").into());
+        }
+
+        Ok(())
+    }
+
+    /// Physical address of `segment:offset`, or of `offset` in `default`.
+    fn parse_address(
+        &self,
+        address: &str,
+        default: SegmentRegister,
+    ) -> Result<usize, Box<dyn Error>> {
+        let (segment, offset) = match address.split_once(':') {
+            Some((segment, offset)) => {
+                let segment = match SegmentRegister::from_name(segment) {
+                    Some(sr) => self.cpu.registers.get_segment(sr),
+                    None => parse(segment)? as u16,
+                };
+                (segment, parse(offset)? as u16)
+            }
+            None => (
+                self.cpu.registers.get_segment(default),
+                parse(address)? as u16,
+            ),
+        };
+
+        Ok(Memory::physical_address(segment, offset))
+    }
+
+    /// 16 bytes per line, e.g. `0x003e8  01 00 ... 00  ................`.
+    fn hexdump(&self, address: &str, len: usize) -> Result<String, Box<dyn Error>> {
+        let addr = self.parse_address(address, SegmentRegister::DS)?;
+        let bytes = self.cpu.memory.range(addr, len);
+
+        let mut res = String::new();
+        for (i, line) in bytes.chunks(16).enumerate() {
+            let hex: Vec<String> = line.iter().map(|byte| format!("{byte:02x}")).collect();
+            let ascii: String = line
+                .iter()
+                .map(|byte| match byte {
+                    0x20..=0x7E => *byte as char,
+                    _ => '.',
+                })
+                .collect();
+
+            res.push_str(&format!(
+                "{:#07x}  {:<47}  {ascii}\r\n",
+                addr + 16 * i,
+                hex.join(" ")
+            ));
+        }
+
+        Ok(res)
+    }
+
+    /// Up to [`DISASM_CONTEXT`] instructions before IP, found by decoding
+    /// from the start of the code segment, then `count` instructions from IP
+    /// with the next one marked.
+    fn disassemble(&self, count: usize) -> String {
+        let cs = self.cpu.registers.get_segment(SegmentRegister::CS);
+        let ip = self.cpu.registers.ip;
+
+        let mut before = Vec::new();
+        let mut offset = 0u16;
+        while offset < ip && Memory::physical_address(cs, offset) < self.end {
+            let Ok(instruction) = self.cpu.decode_at(cs, offset) else {
+                break;
+            };
+            before.push(offset);
+            offset = offset.wrapping_add(instruction.offset() as u16);
+        }
+        if offset != ip {
+            // IP is not on an instruction boundary of the linear sweep.
+            before.clear();
+        }
+
+        let start = before.len().saturating_sub(DISASM_CONTEXT);
+        let mut offsets = before.split_off(start);
+
+        let mut offset = ip;
+        for _ in 0..count {
+            if Memory::physical_address(cs, offset) >= self.end {
+                break;
+            }
+
+            let Ok(instruction) = self.cpu.decode_at(cs, offset) else {
+                break;
+            };
+            offsets.push(offset);
+            offset = offset.wrapping_add(instruction.offset() as u16);
+        }
+
+        let mut res = String::new();
+        for offset in offsets {
+            let marker = if offset == ip { "=>" } else { "  " };
+            let instruction = self.cpu.decode_at(cs, offset).expect("decoded above");
+
+            res.push_str(&format!(
+                "{marker} {cs:04x}:{offset:04x}  {}",
+                instruction.to_string()
+            ));
+        }
+
+        res
+    }
+}
+
+fn parse(value: &str) -> Result<usize, Box<dyn Error>> {
+    parse_number(value).ok_or_else(|| format!("invalid number `{value}`").into())
+}
@@ -0,0 +1,248 @@
+use std::{
+    error::Error,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{
+    cpu::Cpu,
+    flags::Flags,
+    loader::install_service,
+    memory::Memory,
+    register::{Register, SegmentRegister},
+};
+
+pub const SECTOR_SIZE: usize = 512;
+
+/// Where the IBM PC BIOS has its INT 13h entry. Execution reaching the stub
+/// placed there hands the call over to [`Disk::service`].
+const ENTRY: (u16, u16) = (0xF000, 0xE3FE);
+
+const INVALID_FUNCTION: u8 = 0x01;
+const SECTOR_NOT_FOUND: u8 = 0x04;
+const TIMEOUT: u8 = 0x80;
+
+/// Cylinders, heads and sectors per track of a disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub cylinders: u16,
+    pub heads: u8,
+    pub sectors: u8,
+}
+
+impl Geometry {
+    /// Geometry of the standard floppy format of `size` bytes or, for a hard
+    /// disk, 16 heads of 63 sectors per track.
+    pub fn for_size(size: u64, hard_disk: bool) -> Option<Self> {
+        let (cylinders, heads, sectors) = match (hard_disk, size / 1024) {
+            (false, 160) => (40, 1, 8),
+            (false, 180) => (40, 1, 9),
+            (false, 320) => (40, 2, 8),
+            (false, 360) => (40, 2, 9),
+            (false, 720) => (80, 2, 9),
+            (false, 1200) => (80, 2, 15),
+            (false, 1440) => (80, 2, 18),
+            (false, 2880) => (80, 2, 36),
+            (false, _) => return None,
+            (true, _) => {
+                let cylinders = size / (16 * 63 * SECTOR_SIZE as u64);
+                if cylinders == 0 || cylinders > 1024 {
+                    return None;
+                }
+
+                (cylinders as u16, 16, 63)
+            }
+        };
+
+        Some(Self {
+            cylinders,
+            heads,
+            sectors,
+        })
+    }
+
+    /// Sector number from the start of the disk of a cylinder, head and
+    /// 1-based sector.
+    fn lba(&self, cylinder: u16, head: u8, sector: u8) -> Option<u64> {
+        if cylinder >= self.cylinders || head >= self.heads || sector == 0 || sector > self.sectors
+        {
+            return None;
+        }
+
+        let track = cylinder as u64 * self.heads as u64 + head as u64;
+        Some(track * self.sectors as u64 + sector as u64 - 1)
+    }
+}
+
+/// A disk image file served to the program through INT 13h as BIOS drive
+/// `drive`: 0x00 for the first floppy, 0x80 for the first hard disk. Writes
+/// go straight to the file.
+pub struct Disk {
+    file: File,
+    drive: u8,
+    geometry: Geometry,
+    /// Status of the last operation, returned by function 01h.
+    status: u8,
+}
+
+impl fmt::Debug for Disk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Disk")
+            .field("drive", &self.drive)
+            .field("geometry", &self.geometry)
+            .field("status", &self.status)
+            .finish()
+    }
+}
+
+impl Disk {
+    /// Opens the image at `path` for reading and writing, with the geometry
+    /// its size implies.
+    pub fn open(path: impl AsRef<Path>, drive: u8) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        let Some(geometry) = Geometry::for_size(size, drive >= 0x80) else {
+            return Err(format!("No disk geometry matches an image of {size} bytes").into());
+        };
+
+        Ok(Self {
+            file,
+            drive,
+            geometry,
+            status: 0,
+        })
+    }
+
+    pub fn drive(&self) -> u8 {
+        self.drive
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        self.geometry
+    }
+
+    /// Points INT 13h at the service stub.
+    pub fn install(&self, cpu: &mut Cpu) {
+        install_service(&mut cpu.memory, 0x13, ENTRY);
+    }
+
+    /// Whether `addr` is the service stub.
+    pub fn is_entry(&self, addr: usize) -> bool {
+        Memory::physical_address(ENTRY.0, ENTRY.1) == addr
+    }
+
+    /// Performs the INT 13h call in AH: reset (00h), status (01h), read
+    /// (02h) and write (03h) sectors, and get drive parameters (08h). Like
+    /// the BIOS, failures return CF set and the status in AH, including for
+    /// functions that aren't emulated.
+    pub fn service(&mut self, cpu: &mut Cpu) -> Result<(), Box<dyn Error>> {
+        let function = cpu.registers.get(Register::AH) as u8;
+        let drive = cpu.registers.get(Register::DL) as u8;
+
+        let res = match function {
+            _ if drive != self.drive && function != 0x01 => Err(TIMEOUT),
+            0x00 => Ok(()),
+            0x01 => {
+                let status = self.status;
+                self.status = 0;
+                cpu.registers.set(Register::AH, status as u16);
+                cpu.registers.flags.set(Flags::CF, status != 0);
+
+                return Ok(());
+            }
+            0x02 => self.transfer(cpu, false)?,
+            0x03 => self.transfer(cpu, true)?,
+            0x08 => {
+                let Geometry {
+                    cylinders,
+                    heads,
+                    sectors,
+                } = self.geometry;
+                let max_cylinder = cylinders - 1;
+
+                cpu.registers.set(Register::AX, 0);
+                cpu.registers.set(Register::CH, max_cylinder & 0x00FF);
+                cpu.registers.set(
+                    Register::CL,
+                    (sectors as u16) | ((max_cylinder >> 2) & 0b1100_0000),
+                );
+                cpu.registers.set(Register::DH, heads as u16 - 1);
+                cpu.registers.set(Register::DL, 1);
+                if self.drive < 0x80 {
+                    cpu.registers.set(Register::BL, floppy_type(self.geometry));
+                }
+
+                Ok(())
+            }
+            _ => Err(INVALID_FUNCTION),
+        };
+
+        self.status = res.err().unwrap_or(0);
+        cpu.registers.set(Register::AH, self.status as u16);
+        cpu.registers.flags.set(Flags::CF, self.status != 0);
+
+        Ok(())
+    }
+
+    /// Reads or writes AL sectors starting at the cylinder, head and sector
+    /// in CX and DH, to or from ES:BX. AL is left as the number of sectors
+    /// transferred.
+    fn transfer(&mut self, cpu: &mut Cpu, write: bool) -> Result<Result<(), u8>, Box<dyn Error>> {
+        let count = cpu.registers.get(Register::AL);
+        let cx = cpu.registers.get(Register::CX);
+        let cylinder = (cx >> 8) | ((cx & 0b1100_0000) << 2);
+        let sector = (cx & 0b0011_1111) as u8;
+        let head = cpu.registers.get(Register::DH) as u8;
+
+        let Some(lba) = self.geometry.lba(cylinder, head, sector) else {
+            cpu.registers.set(Register::AL, 0);
+            return Ok(Err(SECTOR_NOT_FOUND));
+        };
+
+        let total = self.geometry.cylinders as u64
+            * self.geometry.heads as u64
+            * self.geometry.sectors as u64;
+        let count = count.min(total.saturating_sub(lba) as u16);
+
+        let (es, bx) = (
+            cpu.registers.get_segment(SegmentRegister::ES),
+            cpu.registers.get(Register::BX),
+        );
+        let len = count as usize * SECTOR_SIZE;
+
+        self.file.seek(SeekFrom::Start(lba * SECTOR_SIZE as u64))?;
+        if write {
+            let buffer: Vec<u8> = (0..len)
+                .map(|i| cpu.memory.read_byte(es, bx.wrapping_add(i as u16)))
+                .collect();
+            self.file.write_all(&buffer)?;
+        } else {
+            let mut buffer = vec![0; len];
+            self.file.read_exact(&mut buffer)?;
+            for (i, byte) in buffer.iter().enumerate() {
+                cpu.memory.write_byte(es, bx.wrapping_add(i as u16), *byte);
+            }
+        }
+
+        cpu.registers.set(Register::AL, count);
+        if count == 0 {
+            return Ok(Err(SECTOR_NOT_FOUND));
+        }
+
+        Ok(Ok(()))
+    }
+}
+
+/// CMOS drive type INT 13h function 08h reports in BL for a floppy.
+fn floppy_type(geometry: Geometry) -> u16 {
+    match (geometry.cylinders, geometry.sectors) {
+        (40, _) => 1,
+        (80, 15) => 2,
+        (80, 9) => 3,
+        (80, 18) => 4,
+        _ => 5,
+    }
+}
@@ -0,0 +1,490 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    cpu::Cpu,
+    flags::Flags,
+    loader::{install_service, EXIT_CODE, EXIT_STUB, MEMORY_TOP, SERVICE_STUB},
+    memory::Memory,
+    register::{Register, SegmentRegister},
+};
+
+/// Segment of the stubs INT 20h and INT 21h point at. Execution reaching
+/// one is what hands the call over to [`Dos::service`].
+const ENTRY_SEGMENT: u16 = EXIT_STUB.0;
+const INT20_ENTRY: u16 = EXIT_STUB.1 + EXIT_CODE.len() as u16;
+const INT21_ENTRY: u16 = INT20_ENTRY + SERVICE_STUB.len() as u16;
+
+/// First handle given to a file, after stdin, stdout, stderr, aux and prn.
+const FIRST_FILE_HANDLE: u16 = 5;
+
+const INVALID_FUNCTION: u16 = 0x01;
+const FILE_NOT_FOUND: u16 = 0x02;
+const PATH_NOT_FOUND: u16 = 0x03;
+const TOO_MANY_OPEN_FILES: u16 = 0x04;
+const ACCESS_DENIED: u16 = 0x05;
+const INVALID_HANDLE: u16 = 0x06;
+const INSUFFICIENT_MEMORY: u16 = 0x08;
+const INVALID_BLOCK: u16 = 0x09;
+
+/// Emulation of the INT 20h and INT 21h services DOS programs commonly use:
+/// console input and output, termination, interrupt vectors, the version,
+/// memory allocation, and file access confined to a sandbox directory.
+///
+/// Attach it to [`Cpu::dos`] before the program is loaded, so that the
+/// loader can point the vectors at it.
+pub struct Dos {
+    sandbox: PathBuf,
+    input: Box<dyn BufRead>,
+    /// Where console output goes; when `None` it is kept in `captured`.
+    output: Option<Box<dyn Write>>,
+    captured: Vec<u8>,
+    files: HashMap<u16, File>,
+    /// Allocated memory blocks, by segment, with their size in paragraphs.
+    blocks: BTreeMap<u16, u16>,
+    exit_code: Option<u8>,
+}
+
+impl fmt::Debug for Dos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dos")
+            .field("sandbox", &self.sandbox)
+            .field("files", &self.files.len())
+            .field("blocks", &self.blocks)
+            .field("exit_code", &self.exit_code)
+            .finish()
+    }
+}
+
+impl Dos {
+    /// Files are created and opened inside `sandbox`. The console reads
+    /// nothing and its output is captured until [`Dos::with_input`] and
+    /// [`Dos::with_output`] say otherwise.
+    pub fn new(sandbox: impl Into<PathBuf>) -> Self {
+        Self {
+            sandbox: sandbox.into(),
+            input: Box::new(io::empty()),
+            output: None,
+            captured: Vec::new(),
+            files: HashMap::new(),
+            blocks: BTreeMap::new(),
+            exit_code: None,
+        }
+    }
+
+    pub fn with_input(mut self, input: Box<dyn BufRead>) -> Self {
+        self.input = input;
+        self
+    }
+
+    pub fn with_output(mut self, output: Box<dyn Write>) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    /// Console output written while no output was given.
+    pub fn captured(&self) -> &[u8] {
+        &self.captured
+    }
+
+    /// Code the program terminated with, once it has.
+    pub fn exit_code(&self) -> Option<u8> {
+        self.exit_code
+    }
+
+    /// Points INT 20h and INT 21h at the service stubs and gives the program
+    /// whose PSP is at `psp` all conventional memory above it, as DOS does.
+    pub fn install(&mut self, cpu: &mut Cpu, psp: u16) {
+        for (vector, entry) in [(0x20, INT20_ENTRY), (0x21, INT21_ENTRY)] {
+            install_service(&mut cpu.memory, vector, (ENTRY_SEGMENT, entry));
+        }
+
+        self.blocks.clear();
+        self.blocks.insert(psp, MEMORY_TOP.saturating_sub(psp));
+    }
+
+    /// Whether `addr` is one of the service stubs.
+    pub fn is_entry(&self, addr: usize) -> bool {
+        [INT20_ENTRY, INT21_ENTRY]
+            .iter()
+            .any(|entry| Memory::physical_address(ENTRY_SEGMENT, *entry) == addr)
+    }
+
+    /// Performs the call that reached the stub at CS:IP. Failures are
+    /// reported the DOS way, with CF set and the error code in AX; only
+    /// functions that aren't emulated are returned as errors.
+    pub fn service(&mut self, cpu: &mut Cpu) -> Result<(), Box<dyn Error>> {
+        let ip = cpu.registers.ip;
+        let function = if ip == INT20_ENTRY {
+            0x00
+        } else {
+            cpu.registers.get(Register::AH) as u8
+        };
+
+        let res = match function {
+            0x00 => self.terminate(cpu, 0),
+            0x4C => self.terminate(cpu, cpu.registers.get(Register::AL) as u8),
+            0x01 => self.read_char(cpu),
+            0x02 => {
+                let dl = cpu.registers.get(Register::DL) as u8;
+                self.write(&[dl])?;
+                cpu.registers.set(Register::AL, dl as u16);
+
+                Ok(())
+            }
+            0x09 => {
+                let text = read_string(cpu, b'$');
+                self.write(&text)?;
+                cpu.registers.set(Register::AL, b'$' as u16);
+
+                Ok(())
+            }
+            0x0A => self.read_line(cpu),
+            0x25 => {
+                let vector = cpu.registers.get(Register::AL);
+                let (segment, offset) = (
+                    cpu.registers.get_segment(SegmentRegister::DS),
+                    cpu.registers.get(Register::DX),
+                );
+                cpu.memory.write_word(0, 4 * vector, offset);
+                cpu.memory.write_word(0, 4 * vector + 2, segment);
+
+                Ok(())
+            }
+            0x35 => {
+                let vector = cpu.registers.get(Register::AL);
+                let offset = cpu.memory.read_word(0, 4 * vector);
+                let segment = cpu.memory.read_word(0, 4 * vector + 2);
+                cpu.registers.set(Register::BX, offset);
+                cpu.registers.set_segment(SegmentRegister::ES, segment);
+
+                Ok(())
+            }
+            // DOS 5.0, with no OEM or serial number.
+            0x30 => {
+                cpu.registers.set(Register::AX, 0x0005);
+                cpu.registers.set(Register::BX, 0);
+                cpu.registers.set(Register::CX, 0);
+
+                Ok(())
+            }
+            0x48 => self.allocate(cpu),
+            0x49 => {
+                let segment = cpu.registers.get_segment(SegmentRegister::ES);
+                self.blocks
+                    .remove(&segment)
+                    .map(|_| ())
+                    .ok_or(INVALID_BLOCK)
+            }
+            0x4A => self.resize(cpu),
+            0x3C => self.open(cpu, true),
+            0x3D => self.open(cpu, false),
+            0x3E => {
+                let handle = cpu.registers.get(Register::BX);
+                self.files.remove(&handle).map(|_| ()).ok_or(INVALID_HANDLE)
+            }
+            0x3F => self.read_file(cpu),
+            0x40 => self.write_file(cpu),
+            0x41 => {
+                let path = self.path(cpu);
+                path.and_then(|path| fs::remove_file(path).map_err(|e| error_code(&e)))
+            }
+            0x42 => self.seek(cpu),
+            _ => return Err(format!("Unsupported DOS function {function:#04x}").into()),
+        };
+
+        let flags = &mut cpu.registers.flags;
+        match res {
+            Ok(()) => flags.set(Flags::CF, false),
+            Err(code) => {
+                flags.set(Flags::CF, true);
+                cpu.registers.set(Register::AX, code);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records the exit code and makes the stub return to the `hlt` that
+    /// ends the simulation instead of to the program.
+    fn terminate(&mut self, cpu: &mut Cpu, code: u8) -> Result<(), u16> {
+        self.exit_code = Some(code);
+        self.files.clear();
+
+        let (ss, sp) = (
+            cpu.registers.get_segment(SegmentRegister::SS),
+            cpu.registers.get(Register::SP),
+        );
+        let (segment, offset) = EXIT_STUB;
+        cpu.memory.write_word(ss, sp, offset);
+        cpu.memory.write_word(ss, sp.wrapping_add(2), segment);
+
+        Ok(())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match &mut self.output {
+            Some(output) => {
+                output.write_all(bytes)?;
+                output.flush()
+            }
+            None => {
+                self.captured.extend_from_slice(bytes);
+                Ok(())
+            }
+        }
+    }
+
+    /// Function 01h: reads a character into AL and echoes it. The end of
+    /// input reads as Ctrl-Z.
+    fn read_char(&mut self, cpu: &mut Cpu) -> Result<(), u16> {
+        let mut byte = [0x1A];
+        if self.input.read(&mut byte).map_err(|e| error_code(&e))? == 1 {
+            self.write(&byte).map_err(|e| error_code(&e))?;
+        }
+        cpu.registers.set(Register::AL, byte[0] as u16);
+
+        Ok(())
+    }
+
+    /// Function 0Ah: reads a line into the buffer at DS:DX, whose first byte
+    /// is its size. The line is stored from the third byte, truncated to
+    /// fit, terminated by a carriage return that the count in the second
+    /// byte leaves out.
+    fn read_line(&mut self, cpu: &mut Cpu) -> Result<(), u16> {
+        let (ds, dx) = (
+            cpu.registers.get_segment(SegmentRegister::DS),
+            cpu.registers.get(Register::DX),
+        );
+        let size = cpu.memory.read_byte(ds, dx) as usize;
+        if size == 0 {
+            return Ok(());
+        }
+
+        let mut line = Vec::new();
+        self.input
+            .read_until(b'\n', &mut line)
+            .map_err(|e| error_code(&e))?;
+        while matches!(line.last(), Some(b'\n' | b'\r')) {
+            line.pop();
+        }
+        line.truncate(size - 1);
+
+        for (i, byte) in line.iter().chain([&b'\r']).enumerate() {
+            cpu.memory
+                .write_byte(ds, dx.wrapping_add(2 + i as u16), *byte);
+        }
+        cpu.memory
+            .write_byte(ds, dx.wrapping_add(1), line.len() as u8);
+
+        line.push(b'\r');
+        self.write(&line).map_err(|e| error_code(&e))
+    }
+
+    /// Function 48h: allocates BX paragraphs at the first large enough gap
+    /// and returns the segment in AX, or the largest gap in BX.
+    fn allocate(&mut self, cpu: &mut Cpu) -> Result<(), u16> {
+        let size = cpu.registers.get(Register::BX);
+
+        let mut start = *self.blocks.keys().next().unwrap_or(&MEMORY_TOP);
+        let mut largest = 0;
+        let ends = self
+            .blocks
+            .iter()
+            .map(|(segment, size)| (*segment, segment + size))
+            .chain([(MEMORY_TOP, MEMORY_TOP)])
+            .collect::<Vec<_>>();
+
+        for (segment, end) in ends {
+            let gap = segment.saturating_sub(start);
+            if gap >= size {
+                self.blocks.insert(start, size);
+                cpu.registers.set(Register::AX, start);
+
+                return Ok(());
+            }
+
+            largest = largest.max(gap);
+            start = end;
+        }
+
+        cpu.registers.set(Register::BX, largest);
+        Err(INSUFFICIENT_MEMORY)
+    }
+
+    /// Function 4Ah: resizes the block at ES to BX paragraphs, or returns the
+    /// most it can grow to in BX.
+    fn resize(&mut self, cpu: &mut Cpu) -> Result<(), u16> {
+        let segment = cpu.registers.get_segment(SegmentRegister::ES);
+        let size = cpu.registers.get(Register::BX);
+
+        if !self.blocks.contains_key(&segment) {
+            return Err(INVALID_BLOCK);
+        }
+
+        let limit = self
+            .blocks
+            .range(segment + 1..)
+            .next()
+            .map_or(MEMORY_TOP, |(next, _)| *next);
+        let available = limit.saturating_sub(segment);
+
+        if size > available {
+            cpu.registers.set(Register::BX, available);
+            return Err(INSUFFICIENT_MEMORY);
+        }
+
+        self.blocks.insert(segment, size);
+        Ok(())
+    }
+
+    /// Host path of the ASCIIZ name at DS:DX inside the sandbox. Drive
+    /// letters, absolute paths and `..` are refused.
+    fn path(&self, cpu: &Cpu) -> Result<PathBuf, u16> {
+        let name = String::from_utf8_lossy(&read_string(cpu, 0)).replace('\\', "/");
+        if name.contains(':') {
+            return Err(PATH_NOT_FOUND);
+        }
+
+        let mut path = self.sandbox.clone();
+        for component in Path::new(&name).components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::CurDir => {}
+                _ => return Err(ACCESS_DENIED),
+            }
+        }
+
+        Ok(path)
+    }
+
+    /// Functions 3Ch (create, truncating) and 3Dh (open with the access mode
+    /// in AL); the handle is returned in AX.
+    fn open(&mut self, cpu: &mut Cpu, create: bool) -> Result<(), u16> {
+        let path = self.path(cpu)?;
+
+        let mut options = OpenOptions::new();
+        if create {
+            options.read(true).write(true).create(true).truncate(true);
+        } else {
+            match cpu.registers.get(Register::AL) & 0b111 {
+                0 => options.read(true),
+                1 => options.write(true),
+                2 => options.read(true).write(true),
+                _ => return Err(INVALID_FUNCTION),
+            };
+        }
+
+        let file = options.open(path).map_err(|e| error_code(&e))?;
+        let handle = (FIRST_FILE_HANDLE..=u16::MAX)
+            .find(|handle| !self.files.contains_key(handle))
+            .ok_or(TOO_MANY_OPEN_FILES)?;
+
+        self.files.insert(handle, file);
+        cpu.registers.set(Register::AX, handle);
+
+        Ok(())
+    }
+
+    /// Function 3Fh: reads up to CX bytes from handle BX to DS:DX and
+    /// returns the count in AX.
+    fn read_file(&mut self, cpu: &mut Cpu) -> Result<(), u16> {
+        let handle = cpu.registers.get(Register::BX);
+        let mut buffer = vec![0; cpu.registers.get(Register::CX) as usize];
+
+        let count = match handle {
+            0 => self.input.read(&mut buffer),
+            _ => match self.files.get_mut(&handle) {
+                Some(file) => file.read(&mut buffer),
+                None => return Err(INVALID_HANDLE),
+            },
+        };
+        let count = count.map_err(|e| error_code(&e))?;
+
+        let (ds, dx) = (
+            cpu.registers.get_segment(SegmentRegister::DS),
+            cpu.registers.get(Register::DX),
+        );
+        for (i, byte) in buffer[..count].iter().enumerate() {
+            cpu.memory.write_byte(ds, dx.wrapping_add(i as u16), *byte);
+        }
+        cpu.registers.set(Register::AX, count as u16);
+
+        Ok(())
+    }
+
+    /// Function 40h: writes CX bytes from DS:DX to handle BX and returns the
+    /// count in AX. Stdout and stderr go to the console.
+    fn write_file(&mut self, cpu: &mut Cpu) -> Result<(), u16> {
+        let handle = cpu.registers.get(Register::BX);
+        let (ds, dx) = (
+            cpu.registers.get_segment(SegmentRegister::DS),
+            cpu.registers.get(Register::DX),
+        );
+        let bytes: Vec<u8> = (0..cpu.registers.get(Register::CX))
+            .map(|i| cpu.memory.read_byte(ds, dx.wrapping_add(i)))
+            .collect();
+
+        let res = match handle {
+            1 | 2 => self.write(&bytes),
+            _ => match self.files.get_mut(&handle) {
+                Some(file) => file.write_all(&bytes),
+                None => return Err(INVALID_HANDLE),
+            },
+        };
+        res.map_err(|e| error_code(&e))?;
+        cpu.registers.set(Register::AX, bytes.len() as u16);
+
+        Ok(())
+    }
+
+    /// Function 42h: moves handle BX by CX:DX from the origin in AL and
+    /// returns the new position in DX:AX.
+    fn seek(&mut self, cpu: &mut Cpu) -> Result<(), u16> {
+        let handle = cpu.registers.get(Register::BX);
+        let offset = ((cpu.registers.get(Register::CX) as u32) << 16)
+            | cpu.registers.get(Register::DX) as u32;
+
+        let from = match cpu.registers.get(Register::AL) {
+            0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset as i32 as i64),
+            2 => SeekFrom::End(offset as i32 as i64),
+            _ => return Err(INVALID_FUNCTION),
+        };
+
+        let file = self.files.get_mut(&handle).ok_or(INVALID_HANDLE)?;
+        let position = file.seek(from).map_err(|e| error_code(&e))? as u32;
+
+        cpu.registers.set(Register::AX, position as u16);
+        cpu.registers.set(Register::DX, (position >> 16) as u16);
+
+        Ok(())
+    }
+}
+
+/// Bytes at DS:DX up to, but not including, `terminator`.
+fn read_string(cpu: &Cpu, terminator: u8) -> Vec<u8> {
+    let (ds, dx) = (
+        cpu.registers.get_segment(SegmentRegister::DS),
+        cpu.registers.get(Register::DX),
+    );
+
+    (0..u16::MAX)
+        .map(|i| cpu.memory.read_byte(ds, dx.wrapping_add(i)))
+        .take_while(|byte| *byte != terminator)
+        .collect()
+}
+
+/// DOS error code closest to a host I/O error.
+fn error_code(e: &io::Error) -> u16 {
+    match e.kind() {
+        io::ErrorKind::NotFound => FILE_NOT_FOUND,
+        _ => ACCESS_DENIED,
+    }
+}
@@ -0,0 +1,192 @@
+use crate::{arithmetic::ArithmeticOp, shift::ShiftOp};
+
+/// The FLAGS register, stored with the same bit layout as on the 8086.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Flags(pub u16);
+
+impl Flags {
+    pub const CF: u16 = 1 << 0;
+    pub const PF: u16 = 1 << 2;
+    pub const AF: u16 = 1 << 4;
+    pub const ZF: u16 = 1 << 6;
+    pub const SF: u16 = 1 << 7;
+    pub const TF: u16 = 1 << 8;
+    pub const IF: u16 = 1 << 9;
+    pub const DF: u16 = 1 << 10;
+    pub const OF: u16 = 1 << 11;
+
+    /// Bits that hold a flag; the rest are ignored when FLAGS is loaded.
+    pub const DEFINED: u16 = 0x0FD5;
+    /// Bits the 8086 always stores as set when FLAGS is saved to memory.
+    pub const RESERVED: u16 = 0xF002;
+
+    /// Flags in the order they are listed in traces, with their letters.
+    const NAMES: [(u16, char); 9] = [
+        (Self::CF, 'C'),
+        (Self::PF, 'P'),
+        (Self::AF, 'A'),
+        (Self::ZF, 'Z'),
+        (Self::SF, 'S'),
+        (Self::TF, 'T'),
+        (Self::IF, 'I'),
+        (Self::DF, 'D'),
+        (Self::OF, 'O'),
+    ];
+
+    /// Looks up a flag by its trace letter, e.g. `Z` for [`Flags::ZF`].
+    pub fn from_letter(letter: char) -> Option<u16> {
+        Self::NAMES
+            .iter()
+            .find(|(_, name)| *name == letter.to_ascii_uppercase())
+            .map(|(flag, _)| *flag)
+    }
+
+    pub fn get(&self, flag: u16) -> bool {
+        self.0 & flag == flag
+    }
+
+    pub fn set(&mut self, flag: u16, value: bool) {
+        if value {
+            self.0 |= flag;
+        } else {
+            self.0 &= !flag;
+        }
+    }
+
+    /// Sets ZF, SF and PF from `res`, which is `w` wide.
+    pub fn set_result(&mut self, res: u16, w: bool) {
+        let sign = if w { 0x8000 } else { 0x0080 };
+        let mask = if w { 0xFFFF } else { 0x00FF };
+
+        self.set(Self::ZF, res & mask == 0);
+        self.set(Self::SF, res & sign != 0);
+        self.set(Self::PF, (res as u8).count_ones().is_multiple_of(2));
+    }
+
+    /// Performs `op` on `dst` and `src` at width `w`, updating CF, PF, AF, ZF,
+    /// SF and OF exactly as the 8086 does, and returns the result.
+    pub fn arithmetic(&mut self, op: ArithmeticOp, dst: u16, src: u16, w: bool) -> u16 {
+        let sign: u32 = if w { 0x8000 } else { 0x0080 };
+        let mask: u32 = if w { 0xFFFF } else { 0x00FF };
+
+        let (a, b) = (dst as u32 & mask, src as u32 & mask);
+        let carry = self.get(Self::CF) as u32;
+
+        let res = match op {
+            ArithmeticOp::Add | ArithmeticOp::Adc => {
+                let c = if op == ArithmeticOp::Adc { carry } else { 0 };
+                let res = a + b + c;
+
+                self.set(Self::CF, res > mask);
+                self.set(Self::AF, (a ^ b ^ res) & 0x10 != 0);
+                self.set(Self::OF, (a ^ res) & (b ^ res) & sign != 0);
+
+                res
+            }
+            ArithmeticOp::Sub | ArithmeticOp::Sbb | ArithmeticOp::Cmp => {
+                let c = if op == ArithmeticOp::Sbb { carry } else { 0 };
+                let res = a.wrapping_sub(b).wrapping_sub(c);
+
+                self.set(Self::CF, b + c > a);
+                self.set(Self::AF, (a ^ b ^ res) & 0x10 != 0);
+                self.set(Self::OF, (a ^ b) & (a ^ res) & sign != 0);
+
+                res
+            }
+            ArithmeticOp::And | ArithmeticOp::Test | ArithmeticOp::Or | ArithmeticOp::Xor => {
+                let res = match op {
+                    ArithmeticOp::Or => a | b,
+                    ArithmeticOp::Xor => a ^ b,
+                    _ => a & b,
+                };
+
+                self.set(Self::CF, false);
+                self.set(Self::AF, false);
+                self.set(Self::OF, false);
+
+                res
+            }
+        };
+
+        let res = (res & mask) as u16;
+        self.set_result(res, w);
+
+        res
+    }
+
+    /// Increments or decrements `value` at width `w`. Unlike `add` and `sub`
+    /// this leaves CF alone.
+    pub fn inc_dec(&mut self, dec: bool, value: u16, w: bool) -> u16 {
+        let cf = self.get(Self::CF);
+        let op = if dec {
+            ArithmeticOp::Sub
+        } else {
+            ArithmeticOp::Add
+        };
+
+        let res = self.arithmetic(op, value, 1, w);
+        self.set(Self::CF, cf);
+
+        res
+    }
+
+    /// Shifts or rotates `value` at width `w` by `count` bits, one bit at a
+    /// time as the 8086 does. A count of 0 leaves the flags alone; OF is
+    /// only meaningful for a count of 1.
+    pub fn shift(&mut self, op: ShiftOp, value: u16, count: u8, w: bool) -> u16 {
+        let bits = if w { 16 } else { 8 };
+        let sign: u16 = 1 << (bits - 1);
+        let mask: u16 = if w { 0xFFFF } else { 0x00FF };
+
+        let mut res = value & mask;
+        if count == 0 {
+            return res;
+        }
+
+        for _ in 0..count {
+            let (msb, lsb) = (res & sign != 0, res & 1 != 0);
+            let carry = self.get(Self::CF);
+
+            res = match op {
+                ShiftOp::Rol => (res << 1) | msb as u16,
+                ShiftOp::Ror => (res >> 1) | if lsb { sign } else { 0 },
+                ShiftOp::Rcl => (res << 1) | carry as u16,
+                ShiftOp::Rcr => (res >> 1) | if carry { sign } else { 0 },
+                ShiftOp::Shl => res << 1,
+                ShiftOp::Shr => res >> 1,
+                ShiftOp::Sar => (res >> 1) | (res & sign),
+            } & mask;
+
+            let cf = match op {
+                ShiftOp::Rol | ShiftOp::Rcl | ShiftOp::Shl => msb,
+                ShiftOp::Ror | ShiftOp::Rcr | ShiftOp::Shr | ShiftOp::Sar => lsb,
+            };
+            self.set(Self::CF, cf);
+        }
+
+        let (msb, next) = (res & sign != 0, res & (sign >> 1) != 0);
+        let of = match op {
+            ShiftOp::Rol | ShiftOp::Rcl | ShiftOp::Shl => msb != self.get(Self::CF),
+            ShiftOp::Ror | ShiftOp::Rcr => msb != next,
+            ShiftOp::Shr => value & sign != 0,
+            ShiftOp::Sar => false,
+        };
+        self.set(Self::OF, of);
+
+        if matches!(op, ShiftOp::Shl | ShiftOp::Shr | ShiftOp::Sar) {
+            self.set(Self::AF, false);
+            self.set_result(res, w);
+        }
+
+        res
+    }
+
+    /// Letters of every set flag, e.g. `CPZ`.
+    pub fn to_string(self) -> String {
+        Self::NAMES
+            .iter()
+            .filter(|(flag, _)| self.get(*flag))
+            .map(|(_, name)| name)
+            .collect()
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    arithmetic::ArithmeticOp, instruction::Instruction, mode::Mode, parse_number,
+    register::Register, stack::StackOp,
+};
+
+/// A function found in decoded instructions: a call target, or code that
+/// starts with the `push bp; mov bp, sp` prologue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub start: usize,
+    /// Whether it sets up a frame with `push bp; mov bp, sp`.
+    pub framed: bool,
+    /// Bytes of locals the prologue reserves with `sub sp, N`.
+    pub locals: u16,
+    /// Whether it returns with `retf`, so its arguments start at `bp + 6`
+    /// rather than `bp + 4`.
+    pub far: bool,
+    /// Offsets of its instructions: those reached from `start` without
+    /// following calls.
+    pub instructions: BTreeSet<usize>,
+    /// Offsets where an epilogue starts: `mov sp, bp` or `pop bp` before a
+    /// return.
+    pub epilogues: BTreeSet<usize>,
+}
+
+impl Function {
+    /// Offset from BP of the first argument.
+    fn arguments(&self) -> i32 {
+        if self.far {
+            6
+        } else {
+            4
+        }
+    }
+
+    /// `text` with the displacements of BP-based operands named, `[bp + 4]`
+    /// as `[bp + arg_0]` and `[bp - 2]` as `[bp - local_2]`, and the names
+    /// used with their values. The saved BP and return address keep their
+    /// numbers.
+    pub(crate) fn rename(&self, text: &str) -> (String, BTreeMap<String, i32>) {
+        let mut names = BTreeMap::new();
+        if !self.framed {
+            return (text.to_string(), names);
+        }
+
+        let Some((head, rest)) = text.split_once('[') else {
+            return (text.to_string(), names);
+        };
+        let Some((inner, tail)) = rest.split_once(']') else {
+            return (text.to_string(), names);
+        };
+
+        let (segment, address) = match inner.split_once(':') {
+            Some((segment, address)) => (format!("{segment}:"), address),
+            None => (String::new(), inner),
+        };
+        let terms: Vec<&str> = address.split(' ').collect();
+        let [registers @ .., sign @ ("+" | "-"), number] = terms.as_slice() else {
+            return (text.to_string(), names);
+        };
+        let Some(number) = parse_number(number) else {
+            return (text.to_string(), names);
+        };
+        if registers.first() != Some(&"bp") {
+            return (text.to_string(), names);
+        }
+
+        let displacement = if *sign == "-" {
+            -(number as i32)
+        } else {
+            number as i32
+        };
+        let (name, value) = match displacement {
+            d if d < 0 => (format!("local_{}", -d), -d),
+            d if d >= self.arguments() => (format!("arg_{}", d - self.arguments()), d),
+            _ => return (text.to_string(), names),
+        };
+
+        let renamed = format!(
+            "{head}[{segment}{} {sign} {name}]{tail}",
+            registers.join(" ")
+        );
+        names.insert(name, value);
+        (renamed, names)
+    }
+
+    /// Comment and `%define`s that go before the function.
+    pub(crate) fn header(&self, names: &BTreeMap<String, i32>) -> String {
+        let mut res = String::new();
+
+        let frame = if self.framed {
+            format!("bp frame, {} bytes of locals", self.locals)
+        } else {
+            "no bp frame".to_string()
+        };
+        let arguments = names
+            .iter()
+            .filter(|(name, _)| name.starts_with("arg_"))
+            .map(|(_, value)| value - self.arguments() + 2)
+            .max();
+        let arguments = match arguments {
+            Some(bytes) => format!(", {bytes} bytes of arguments"),
+            None => String::new(),
+        };
+        res.push_str(&format!(
+            "\r\n; function {:#06x}: {frame}{arguments}\r\n",
+            self.start
+        ));
+
+        for (name, value) in names {
+            res.push_str(&format!("%define {name} {value}\r\n"));
+        }
+
+        res
+    }
+}
+
+/// Whether `instruction` is `push reg` or `pop reg`, in either encoding.
+fn is_stack(instruction: &Instruction, op: StackOp, reg: Register) -> bool {
+    match instruction {
+        Instruction::StackRegister(i) => i.op == op && i.reg == reg,
+        Instruction::StackRegisterMemory(i) => i.op == op && i.mode == Mode::Reg && i.rm == reg,
+        _ => false,
+    }
+}
+
+/// Whether `instruction` is `mov dst, src` between word registers, in either
+/// direction of the encoding.
+fn is_move(instruction: &Instruction, dst: Register, src: Register) -> bool {
+    match instruction {
+        Instruction::RegisterMemoryToFromRegister(i) if i.mode == Mode::Reg => {
+            let (to, from) = if i.d { (i.reg, i.rm) } else { (i.rm, i.reg) };
+            (to, from) == (dst, src)
+        }
+        _ => false,
+    }
+}
+
+/// Bytes reserved by `sub sp, N`, if `instruction` is one. A sign-extended
+/// negative `N` frees stack instead.
+fn reserved(instruction: &Instruction) -> Option<u16> {
+    match instruction {
+        Instruction::ArithmeticImmediateToRegisterMemory(i)
+            if i.op == ArithmeticOp::Sub && i.mode == Mode::Reg && i.rm == Register::SP =>
+        {
+            Some(i.data).filter(|data| !i.s || (*data as i16) >= 0)
+        }
+        _ => None,
+    }
+}
+
+/// Whether `push bp; mov bp, sp` starts at `offset`.
+fn has_prologue(by_offset: &BTreeMap<usize, &Instruction>, offset: usize) -> bool {
+    let Some(push) = by_offset.get(&offset) else {
+        return false;
+    };
+
+    is_stack(push, StackOp::Push, Register::BP)
+        && by_offset
+            .get(&(offset + push.offset()))
+            .is_some_and(|mov| is_move(mov, Register::BP, Register::SP))
+}
+
+/// The functions of `instructions`, given with their offsets, by start.
+pub(crate) fn find(instructions: &[(usize, Instruction)]) -> Vec<Function> {
+    let by_offset: BTreeMap<usize, &Instruction> = instructions
+        .iter()
+        .map(|(offset, instruction)| (*offset, instruction))
+        .collect();
+    let next = |offset: usize| offset + by_offset[&offset].offset();
+
+    let mut starts = BTreeSet::new();
+    for (offset, instruction) in instructions {
+        if instruction.is_call() {
+            if let Some(target) = instruction.relative_target(*offset) {
+                starts.insert(target);
+            }
+        }
+        if has_prologue(&by_offset, *offset) {
+            starts.insert(*offset);
+        }
+    }
+    starts.retain(|start| by_offset.contains_key(start));
+
+    let mut res = Vec::new();
+    for start in starts {
+        let mut body = BTreeSet::new();
+        let mut pending = vec![start];
+        while let Some(offset) = pending.pop() {
+            let Some(instruction) = by_offset.get(&offset) else {
+                continue;
+            };
+            if !body.insert(offset) {
+                continue;
+            }
+
+            if !instruction.is_call() {
+                if let Some(target) = instruction.relative_target(offset) {
+                    pending.push(target);
+                }
+            }
+            if instruction.falls_through() {
+                pending.push(next(offset));
+            }
+        }
+
+        let framed = has_prologue(&by_offset, start);
+        let locals = match framed {
+            true => by_offset
+                .get(&next(next(start)))
+                .and_then(|instruction| reserved(instruction))
+                .unwrap_or(0),
+            false => 0,
+        };
+        let far = body.iter().any(|offset| {
+            matches!(by_offset[offset], Instruction::Return(instruction) if instruction.far)
+        });
+
+        let mut epilogues = BTreeSet::new();
+        for offset in &body {
+            let returns = by_offset
+                .get(&next(*offset))
+                .is_some_and(|instruction| instruction.is_return());
+            if is_stack(by_offset[offset], StackOp::Pop, Register::BP) && returns {
+                let previous = body.range(..offset).next_back();
+                match previous {
+                    Some(previous)
+                        if next(*previous) == *offset
+                            && is_move(by_offset[previous], Register::SP, Register::BP) =>
+                    {
+                        epilogues.insert(*previous)
+                    }
+                    _ => epilogues.insert(*offset),
+                };
+            }
+        }
+
+        res.push(Function {
+            start,
+            framed,
+            locals,
+            far,
+            instructions: body,
+            epilogues,
+        });
+    }
+
+    res
+}
@@ -0,0 +1,59 @@
+use std::{cell::RefCell, collections::BTreeMap, error::Error, fmt, rc::Rc};
+
+use crate::cpu::Cpu;
+
+/// Services interrupt vectors in Rust instead of in guest code. It is called
+/// in place of the interrupt sequence, with IP past the instruction that
+/// caused the interrupt, and returns whether it serviced the interrupt; if
+/// not, the sequence continues to the guest handler in the vector table.
+pub trait InterruptHandler {
+    fn interrupt(&mut self, cpu: &mut Cpu, vector: u8) -> Result<bool, Box<dyn Error>>;
+}
+
+impl<F> InterruptHandler for F
+where
+    F: FnMut(&mut Cpu, u8) -> Result<bool, Box<dyn Error>>,
+{
+    fn interrupt(&mut self, cpu: &mut Cpu, vector: u8) -> Result<bool, Box<dyn Error>> {
+        self(cpu, vector)
+    }
+}
+
+/// A handler shared between copies of the CPU, and with the host.
+pub type SharedHandler = Rc<RefCell<dyn InterruptHandler>>;
+
+/// Host handlers by vector. Vectors serviced here can't be hooked by the
+/// guest through the vector table.
+#[derive(Clone, Default)]
+pub struct Handlers {
+    handlers: BTreeMap<u8, SharedHandler>,
+}
+
+impl fmt::Debug for Handlers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.handlers.keys()).finish()
+    }
+}
+
+impl Handlers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Services `vector` with `handler`, replacing any handler it had.
+    pub fn attach(&mut self, vector: u8, handler: SharedHandler) {
+        self.handlers.insert(vector, handler);
+    }
+
+    pub fn detach(&mut self, vector: u8) {
+        self.handlers.remove(&vector);
+    }
+
+    pub fn get(&self, vector: u8) -> Option<SharedHandler> {
+        self.handlers.get(&vector).cloned()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+}
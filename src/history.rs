@@ -0,0 +1,243 @@
+use std::{collections::VecDeque, error::Error, rc::Rc};
+
+use crate::{
+    bus::Bus,
+    clocks::Clocks,
+    cpu::{Cpu, Registers, Shadow},
+    instruction::Instruction,
+    io::SharedDevice,
+    pic::Pic,
+    pit::Pit,
+};
+
+/// What is needed to take back one executed instruction.
+#[derive(Debug, Clone)]
+struct UndoEntry {
+    registers: Registers,
+    halted: bool,
+    nmi: bool,
+    shadow: Shadow,
+    clocks: u64,
+    instruction_clocks: Clocks,
+    /// Prefetch queue, bus cycle and T-states, which the clocks count when
+    /// there is a bus model.
+    bus: Option<Bus>,
+    pit_ticks: u64,
+    devices: Devices,
+    /// Physical address and previous value of every byte written, oldest
+    /// first.
+    writes: Vec<(usize, u8)>,
+}
+
+/// Copies of the devices the CPU shares with its I/O bus, which a clone of
+/// the CPU doesn't copy.
+#[derive(Debug, Clone)]
+struct Devices {
+    pit: Option<Pit>,
+    pic: Option<Pic>,
+}
+
+impl Devices {
+    fn save(cpu: &Cpu) -> Self {
+        Self {
+            pit: cpu.pit.as_ref().map(|pit| pit.borrow().clone()),
+            pic: cpu.pic.as_ref().map(|pic| pic.borrow().clone()),
+        }
+    }
+
+    /// Puts the saved state back into the devices `cpu` shares, so the I/O
+    /// bus sees it too.
+    fn restore(&self, cpu: &Cpu) {
+        if let (Some(pit), Some(saved)) = (&cpu.pit, &self.pit) {
+            *pit.borrow_mut() = saved.clone();
+        }
+        if let (Some(pic), Some(saved)) = (&cpu.pic, &self.pic) {
+            *pic.borrow_mut() = saved.clone();
+        }
+    }
+}
+
+/// Records executed instructions so they can be stepped back through.
+///
+/// The most recent instructions are kept as an undo log in a ring buffer of
+/// `capacity` entries. Every `snapshot_interval` instructions a full copy of
+/// the CPU is kept as well, so stepping back past the start of the undo log
+/// restores the nearest earlier snapshot and executes forward again. Changes
+/// made to the CPU outside of [`History::step`] are not replayed.
+///
+/// Executing again would repeat whatever DOS, the disk, host interrupt
+/// handlers and I/O devices other than the timer and interrupt controller
+/// did to the host, so no snapshots are kept while the CPU has any of them,
+/// and stepping back stops at the start of the undo log. Neither can take
+/// back what DOS, the disk or a handler did, such as files written or
+/// console output, so the undo log is dropped after each of their calls
+/// and stepping back stops just past it.
+#[derive(Debug, Clone)]
+pub struct History {
+    entries: VecDeque<UndoEntry>,
+    capacity: usize,
+    /// Full CPU copies, with their devices, and the number of instructions
+    /// executed before them, oldest first.
+    snapshots: VecDeque<(u64, Cpu, Devices)>,
+    snapshot_interval: u64,
+    max_snapshots: usize,
+    /// Instructions executed since the history started.
+    executed: u64,
+}
+
+impl History {
+    /// Starts recording from `cpu`'s current state.
+    pub fn new(cpu: &Cpu, capacity: usize, snapshot_interval: u64, max_snapshots: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            snapshots: match Self::replayable(cpu) {
+                true => VecDeque::from([(0, cpu.clone(), Devices::save(cpu))]),
+                false => VecDeque::new(),
+            },
+            snapshot_interval,
+            max_snapshots,
+            executed: 0,
+        }
+    }
+
+    /// Instructions executed since the history started, minus those stepped
+    /// back.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// How far back [`History::step_back`] can go.
+    pub fn available(&self) -> u64 {
+        let oldest_snapshot = self.snapshots.front().map_or(self.executed, |(at, ..)| *at);
+        let oldest_entry = self.executed - self.entries.len() as u64;
+
+        self.executed - oldest_snapshot.min(oldest_entry)
+    }
+
+    /// Executes one instruction with [`Cpu::step`], recording how to undo it.
+    pub fn step(&mut self, cpu: &mut Cpu) -> Result<Instruction, Box<dyn Error>> {
+        if !Self::replayable(cpu) {
+            self.snapshots.clear();
+        }
+
+        let mut entry = UndoEntry {
+            registers: cpu.registers,
+            halted: cpu.halted,
+            nmi: cpu.nmi,
+            shadow: cpu.shadow,
+            clocks: cpu.clocks,
+            instruction_clocks: cpu.instruction_clocks,
+            bus: cpu.bus.clone(),
+            pit_ticks: cpu.pit_ticks,
+            devices: Devices::save(cpu),
+            writes: Vec::new(),
+        };
+
+        let host_calls = cpu.host_calls;
+        cpu.memory.start_journal();
+        let res = cpu.step();
+        entry.writes = cpu.memory.take_journal();
+
+        if let Err(e) = res {
+            Self::undo(cpu, entry);
+            if cpu.host_calls != host_calls {
+                self.entries.clear();
+            }
+            return Err(e);
+        }
+
+        if cpu.host_calls != host_calls {
+            self.entries.clear();
+        } else {
+            if self.entries.len() == self.capacity {
+                self.entries.pop_front();
+            }
+            self.entries.push_back(entry);
+        }
+        self.executed += 1;
+
+        if self.executed.is_multiple_of(self.snapshot_interval) && Self::replayable(cpu) {
+            if self.snapshots.len() == self.max_snapshots {
+                self.snapshots.pop_front();
+            }
+            self.snapshots
+                .push_back((self.executed, cpu.clone(), Devices::save(cpu)));
+        }
+
+        res
+    }
+
+    /// Takes back the last `count` instructions, or as many as
+    /// [`History::available`] allows. Returns how many were taken back, or
+    /// the error of an instruction executed again from a snapshot, which
+    /// leaves the CPU where that instruction started.
+    pub fn step_back(&mut self, cpu: &mut Cpu, count: u64) -> Result<u64, Box<dyn Error>> {
+        let count = count.min(self.available());
+        let target = self.executed - count;
+
+        if count <= self.entries.len() as u64 {
+            for _ in 0..count {
+                let entry = self.entries.pop_back().expect("enough entries");
+                Self::undo(cpu, entry);
+            }
+        } else {
+            let (at, snapshot, devices) = self
+                .snapshots
+                .iter()
+                .rev()
+                .find(|(at, ..)| *at <= target)
+                .expect("a snapshot before the target");
+            let at = *at;
+            *cpu = snapshot.clone();
+            devices.restore(cpu);
+
+            self.entries.clear();
+            self.snapshots
+                .retain(|(snapshot_at, ..)| *snapshot_at <= at);
+            self.executed = at;
+
+            // Replaying executes exactly what was executed before.
+            for _ in at..target {
+                self.step(cpu)?;
+            }
+        }
+
+        self.executed = target;
+        self.snapshots.retain(|(at, ..)| *at <= target);
+
+        Ok(count)
+    }
+
+    /// Whether executing again from a snapshot only changes `cpu` and the
+    /// devices a snapshot restores.
+    fn replayable(cpu: &Cpu) -> bool {
+        let restored = |device: &SharedDevice| {
+            let device = Rc::as_ptr(device) as *const u8;
+            let pit = cpu.pit.as_ref().map(|pit| Rc::as_ptr(pit) as *const u8);
+            let pic = cpu.pic.as_ref().map(|pic| Rc::as_ptr(pic) as *const u8);
+            pit == Some(device) || pic == Some(device)
+        };
+
+        cpu.dos.is_none()
+            && cpu.disk.is_none()
+            && cpu.handlers.is_empty()
+            && cpu.io.devices().all(restored)
+    }
+
+    fn undo(cpu: &mut Cpu, entry: UndoEntry) {
+        for (addr, value) in entry.writes.into_iter().rev() {
+            cpu.memory.write_physical(addr, value);
+        }
+
+        cpu.registers = entry.registers;
+        cpu.halted = entry.halted;
+        cpu.nmi = entry.nmi;
+        cpu.shadow = entry.shadow;
+        cpu.clocks = entry.clocks;
+        cpu.instruction_clocks = entry.instruction_clocks;
+        cpu.bus = entry.bus;
+        cpu.pit_ticks = entry.pit_ticks;
+        entry.devices.restore(cpu);
+    }
+}
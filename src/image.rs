@@ -0,0 +1,161 @@
+use std::{collections::BTreeMap, error::Error};
+
+use crate::memory::MEMORY_SIZE;
+
+/// Bytes at scattered physical addresses, as ROM images in Intel HEX or
+/// Motorola S-record form describe them, with the address execution starts
+/// at if the file gives one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SparseImage {
+    /// Contiguous regions as `(address, bytes)`, in address order and never
+    /// touching each other.
+    pub regions: Vec<(usize, Vec<u8>)>,
+    /// Start address as CS:IP.
+    pub start: Option<(u16, u16)>,
+}
+
+impl SparseImage {
+    /// Parses Intel HEX records: data (00), end of file (01), extended
+    /// segment address (02), start segment address (03), extended linear
+    /// address (04) and start linear address (05).
+    pub fn parse_intel_hex(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut bytes = BTreeMap::new();
+        let mut start = None;
+        let mut base = 0;
+
+        for (number, line) in records(text) {
+            let error = |message: &str| format!("Intel HEX line {number}: {message}");
+
+            let Some(hex) = line.strip_prefix(':') else {
+                return Err(error("missing ':'").into());
+            };
+            let record = decode_hex(hex).ok_or_else(|| error("invalid hex digits"))?;
+            if record.len() < 5 || record.len() != 5 + record[0] as usize {
+                return Err(error("wrong length").into());
+            }
+            if record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+                return Err(error("bad checksum").into());
+            }
+
+            let offset = u16::from_be_bytes([record[1], record[2]]) as usize;
+            let data = &record[4..record.len() - 1];
+            let word = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+
+            match (record[3], data.len()) {
+                (0x00, _) => {
+                    for (i, byte) in data.iter().enumerate() {
+                        // Offsets wrap within the 64 KiB the base selects.
+                        bytes.insert((base + (offset + i) % 0x10000) % MEMORY_SIZE, *byte);
+                    }
+                }
+                (0x01, _) => break,
+                (0x02, 2) => base = 16 * word(0) as usize,
+                (0x03, 4) => start = Some((word(0), word(2))),
+                (0x04, 2) => base = (word(0) as usize) << 16,
+                (0x05, 4) => {
+                    start = Some(linear_start((word(0) as usize) << 16 | word(2) as usize))
+                }
+                (0x02..=0x05, _) => return Err(error("wrong length").into()),
+                (kind, _) => return Err(error(&format!("unknown record type {kind:02X}")).into()),
+            }
+        }
+
+        Ok(Self::from_bytes(bytes, start))
+    }
+
+    /// Parses Motorola S-records: data with 16, 24 and 32-bit addresses
+    /// (S1-S3) and the matching start addresses (S9-S7). Headers (S0) and
+    /// record counts (S5, S6) are checked but otherwise ignored.
+    pub fn parse_srecord(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut bytes = BTreeMap::new();
+        let mut start = None;
+
+        for (number, line) in records(text) {
+            let error = |message: &str| format!("S-record line {number}: {message}");
+
+            let mut chars = line.chars();
+            let (Some('S'), Some(kind)) = (chars.next(), chars.next()) else {
+                return Err(error("missing 'S' and record type").into());
+            };
+            let record = decode_hex(chars.as_str()).ok_or_else(|| error("invalid hex digits"))?;
+            if record.is_empty() || record.len() != 1 + record[0] as usize {
+                return Err(error("wrong length").into());
+            }
+            if record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0xFF {
+                return Err(error("bad checksum").into());
+            }
+
+            let address_len = match kind {
+                '0' | '1' | '5' | '9' => 2,
+                '2' | '6' | '8' => 3,
+                '3' | '7' => 4,
+                _ => return Err(error(&format!("unknown record type S{kind}")).into()),
+            };
+            if record.len() < 2 + address_len {
+                return Err(error("wrong length").into());
+            }
+
+            let address = record[1..1 + address_len]
+                .iter()
+                .fold(0, |address, byte| address << 8 | *byte as usize);
+            let data = &record[1 + address_len..record.len() - 1];
+
+            match kind {
+                '1'..='3' => {
+                    for (i, byte) in data.iter().enumerate() {
+                        bytes.insert((address + i) % MEMORY_SIZE, *byte);
+                    }
+                }
+                '7'..='9' => start = Some(linear_start(address)),
+                _ => {}
+            }
+        }
+
+        Ok(Self::from_bytes(bytes, start))
+    }
+
+    /// Start address, or the first byte of the image if the file gave none.
+    pub fn entry(&self) -> (u16, u16) {
+        self.start
+            .unwrap_or_else(|| linear_start(self.regions.first().map_or(0, |(addr, _)| *addr)))
+    }
+
+    fn from_bytes(bytes: BTreeMap<usize, u8>, start: Option<(u16, u16)>) -> Self {
+        let mut regions: Vec<(usize, Vec<u8>)> = Vec::new();
+
+        for (addr, byte) in bytes {
+            match regions.last_mut() {
+                Some((start, region)) if *start + region.len() == addr => region.push(byte),
+                _ => regions.push((addr, vec![byte])),
+            }
+        }
+
+        Self { regions, start }
+    }
+}
+
+/// Non-empty lines with their 1-based line numbers.
+fn records(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// CS:IP of a linear address, with CS on a 64 KiB boundary.
+fn linear_start(address: usize) -> (u16, u16) {
+    let address = address % MEMORY_SIZE;
+
+    (((address >> 4) & 0xF000) as u16, address as u16)
+}
@@ -1,8 +1,22 @@
 use std::error::Error;
 
-use crate::mov::{
-    AccumulatorToMemory, ImmediateToRegister, ImmediateToRegisterMemory, MemoryToAccumulator,
-    RegisterMemoryToFromRegister, RegisterMemoryToSegmentRegister, SegmentRegisterToRegisterMemory,
+use crate::{
+    arithmetic::{self, ArithmeticOp},
+    interrupt::Interrupt,
+    jump::{ConditionalJump, DirectIntersegment, DirectWithinSegment, Indirect, Return, Transfer},
+    mode::Mode,
+    mov::{
+        AccumulatorToMemory, ImmediateToRegister, ImmediateToRegisterMemory, MemoryToAccumulator,
+        RegisterMemoryToFromRegister, RegisterMemoryToSegmentRegister,
+        SegmentRegisterToRegisterMemory,
+    },
+    register::{Register, SegmentRegister},
+    shift::{Shift, ShiftOp},
+    simple::SimpleOp,
+    stack,
+    string::StringInstruction,
+    transfer::{Exchange, ExchangeAccumulator, LoadAddress, Port},
+    unary::{self, AsciiAdjust, UnaryOp},
 };
 
 #[derive(Debug)]
@@ -15,37 +29,137 @@ pub enum Instruction {
     AccumulatorToMemory(AccumulatorToMemory),
     RegisterMemoryToSegmentRegister(RegisterMemoryToSegmentRegister),
     SegmentRegisterToRegisterMemory(SegmentRegisterToRegisterMemory),
+    ArithmeticRegisterMemoryWithRegister(arithmetic::RegisterMemoryWithRegister),
+    ArithmeticImmediateToRegisterMemory(arithmetic::ImmediateToRegisterMemory),
+    ArithmeticImmediateToAccumulator(arithmetic::ImmediateToAccumulator),
+    ConditionalJump(ConditionalJump),
+    DirectWithinSegment(DirectWithinSegment),
+    DirectIntersegment(DirectIntersegment),
+    Indirect(Indirect),
+    Return(Return),
+    Halt,
+    StackRegisterMemory(stack::RegisterMemory),
+    StackRegister(stack::WordRegister),
+    StackSegmentRegister(stack::Segment),
+    Exchange(Exchange),
+    ExchangeAccumulator(ExchangeAccumulator),
+    Port(Port),
+    LoadAddress(LoadAddress),
+    UnaryRegisterMemory(unary::RegisterMemory),
+    UnaryRegister(unary::WordRegister),
+    AsciiAdjust(AsciiAdjust),
+    Shift(Shift),
+    String(StringInstruction),
+    Interrupt(Interrupt),
+    Simple(SimpleOp),
+    /// An instruction preceded by a segment override prefix.
+    SegmentOverride(SegmentRegister, Box<Instruction>),
+    /// An instruction preceded by `rep`/`repe` (`true`, ZF must stay set)
+    /// or `repne` (`false`).
+    Repeat(bool, Box<Instruction>),
+    /// An instruction preceded by `lock`.
+    Lock(Box<Instruction>),
 }
 impl Instruction {
     pub(crate) fn decode(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
-        let res = match bytes[0] >> 0 {
+        let res = match bytes[0] {
             0b10001110 => Self::RegisterMemoryToSegmentRegister(
                 RegisterMemoryToSegmentRegister::decode(bytes),
             ),
             0b10001100 => Self::SegmentRegisterToRegisterMemory(
                 SegmentRegisterToRegisterMemory::decode(bytes),
             ),
+            0b10000000..=0b10000011 => Self::ArithmeticImmediateToRegisterMemory(
+                arithmetic::ImmediateToRegisterMemory::decode(
+                    bytes,
+                    ArithmeticOp::decode((bytes[1] & 0b0011_1000) >> 3),
+                ),
+            ),
+            0x70..=0x7F | 0xE0..=0xE3 => Self::ConditionalJump(ConditionalJump::decode(bytes)),
+            0xE8 | 0xE9 | 0xEB => Self::DirectWithinSegment(DirectWithinSegment::decode(bytes)),
+            0x9A | 0xEA => Self::DirectIntersegment(DirectIntersegment::decode(bytes)),
+            0xFF if (0b010..=0b101).contains(&((bytes[1] & 0b0011_1000) >> 3)) => {
+                Self::Indirect(Indirect::decode(bytes))
+            }
+            0xC2 | 0xC3 | 0xCA | 0xCB => Self::Return(Return::decode(bytes)),
+            0xF4 => Self::Halt,
+            0x26 | 0x2E | 0x36 | 0x3E => Self::SegmentOverride(
+                SegmentRegister::decode((bytes[0] & 0b0001_1000) >> 3),
+                Box::new(Self::decode(&bytes[1..])?),
+            ),
+            0xF2 | 0xF3 => Self::Repeat(bytes[0] == 0xF3, Box::new(Self::decode(&bytes[1..])?)),
+            0xF0 => Self::Lock(Box::new(Self::decode(&bytes[1..])?)),
+            0x06 | 0x07 | 0x0E | 0x16 | 0x17 | 0x1E | 0x1F => {
+                Self::StackSegmentRegister(stack::Segment::decode(bytes))
+            }
+            0x50..=0x5F => Self::StackRegister(stack::WordRegister::decode(bytes)),
+            0x8F if (bytes[1] & 0b0011_1000) == 0 => {
+                Self::StackRegisterMemory(stack::RegisterMemory::decode(bytes))
+            }
+            0xFF if (bytes[1] & 0b0011_1000) >> 3 == 0b110 => {
+                Self::StackRegisterMemory(stack::RegisterMemory::decode(bytes))
+            }
+            0x40..=0x4F => Self::UnaryRegister(unary::WordRegister::decode(bytes)),
+            0xFE | 0xFF if (bytes[1] & 0b0011_1000) >> 3 <= 0b001 => {
+                Self::UnaryRegisterMemory(unary::RegisterMemory::decode(bytes))
+            }
+            0xF6 | 0xF7 if (bytes[1] & 0b0011_1000) >> 3 >= 0b010 => {
+                Self::UnaryRegisterMemory(unary::RegisterMemory::decode(bytes))
+            }
+            0xD4 | 0xD5 => Self::AsciiAdjust(AsciiAdjust::decode(bytes)),
+            0x86 | 0x87 => Self::Exchange(Exchange::decode(bytes)),
+            0x91..=0x97 => Self::ExchangeAccumulator(ExchangeAccumulator::decode(bytes)),
+            0xE4..=0xE7 | 0xEC..=0xEF => Self::Port(Port::decode(bytes)),
+            0x8D | 0xC4 | 0xC5 => Self::LoadAddress(LoadAddress::decode(bytes)),
+            0xD0..=0xD3 => match ShiftOp::decode((bytes[1] & 0b0011_1000) >> 3) {
+                Some(op) => Self::Shift(Shift::decode(bytes, op)),
+                None => return Err(format!("Instruction Not Implemented: {:08b}", bytes[0]).into()),
+            },
+            0xA4..=0xA7 | 0xAA..=0xAF => Self::String(StringInstruction::decode(bytes)),
+            0xCD => Self::Interrupt(Interrupt::decode(bytes)),
+            b if SimpleOp::decode(b).is_some() => Self::Simple(SimpleOp::decode(b).unwrap()),
+            b if b & 0b1100_0100 == 0b0000_0000 => Self::ArithmeticRegisterMemoryWithRegister(
+                arithmetic::RegisterMemoryWithRegister::decode(
+                    bytes,
+                    ArithmeticOp::decode((b & 0b0011_1000) >> 3),
+                ),
+            ),
+            b if b & 0b1100_0110 == 0b0000_0100 => {
+                Self::ArithmeticImmediateToAccumulator(arithmetic::ImmediateToAccumulator::decode(
+                    bytes,
+                    ArithmeticOp::decode((b & 0b0011_1000) >> 3),
+                ))
+            }
             _ => match bytes[0] >> 1 {
                 0b1100011 => {
                     Self::ImmediateToRegisterMemory(ImmediateToRegisterMemory::decode(bytes))
                 }
                 0b1010000 => Self::MemoryToAccumulator(MemoryToAccumulator::decode(bytes)),
                 0b1010001 => Self::AccumulatorToMemory(AccumulatorToMemory::decode(bytes)),
+                0b1000010 => Self::ArithmeticRegisterMemoryWithRegister(
+                    arithmetic::RegisterMemoryWithRegister::decode(bytes, ArithmeticOp::Test),
+                ),
+                0b1010100 => Self::ArithmeticImmediateToAccumulator(
+                    arithmetic::ImmediateToAccumulator::decode(bytes, ArithmeticOp::Test),
+                ),
+                0b1111011 if (bytes[1] & 0b0011_1000) == 0 => {
+                    Self::ArithmeticImmediateToRegisterMemory(
+                        arithmetic::ImmediateToRegisterMemory::decode(bytes, ArithmeticOp::Test),
+                    )
+                }
 
                 _ => match bytes[0] >> 2 {
                     0b100010 => Self::RegisterMemoryToFromRegister(
                         RegisterMemoryToFromRegister::decode(bytes),
                     ),
-                    _ => match bytes[0] >> 3 {
-                        _ => match bytes[0] >> 4 {
-                            0b1011 => Self::ImmediateToRegister(ImmediateToRegister::decode(bytes)),
+                    _ => match bytes[0] >> 4 {
+                        0b1011 => Self::ImmediateToRegister(ImmediateToRegister::decode(bytes)),
 
-                            _ => {
-                                eprintln!("Instruction Not Implemented: {:08b}", bytes[0]);
-
-                                unimplemented!()
-                            }
-                        },
+                        _ => {
+                            return Err(
+                                format!("Instruction Not Implemented: {:08b}", bytes[0]).into()
+                            )
+                        }
                     },
                 },
             },
@@ -54,6 +168,180 @@ impl Instruction {
         Ok(res)
     }
 
+    /// Target of a branch encoded relative to IP, for the instruction
+    /// starting at `offset` in the bytes decoded. Offsets aren't wrapped to
+    /// 16 bits, so targets before the start of the bytes have none.
+    pub(crate) fn relative_target(&self, offset: usize) -> Option<usize> {
+        let next = offset + self.offset();
+
+        match self {
+            Instruction::ConditionalJump(i) => next.checked_add_signed(i.ip_inc8 as isize),
+            Instruction::DirectWithinSegment(i) => next.checked_add_signed(i.ip_inc as isize),
+            Instruction::SegmentOverride(_, i)
+            | Instruction::Repeat(_, i)
+            | Instruction::Lock(i) => i.relative_target(offset + 1),
+            _ => None,
+        }
+    }
+
+    /// Whether execution can continue anywhere other than the next
+    /// instruction.
+    pub(crate) fn is_branch(&self) -> bool {
+        match self {
+            Instruction::ConditionalJump(_)
+            | Instruction::DirectWithinSegment(_)
+            | Instruction::DirectIntersegment(_)
+            | Instruction::Indirect(_)
+            | Instruction::Return(_)
+            | Instruction::Halt
+            | Instruction::Interrupt(_)
+            | Instruction::Simple(SimpleOp::Int3 | SimpleOp::Into | SimpleOp::Iret) => true,
+            Instruction::SegmentOverride(_, i)
+            | Instruction::Repeat(_, i)
+            | Instruction::Lock(i) => i.is_branch(),
+            _ => false,
+        }
+    }
+
+    /// Whether this is a `call` of any kind.
+    pub(crate) fn is_call(&self) -> bool {
+        match self {
+            Instruction::DirectWithinSegment(i) => i.transfer == Transfer::Call,
+            Instruction::DirectIntersegment(i) => i.transfer == Transfer::Call,
+            Instruction::Indirect(i) => i.transfer == Transfer::Call,
+            Instruction::SegmentOverride(_, i)
+            | Instruction::Repeat(_, i)
+            | Instruction::Lock(i) => i.is_call(),
+            _ => false,
+        }
+    }
+
+    /// Whether this is an interrupt that continues with the next instruction
+    /// once its handler returns: `int` and `int3`.
+    pub(crate) fn returns_after_interrupt(&self) -> bool {
+        match self {
+            Instruction::Interrupt(_) | Instruction::Simple(SimpleOp::Int3) => true,
+            Instruction::SegmentOverride(_, i)
+            | Instruction::Repeat(_, i)
+            | Instruction::Lock(i) => i.returns_after_interrupt(),
+            _ => false,
+        }
+    }
+
+    /// Whether execution can go on to the next instruction: anything but an
+    /// unconditional jump, a return, `iret` or `hlt`.
+    pub(crate) fn falls_through(&self) -> bool {
+        !self.is_branch()
+            || self.is_call()
+            || self.is_conditional()
+            || self.returns_after_interrupt()
+    }
+
+    /// Whether this is a `ret` or `retf`.
+    pub(crate) fn is_return(&self) -> bool {
+        match self {
+            Instruction::Return(_) => true,
+            Instruction::SegmentOverride(_, i)
+            | Instruction::Repeat(_, i)
+            | Instruction::Lock(i) => i.is_return(),
+            _ => false,
+        }
+    }
+
+    /// Whether this is a branch that may also fall through to the next
+    /// instruction.
+    pub(crate) fn is_conditional(&self) -> bool {
+        match self {
+            Instruction::ConditionalJump(_) | Instruction::Simple(SimpleOp::Into) => true,
+            Instruction::SegmentOverride(_, i)
+            | Instruction::Repeat(_, i)
+            | Instruction::Lock(i) => i.is_conditional(),
+            _ => false,
+        }
+    }
+
+    /// The mnemonic, without any prefix.
+    pub(crate) fn mnemonic(&self) -> String {
+        self.unprefixed()
+            .to_string()
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// Addressing mode of the ModR/M operand, with the memory forms of `mov`
+    /// to and from the accumulator as direct addresses. `None` for
+    /// instructions with neither.
+    pub(crate) fn mode(&self) -> Option<Mode> {
+        match self {
+            Instruction::RegisterMemoryToFromRegister(i) => Some(i.mode),
+            Instruction::ImmediateToRegisterMemory(i) => Some(i.mode),
+            Instruction::MemoryToAccumulator(_) | Instruction::AccumulatorToMemory(_) => {
+                Some(Mode::DirectAddress)
+            }
+            Instruction::RegisterMemoryToSegmentRegister(i) => Some(i.mode),
+            Instruction::SegmentRegisterToRegisterMemory(i) => Some(i.mode),
+            Instruction::ArithmeticRegisterMemoryWithRegister(i) => Some(i.mode),
+            Instruction::ArithmeticImmediateToRegisterMemory(i) => Some(i.mode),
+            Instruction::Indirect(i) => Some(i.mode),
+            Instruction::StackRegisterMemory(i) => Some(i.mode),
+            Instruction::Exchange(i) => Some(i.mode),
+            Instruction::LoadAddress(i) => Some(i.mode),
+            Instruction::UnaryRegisterMemory(i) => Some(i.mode),
+            Instruction::Shift(i) => Some(i.mode),
+            Instruction::SegmentOverride(_, i)
+            | Instruction::Repeat(_, i)
+            | Instruction::Lock(i) => i.mode(),
+            _ => None,
+        }
+    }
+
+    /// The prefixes as NASM spells them (`es`, `rep`, `lock`, ...), outermost
+    /// first.
+    pub(crate) fn prefixes(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        let mut instruction = self;
+
+        loop {
+            let prefix = match instruction {
+                Instruction::SegmentOverride(sr, _) => sr.register_mode_to_string(),
+                Instruction::Repeat(z, i) => repeat_prefix(*z, i).to_string(),
+                Instruction::Lock(_) => "lock".to_string(),
+                _ => return res,
+            };
+            res.push(prefix);
+            instruction = instruction.unprefixed_once();
+        }
+    }
+
+    /// The instruction with every prefix taken off.
+    pub(crate) fn unprefixed(&self) -> &Instruction {
+        match self {
+            Instruction::SegmentOverride(..) | Instruction::Repeat(..) | Instruction::Lock(_) => {
+                self.unprefixed_once().unprefixed()
+            }
+            _ => self,
+        }
+    }
+
+    fn unprefixed_once(&self) -> &Instruction {
+        match self {
+            Instruction::SegmentOverride(_, i)
+            | Instruction::Repeat(_, i)
+            | Instruction::Lock(i) => i,
+            _ => self,
+        }
+    }
+
+    /// Whether a segment override, repeat or lock prefix comes first.
+    pub(crate) fn is_prefixed(&self) -> bool {
+        matches!(
+            self,
+            Instruction::SegmentOverride(..) | Instruction::Repeat(..) | Instruction::Lock(_)
+        )
+    }
+
     pub(crate) fn offset(&self) -> usize {
         match self {
             Instruction::RegisterMemoryToFromRegister(i) => i.offset(),
@@ -63,6 +351,32 @@ impl Instruction {
             Instruction::AccumulatorToMemory(i) => i.offset(),
             Instruction::RegisterMemoryToSegmentRegister(i) => i.offset(),
             Instruction::SegmentRegisterToRegisterMemory(i) => i.offset(),
+            Instruction::ArithmeticRegisterMemoryWithRegister(i) => i.offset(),
+            Instruction::ArithmeticImmediateToRegisterMemory(i) => i.offset(),
+            Instruction::ArithmeticImmediateToAccumulator(i) => i.offset(),
+            Instruction::ConditionalJump(i) => i.offset(),
+            Instruction::DirectWithinSegment(i) => i.offset(),
+            Instruction::DirectIntersegment(i) => i.offset(),
+            Instruction::Indirect(i) => i.offset(),
+            Instruction::Return(i) => i.offset(),
+            Instruction::Halt => 1,
+            Instruction::StackRegisterMemory(i) => i.offset(),
+            Instruction::StackRegister(i) => i.offset(),
+            Instruction::StackSegmentRegister(i) => i.offset(),
+            Instruction::Exchange(i) => i.offset(),
+            Instruction::ExchangeAccumulator(i) => i.offset(),
+            Instruction::Port(i) => i.offset(),
+            Instruction::LoadAddress(i) => i.offset(),
+            Instruction::UnaryRegisterMemory(i) => i.offset(),
+            Instruction::UnaryRegister(i) => i.offset(),
+            Instruction::AsciiAdjust(i) => i.offset(),
+            Instruction::Shift(i) => i.offset(),
+            Instruction::String(i) => i.offset(),
+            Instruction::Interrupt(i) => i.offset(),
+            Instruction::Simple(_) => 1,
+            Instruction::SegmentOverride(_, i)
+            | Instruction::Repeat(_, i)
+            | Instruction::Lock(i) => 1 + i.offset(),
         }
     }
 
@@ -75,6 +389,200 @@ impl Instruction {
             Instruction::AccumulatorToMemory(i) => i.to_string(),
             Instruction::RegisterMemoryToSegmentRegister(i) => i.to_string(),
             Instruction::SegmentRegisterToRegisterMemory(i) => i.to_string(),
+            Instruction::ArithmeticRegisterMemoryWithRegister(i) => i.to_string(),
+            Instruction::ArithmeticImmediateToRegisterMemory(i) => i.to_string(),
+            Instruction::ArithmeticImmediateToAccumulator(i) => i.to_string(),
+            Instruction::ConditionalJump(i) => i.to_string(),
+            Instruction::DirectWithinSegment(i) => i.to_string(),
+            Instruction::DirectIntersegment(i) => i.to_string(),
+            Instruction::Indirect(i) => i.to_string(),
+            Instruction::Return(i) => i.to_string(),
+            Instruction::Halt => "hlt\r\n".to_string(),
+            Instruction::StackRegisterMemory(i) => i.to_string(),
+            Instruction::StackRegister(i) => i.to_string(),
+            Instruction::StackSegmentRegister(i) => i.to_string(),
+            Instruction::Exchange(i) => i.to_string(),
+            Instruction::ExchangeAccumulator(i) => i.to_string(),
+            Instruction::Port(i) => i.to_string(),
+            Instruction::LoadAddress(i) => i.to_string(),
+            Instruction::UnaryRegisterMemory(i) => i.to_string(),
+            Instruction::UnaryRegister(i) => i.to_string(),
+            Instruction::AsciiAdjust(i) => i.to_string(),
+            Instruction::Shift(i) => i.to_string(),
+            Instruction::String(i) => i.to_string(),
+            Instruction::Interrupt(i) => i.to_string(),
+            Instruction::Simple(op) => format!("{}\r\n", op.mnemonic()),
+            Instruction::SegmentOverride(sr, i) => {
+                let res = i.to_string();
+
+                // Without a memory operand (string instructions, `xlatb`) the
+                // override is written as a prefix of its own.
+                if res.contains('[') {
+                    let prefix = format!("[{}:", sr.register_mode_to_string());
+                    res.replacen('[', &prefix, 1)
+                } else {
+                    format!("{} {}", sr.register_mode_to_string(), res)
+                }
+            }
+            Instruction::Repeat(z, i) => format!("{} {}", repeat_prefix(*z, i), i.to_string()),
+            Instruction::Lock(i) => format!("lock {}", i.to_string()),
+        }
+    }
+
+    /// The instruction as sim86 prints it: the segment override goes in
+    /// front of the brackets (`es:[bx]`) and zero displacements are left out
+    /// (`[bp]` rather than `[bp + 0]`).
+    pub(crate) fn to_sim86_string(&self) -> String {
+        let res = match self {
+            Instruction::SegmentOverride(sr, i) if i.to_string().contains('[') => {
+                let prefix = format!("{}:[", sr.register_mode_to_string());
+                i.to_string().replacen('[', &prefix, 1)
+            }
+            _ => self.to_string(),
+        };
+
+        res.replace(" + 0]", "]")
+    }
+
+    /// Same as [`Instruction::to_string`], but with the word `offset` bytes
+    /// into the instruction, a segment the loader relocates, shown as a `seg`
+    /// reference relative to the image, e.g. `mov ax, seg 0x0012` or
+    /// `call seg 0x0001:16`.
+    pub(crate) fn to_segment_string(&self, offset: usize) -> String {
+        let reference = |segment: u16| format!("seg {segment:#06x}");
+        let immediate = offset + 2 == self.offset();
+
+        let data = match self {
+            Instruction::DirectIntersegment(i) if offset == 3 => {
+                return format!("{} {}:{}\r\n", i.transfer.mnemonic(), reference(i.cs), i.ip);
+            }
+            Instruction::ImmediateToRegister(i) if immediate && i.w => i.data,
+            Instruction::ImmediateToRegisterMemory(i) if immediate && i.w => i.data,
+            Instruction::ArithmeticImmediateToRegisterMemory(i) if immediate && i.w && !i.s => {
+                i.data
+            }
+            Instruction::ArithmeticImmediateToAccumulator(i) if immediate && i.w => i.data,
+            _ => return self.to_string(),
+        };
+
+        // The immediate is always the last operand.
+        let text = self.to_string();
+        match text.trim_end().rsplit_once(", ") {
+            Some((head, _)) => format!("{head}, {}\r\n", reference(data)),
+            None => text,
+        }
+    }
+
+    /// Whether NASM assembles the disassembly back to `bytes`, the encoding
+    /// this was decoded from. It doesn't when the decoder ignored bits, when
+    /// NASM picks a shorter or otherwise different form of the same
+    /// instruction, or when the prefixes aren't one repeat or lock prefix
+    /// followed by one segment override, the order NASM emits them in.
+    pub(crate) fn nasm_encodes(&self, bytes: &[u8]) -> bool {
+        let mut instruction = self;
+        let mut bytes = bytes;
+        let mut last_prefix = None;
+        loop {
+            let prefix = match instruction {
+                Instruction::Repeat(..) | Instruction::Lock(_) => 0,
+                Instruction::SegmentOverride(..) => 1,
+                _ => break,
+            };
+            if last_prefix.is_some_and(|last| last >= prefix) {
+                return false;
+            }
+            last_prefix = Some(prefix);
+            instruction = instruction.unprefixed_once();
+            bytes = &bytes[1..];
         }
+
+        // `$` is where the prefixes start, but targets are shown relative to
+        // the instruction after them.
+        if last_prefix.is_some() && instruction.relative_target(0).is_some() {
+            return false;
+        }
+
+        let modrm = bytes.get(1).copied().unwrap_or(0);
+        let fits_byte = |data: u16| (-128..=127).contains(&(data as i16));
+        let accumulator = |mode: Mode, rm: Register| {
+            mode == Mode::Reg && matches!(rm, Register::AL | Register::AX)
+        };
+        // NASM leaves out zero displacements, except for `[bp]`, which has
+        // none of its own, and uses a byte for those that fit.
+        let address =
+            |mode: Mode, rm: Register, disp_lo: Option<u8>, disp_hi: Option<u8>| match mode {
+                Mode::Mem8 => disp_lo != Some(0) || matches!(rm, Register::DH | Register::SI),
+                Mode::Mem16 => !fits_byte(u16::from_le_bytes([
+                    disp_lo.unwrap_or(0),
+                    disp_hi.unwrap_or(0),
+                ])),
+                _ => true,
+            };
+
+        match instruction {
+            Instruction::RegisterMemoryToFromRegister(i) => {
+                address(i.mode, i.rm, i.disp_lo, i.disp_hi)
+                    && !(i.mode == Mode::Reg && i.d)
+                    && !(i.mode == Mode::DirectAddress
+                        && matches!(i.reg, Register::AL | Register::AX))
+            }
+            Instruction::ImmediateToRegisterMemory(i) => {
+                address(i.mode, i.rm, i.disp_lo, i.disp_hi)
+                    && i.mode != Mode::Reg
+                    && modrm & 0b0011_1000 == 0
+            }
+            Instruction::RegisterMemoryToSegmentRegister(i) => {
+                address(i.mode, i.rm, i.disp_lo, i.disp_hi) && modrm & 0b0010_0000 == 0
+            }
+            Instruction::SegmentRegisterToRegisterMemory(i) => {
+                address(i.mode, i.rm, i.disp_lo, i.disp_hi) && modrm & 0b0010_0000 == 0
+            }
+            Instruction::ArithmeticRegisterMemoryWithRegister(i) => {
+                address(i.mode, i.rm, i.disp_lo, i.disp_hi) && !(i.mode == Mode::Reg && i.d)
+            }
+            Instruction::ArithmeticImmediateToRegisterMemory(i) => {
+                address(i.mode, i.rm, i.disp_lo, i.disp_hi)
+                    && match (i.op, bytes[0]) {
+                        (ArithmeticOp::Test, _) | (_, 0x80) => !accumulator(i.mode, i.rm),
+                        (_, 0x81) => !fits_byte(i.data) && !accumulator(i.mode, i.rm),
+                        (_, 0x83) => true,
+                        _ => false,
+                    }
+            }
+            Instruction::ArithmeticImmediateToAccumulator(i) => {
+                !(i.w && i.op != ArithmeticOp::Test && fits_byte(i.data))
+            }
+            Instruction::Indirect(i) => {
+                address(i.mode, i.rm, i.disp_lo, i.disp_hi) && !(i.far && i.mode == Mode::Reg)
+            }
+            Instruction::StackRegisterMemory(i) => {
+                address(i.mode, i.rm, i.disp_lo, i.disp_hi) && i.mode != Mode::Reg
+            }
+            Instruction::Exchange(i) => {
+                address(i.mode, i.rm, i.disp_lo, i.disp_hi)
+                    && !(i.mode == Mode::Reg
+                        && i.w
+                        && (i.reg == Register::AX || i.rm == Register::AX))
+            }
+            Instruction::LoadAddress(i) => {
+                address(i.mode, i.rm, i.disp_lo, i.disp_hi) && i.mode != Mode::Reg
+            }
+            Instruction::UnaryRegisterMemory(i) => {
+                address(i.mode, i.rm, i.disp_lo, i.disp_hi)
+                    && !(i.mode == Mode::Reg && i.w && matches!(i.op, UnaryOp::Inc | UnaryOp::Dec))
+            }
+            Instruction::Shift(i) => address(i.mode, i.rm, i.disp_lo, i.disp_hi),
+            _ => true,
+        }
+    }
+}
+
+/// `repne` without ZF, `repe` for the string instructions that compare,
+/// `rep` otherwise.
+fn repeat_prefix(z: bool, instruction: &Instruction) -> &'static str {
+    match (z, instruction) {
+        (false, _) => "repne",
+        (true, Instruction::String(s)) if s.op.compares() => "repe",
+        (true, _) => "rep",
     }
 }
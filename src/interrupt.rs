@@ -0,0 +1,23 @@
+/// `int` with an explicit vector (`11001101`). `int3` and `into` have their
+/// own one-byte encodings and are decoded as [`crate::simple::SimpleOp`]s.
+#[derive(Debug)]
+pub struct Interrupt {
+    pub(crate) vector: u8,
+}
+
+impl Interrupt {
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        Self { vector: bytes[1] }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        2
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        res.push_str(&format!("int {}\r\n", self.vector));
+        res
+    }
+}
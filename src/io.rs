@@ -0,0 +1,147 @@
+use std::{cell::RefCell, error::Error, fmt, ops::RangeInclusive, rc::Rc};
+
+/// Something at the other end of `in` and `out`. Word accesses default to
+/// two byte accesses, low byte first at `port`, as the 8088 performs them.
+pub trait IoDevice {
+    fn read_byte(&mut self, port: u16) -> u8;
+
+    fn write_byte(&mut self, port: u16, value: u8);
+
+    fn read_word(&mut self, port: u16) -> u16 {
+        let lo = self.read_byte(port);
+        let hi = self.read_byte(port.wrapping_add(1));
+
+        u16::from_le_bytes([lo, hi])
+    }
+
+    fn write_word(&mut self, port: u16, value: u16) {
+        let [lo, hi] = value.to_le_bytes();
+
+        self.write_byte(port, lo);
+        self.write_byte(port.wrapping_add(1), hi);
+    }
+}
+
+/// A device attached to a bus. Shared, so the host keeps a handle to the
+/// device it attached, and copies of the CPU talk to the same hardware.
+pub type SharedDevice = Rc<RefCell<dyn IoDevice>>;
+
+/// An access to a port no device claims.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortAccess {
+    pub port: u16,
+    pub w: bool,
+    /// Value written, or `None` for a read.
+    pub written: Option<u16>,
+}
+
+impl PortAccess {
+    pub fn to_string(&self) -> String {
+        let size = if self.w { "word" } else { "byte" };
+
+        match self.written {
+            Some(value) => format!("unclaimed port {:#06x}: {size} write {value:#x}", self.port),
+            None => format!("unclaimed port {:#06x}: {size} read", self.port),
+        }
+    }
+}
+
+/// The I/O address space, routing each port to the device attached to the
+/// range it falls in. Reads of unclaimed ports see an idle bus, all ones,
+/// and writes to them are dropped; both are logged.
+#[derive(Clone, Default)]
+pub struct IoBus {
+    devices: Vec<(RangeInclusive<u16>, SharedDevice)>,
+    unclaimed: Vec<PortAccess>,
+}
+
+impl fmt::Debug for IoBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ranges: Vec<_> = self.devices.iter().map(|(ports, _)| ports).collect();
+
+        f.debug_struct("IoBus")
+            .field("devices", &ranges)
+            .field("unclaimed", &self.unclaimed)
+            .finish()
+    }
+}
+
+impl IoBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Routes `ports` to `device`. Ranges of different devices can't overlap.
+    pub fn attach(
+        &mut self,
+        ports: RangeInclusive<u16>,
+        device: SharedDevice,
+    ) -> Result<(), Box<dyn Error>> {
+        let overlapping = self
+            .devices
+            .iter()
+            .find(|(other, _)| ports.start() <= other.end() && other.start() <= ports.end());
+        if let Some((other, _)) = overlapping {
+            return Err(format!(
+                "Ports {:#06x}-{:#06x} overlap {:#06x}-{:#06x}, which are already attached",
+                ports.start(),
+                ports.end(),
+                other.start(),
+                other.end()
+            )
+            .into());
+        }
+
+        self.devices.push((ports, device));
+        Ok(())
+    }
+
+    pub fn read(&mut self, port: u16, w: bool) -> u16 {
+        match self.device(port) {
+            Some(device) if w => device.borrow_mut().read_word(port),
+            Some(device) => device.borrow_mut().read_byte(port) as u16,
+            None => {
+                self.unclaimed.push(PortAccess {
+                    port,
+                    w,
+                    written: None,
+                });
+
+                if w {
+                    0xFFFF
+                } else {
+                    0x00FF
+                }
+            }
+        }
+    }
+
+    pub fn write(&mut self, port: u16, w: bool, value: u16) {
+        match self.device(port) {
+            Some(device) if w => device.borrow_mut().write_word(port, value),
+            Some(device) => device.borrow_mut().write_byte(port, value as u8),
+            None => self.unclaimed.push(PortAccess {
+                port,
+                w,
+                written: Some(value),
+            }),
+        }
+    }
+
+    /// Unclaimed accesses since the last call, oldest first.
+    pub fn take_unclaimed(&mut self) -> Vec<PortAccess> {
+        std::mem::take(&mut self.unclaimed)
+    }
+
+    /// Every attached device, in the order attached.
+    pub(crate) fn devices(&self) -> impl Iterator<Item = &SharedDevice> {
+        self.devices.iter().map(|(_, device)| device)
+    }
+
+    fn device(&self, port: u16) -> Option<SharedDevice> {
+        self.devices
+            .iter()
+            .find(|(ports, _)| ports.contains(&port))
+            .map(|(_, device)| device.clone())
+    }
+}
@@ -0,0 +1,416 @@
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cpu::MAX_INSTRUCTION_LEN,
+    instruction::Instruction,
+    mode::Mode,
+    register::{Register, SegmentRegister},
+    transfer::LoadOp,
+};
+
+/// Version of the JSON schema, bumped whenever a field changes meaning or
+/// goes away. New optional fields don't bump it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Decoded instructions as JSON, for tools that want structured data rather
+/// than NASM text:
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "instructions": [
+///     {
+///       "address": 0,
+///       "bytes": [38, 137, 70, 2],
+///       "length": 4,
+///       "mnemonic": "mov",
+///       "prefixes": ["es"],
+///       "operands": [
+///         { "kind": "memory", "size": 16, "segment": "es", "base": "bp",
+///           "index": null, "displacement": 2 },
+///         { "kind": "register", "size": 16, "register": "ax" }
+///       ],
+///       "text": "mov [es:bp + 2], ax"
+///     }
+///   ]
+/// }
+/// ```
+///
+/// `address` is the offset in the bytes decoded, or the physical address
+/// for images. Operands come in NASM order, destination first, and have a
+/// `kind`:
+///
+/// - `register`: `register` by name, `size` 8 or 16.
+/// - `memory`: `segment` from an override prefix, `base` (`bx`, `bp`),
+///   `index` (`si`, `di`) and a signed `displacement`, each `null` if
+///   absent; `[bp + 0]` has a displacement of 0. `size` is 8, 16, 32 for
+///   far pointers, or `null` where nothing is accessed (`lea`).
+/// - `immediate`: signed `immediate`, as NASM shows it, and the `size` of
+///   the operation.
+/// - `relative`: `displacement` from the start of the instruction, its
+///   prefixes included, and the `target` address, `null` if it would be
+///   before address 0.
+/// - `far`: a `segment:offset` pointer, for direct intersegment transfers.
+///
+/// Sizes are in bits. Operands implied by the mnemonic, like `si` and `di`
+/// of `movsb`, aren't listed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Listing {
+    pub version: u32,
+    pub instructions: Vec<DecodedInstruction>,
+}
+
+impl Default for Listing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Listing {
+    pub fn new() -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            instructions: Vec::new(),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Parses a listing, refusing other schema versions.
+    pub fn from_json(text: &str) -> Result<Self, Box<dyn Error>> {
+        let res: Self = serde_json::from_str(text)?;
+        if res.version != SCHEMA_VERSION {
+            return Err(format!(
+                "Unsupported schema version {} (expected {SCHEMA_VERSION})",
+                res.version
+            )
+            .into());
+        }
+
+        Ok(res)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecodedInstruction {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+    pub length: usize,
+    /// Mnemonic without its prefixes.
+    pub mnemonic: String,
+    /// Segment override, repeat and lock prefixes, outermost first.
+    pub prefixes: Vec<String>,
+    pub operands: Vec<Operand>,
+    /// The instruction as the disassembler writes it.
+    pub text: String,
+}
+
+impl DecodedInstruction {
+    /// `instruction`, decoded from `bytes` at `address`.
+    pub(crate) fn new(address: usize, bytes: &[u8], instruction: &Instruction) -> Self {
+        Self {
+            address,
+            bytes: bytes.to_vec(),
+            length: bytes.len(),
+            mnemonic: instruction.mnemonic(),
+            prefixes: instruction.prefixes(),
+            operands: Operand::decode(instruction, address),
+            text: instruction.to_string().trim_end().to_string(),
+        }
+    }
+
+    /// Decodes the bytes back into an instruction, checking that the other
+    /// fields describe it.
+    pub fn instruction(&self) -> Result<Instruction, Box<dyn Error>> {
+        let padded = [&self.bytes[..], &[0; MAX_INSTRUCTION_LEN]].concat();
+        let res = Instruction::decode(&padded)?;
+
+        if res.offset() != self.bytes.len() || Self::new(self.address, &self.bytes, &res) != *self {
+            return Err(format!(
+                "Instruction at {:#x} doesn't match its bytes {:02X?}",
+                self.address, self.bytes
+            )
+            .into());
+        }
+
+        Ok(res)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Operand {
+    Register {
+        size: u8,
+        register: String,
+    },
+    Memory {
+        size: Option<u8>,
+        segment: Option<String>,
+        base: Option<String>,
+        index: Option<String>,
+        displacement: Option<i32>,
+    },
+    Immediate {
+        size: Option<u8>,
+        immediate: i32,
+    },
+    Relative {
+        displacement: i32,
+        target: Option<usize>,
+    },
+    Far {
+        segment: u16,
+        offset: u16,
+    },
+}
+
+impl Operand {
+    /// The operands of `instruction`, decoded at `address`, from its fields.
+    fn decode(instruction: &Instruction, address: usize) -> Vec<Self> {
+        let segment = segment_override(instruction).map(|sr| sr.register_mode_to_string());
+        let memory = |mode, rm, disp_lo, disp_hi, size| {
+            Self::memory(mode, rm, disp_lo, disp_hi, size, segment.clone())
+        };
+        let direct = |addr_lo: u8, addr_hi: u8, w: bool| Operand::Memory {
+            size: Some(width(w)),
+            segment: segment.clone(),
+            base: None,
+            index: None,
+            displacement: Some(u16::from_le_bytes([addr_lo, addr_hi]) as i32),
+        };
+        let relative = |ip_inc: i16| {
+            let displacement = instruction.offset() as i32 + ip_inc as i32;
+            Operand::Relative {
+                displacement,
+                target: address.checked_add_signed(displacement as isize),
+            }
+        };
+        let accumulator = |w| register(if w { Register::AX } else { Register::AL });
+
+        match instruction.unprefixed() {
+            Instruction::RegisterMemoryToFromRegister(i) => {
+                let rm = memory(i.mode, i.rm, i.disp_lo, i.disp_hi, Some(width(i.w)));
+                let reg = register(i.reg);
+                if i.d {
+                    vec![reg, rm]
+                } else {
+                    vec![rm, reg]
+                }
+            }
+            Instruction::ImmediateToRegisterMemory(i) => vec![
+                memory(i.mode, i.rm, i.disp_lo, i.disp_hi, Some(width(i.w))),
+                immediate(i.data as i32, width(i.w)),
+            ],
+            Instruction::ImmediateToRegister(i) => {
+                vec![register(i.reg), immediate(i.data as i32, width(i.w))]
+            }
+            Instruction::MemoryToAccumulator(i) => {
+                vec![accumulator(i.w), direct(i.addr_lo, i.addr_hi, i.w)]
+            }
+            Instruction::AccumulatorToMemory(i) => {
+                vec![direct(i.addr_lo, i.addr_hi, i.w), accumulator(i.w)]
+            }
+            Instruction::RegisterMemoryToSegmentRegister(i) => vec![
+                segment_register(i.sr),
+                memory(i.mode, i.rm, i.disp_lo, i.disp_hi, Some(16)),
+            ],
+            Instruction::SegmentRegisterToRegisterMemory(i) => vec![
+                memory(i.mode, i.rm, i.disp_lo, i.disp_hi, Some(16)),
+                segment_register(i.sr),
+            ],
+            Instruction::ArithmeticRegisterMemoryWithRegister(i) => {
+                let rm = memory(i.mode, i.rm, i.disp_lo, i.disp_hi, Some(width(i.w)));
+                let reg = register(i.reg);
+                if i.d {
+                    vec![reg, rm]
+                } else {
+                    vec![rm, reg]
+                }
+            }
+            Instruction::ArithmeticImmediateToRegisterMemory(i) => {
+                // As the disassembly shows it: sign-extended bytes as signed.
+                let data = if i.s {
+                    i.data as i16 as i32
+                } else if i.w {
+                    i.data as i32
+                } else {
+                    i.data as u8 as i32
+                };
+                vec![
+                    memory(i.mode, i.rm, i.disp_lo, i.disp_hi, Some(width(i.w))),
+                    immediate(data, width(i.w)),
+                ]
+            }
+            Instruction::ArithmeticImmediateToAccumulator(i) => {
+                vec![accumulator(i.w), immediate(i.data as i32, width(i.w))]
+            }
+            Instruction::ConditionalJump(i) => vec![relative(i.ip_inc8 as i16)],
+            Instruction::DirectWithinSegment(i) => vec![relative(i.ip_inc)],
+            Instruction::DirectIntersegment(i) => vec![Operand::Far {
+                segment: i.cs,
+                offset: i.ip,
+            }],
+            Instruction::Indirect(i) => {
+                let size = if i.far { 32 } else { 16 };
+                vec![memory(i.mode, i.rm, i.disp_lo, i.disp_hi, Some(size))]
+            }
+            Instruction::Return(i) => i
+                .data
+                .map(|data| immediate(data as i32, 16))
+                .into_iter()
+                .collect(),
+            Instruction::StackRegisterMemory(i) => {
+                vec![memory(i.mode, i.rm, i.disp_lo, i.disp_hi, Some(16))]
+            }
+            Instruction::StackRegister(i) => vec![register(i.reg)],
+            Instruction::StackSegmentRegister(i) => vec![segment_register(i.sr)],
+            Instruction::Exchange(i) => vec![
+                register(i.reg),
+                memory(i.mode, i.rm, i.disp_lo, i.disp_hi, Some(width(i.w))),
+            ],
+            Instruction::ExchangeAccumulator(i) => vec![register(Register::AX), register(i.reg)],
+            Instruction::Port(i) => {
+                let port = match i.port {
+                    Some(port) => immediate(port as i32, 8),
+                    None => register(Register::DX),
+                };
+                if i.out {
+                    vec![port, accumulator(i.w)]
+                } else {
+                    vec![accumulator(i.w), port]
+                }
+            }
+            Instruction::LoadAddress(i) => {
+                let size = match i.op {
+                    LoadOp::Lea => None,
+                    LoadOp::Lds | LoadOp::Les => Some(32),
+                };
+                vec![
+                    register(i.reg),
+                    memory(i.mode, i.rm, i.disp_lo, i.disp_hi, size),
+                ]
+            }
+            Instruction::UnaryRegisterMemory(i) => {
+                vec![memory(i.mode, i.rm, i.disp_lo, i.disp_hi, Some(width(i.w)))]
+            }
+            Instruction::UnaryRegister(i) => vec![register(i.reg)],
+            Instruction::AsciiAdjust(i) if i.base != 10 => vec![immediate(i.base as i32, 8)],
+            Instruction::Shift(i) => vec![
+                memory(i.mode, i.rm, i.disp_lo, i.disp_hi, Some(width(i.w))),
+                if i.v {
+                    register(Register::CL)
+                } else {
+                    immediate(1, 8)
+                },
+            ],
+            Instruction::Interrupt(i) => vec![immediate(i.vector as i32, 8)],
+            _ => Vec::new(),
+        }
+    }
+
+    /// The ModR/M operand: a register in register mode, otherwise memory
+    /// accessed with `size`.
+    fn memory(
+        mode: Mode,
+        rm: Register,
+        disp_lo: Option<u8>,
+        disp_hi: Option<u8>,
+        size: Option<u8>,
+        segment: Option<String>,
+    ) -> Self {
+        let name = |register: &str| Some(register.to_string());
+        let (base, index) = match mode {
+            Mode::Reg => return register(rm),
+            Mode::DirectAddress => (None, None),
+            _ => match rm {
+                Register::AL | Register::AX => (name("bx"), name("si")),
+                Register::CL | Register::CX => (name("bx"), name("di")),
+                Register::DL | Register::DX => (name("bp"), name("si")),
+                Register::BL | Register::BX => (name("bp"), name("di")),
+                Register::AH | Register::SP => (None, name("si")),
+                Register::CH | Register::BP => (None, name("di")),
+                Register::DH | Register::SI => (name("bp"), None),
+                Register::BH | Register::DI => (name("bx"), None),
+            },
+        };
+        let displacement = match mode {
+            Mode::Mem8 => disp_lo.map(|lo| lo as i8 as i32),
+            Mode::Mem16 => Some(i16::from_le_bytes([disp_lo.unwrap(), disp_hi.unwrap()]) as i32),
+            Mode::DirectAddress => {
+                Some(u16::from_le_bytes([disp_lo.unwrap(), disp_hi.unwrap()]) as i32)
+            }
+            Mode::Mem | Mode::Reg => None,
+        };
+
+        Operand::Memory {
+            size,
+            segment,
+            base,
+            index,
+            displacement,
+        }
+    }
+}
+
+fn width(w: bool) -> u8 {
+    if w {
+        16
+    } else {
+        8
+    }
+}
+
+fn register(register: Register) -> Operand {
+    let size = match register {
+        Register::AL
+        | Register::CL
+        | Register::DL
+        | Register::BL
+        | Register::AH
+        | Register::CH
+        | Register::DH
+        | Register::BH => 8,
+        _ => 16,
+    };
+
+    Operand::Register {
+        size,
+        register: register.register_mode_to_string(),
+    }
+}
+
+fn segment_register(sr: SegmentRegister) -> Operand {
+    Operand::Register {
+        size: 16,
+        register: sr.register_mode_to_string(),
+    }
+}
+
+fn immediate(immediate: i32, size: u8) -> Operand {
+    Operand::Immediate {
+        size: Some(size),
+        immediate,
+    }
+}
+
+/// The segment override closest to the instruction, which is the one that
+/// applies.
+fn segment_override(instruction: &Instruction) -> Option<SegmentRegister> {
+    let mut res = None;
+    let mut instruction = instruction;
+    loop {
+        instruction = match instruction {
+            Instruction::SegmentOverride(sr, i) => {
+                res = Some(*sr);
+                i
+            }
+            Instruction::Repeat(_, i) | Instruction::Lock(i) => i,
+            _ => return res,
+        };
+    }
+}
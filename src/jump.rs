@@ -0,0 +1,318 @@
+use crate::{mode::Mode, register::Register};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConditionalOp {
+    Jo,
+    Jno,
+    Jb,
+    Jnb,
+    Je,
+    Jne,
+    Jbe,
+    Ja,
+    Js,
+    Jns,
+    Jp,
+    Jnp,
+    Jl,
+    Jnl,
+    Jle,
+    Jg,
+    Loopnz,
+    Loopz,
+    Loop,
+    Jcxz,
+}
+
+impl ConditionalOp {
+    pub fn decode(byte: u8) -> Self {
+        match byte {
+            0x70 => Self::Jo,
+            0x71 => Self::Jno,
+            0x72 => Self::Jb,
+            0x73 => Self::Jnb,
+            0x74 => Self::Je,
+            0x75 => Self::Jne,
+            0x76 => Self::Jbe,
+            0x77 => Self::Ja,
+            0x78 => Self::Js,
+            0x79 => Self::Jns,
+            0x7A => Self::Jp,
+            0x7B => Self::Jnp,
+            0x7C => Self::Jl,
+            0x7D => Self::Jnl,
+            0x7E => Self::Jle,
+            0x7F => Self::Jg,
+            0xE0 => Self::Loopnz,
+            0xE1 => Self::Loopz,
+            0xE2 => Self::Loop,
+            0xE3 => Self::Jcxz,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            ConditionalOp::Jo => "jo",
+            ConditionalOp::Jno => "jno",
+            ConditionalOp::Jb => "jb",
+            ConditionalOp::Jnb => "jnb",
+            ConditionalOp::Je => "je",
+            ConditionalOp::Jne => "jne",
+            ConditionalOp::Jbe => "jbe",
+            ConditionalOp::Ja => "ja",
+            ConditionalOp::Js => "js",
+            ConditionalOp::Jns => "jns",
+            ConditionalOp::Jp => "jp",
+            ConditionalOp::Jnp => "jnp",
+            ConditionalOp::Jl => "jl",
+            ConditionalOp::Jnl => "jnl",
+            ConditionalOp::Jle => "jle",
+            ConditionalOp::Jg => "jg",
+            ConditionalOp::Loopnz => "loopnz",
+            ConditionalOp::Loopz => "loopz",
+            ConditionalOp::Loop => "loop",
+            ConditionalOp::Jcxz => "jcxz",
+        }
+    }
+}
+
+/// Formats a relative branch target the way NASM reads it back: relative to
+/// the start of the branch instruction, e.g. `$-4`.
+pub(crate) fn relative_target_to_string(ip_inc: i16, len: usize) -> String {
+    format!("${:+}", ip_inc as i32 + len as i32)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+    Jmp,
+    Call,
+}
+
+impl Transfer {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Transfer::Jmp => "jmp",
+            Transfer::Call => "call",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConditionalJump {
+    pub(crate) op: ConditionalOp,
+    pub(crate) ip_inc8: i8,
+}
+
+impl ConditionalJump {
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let op = ConditionalOp::decode(bytes[0]);
+        let ip_inc8 = bytes[1] as i8;
+
+        Self { op, ip_inc8 }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        2
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        let target = relative_target_to_string(self.ip_inc8 as i16, self.offset());
+
+        res.push_str(&format!("{} {}\r\n", self.op.mnemonic(), target));
+        res
+    }
+}
+
+#[derive(Debug)]
+pub struct DirectWithinSegment {
+    pub(crate) transfer: Transfer,
+    pub(crate) short: bool,
+    pub(crate) ip_inc: i16,
+}
+
+impl DirectWithinSegment {
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let (transfer, short) = match bytes[0] {
+            0xE8 => (Transfer::Call, false),
+            0xE9 => (Transfer::Jmp, false),
+            0xEB => (Transfer::Jmp, true),
+            _ => unreachable!(),
+        };
+
+        let ip_inc = if short {
+            bytes[1] as i8 as i16
+        } else {
+            i16::from_le_bytes([bytes[1], bytes[2]])
+        };
+
+        Self {
+            transfer,
+            short,
+            ip_inc,
+        }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        if self.short {
+            2
+        } else {
+            3
+        }
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        let target = relative_target_to_string(self.ip_inc, self.offset());
+        let size = match (self.transfer, self.short) {
+            (Transfer::Call, _) => "",
+            (Transfer::Jmp, true) => "short ",
+            (Transfer::Jmp, false) => "near ",
+        };
+
+        res.push_str(&format!(
+            "{} {}{}\r\n",
+            self.transfer.mnemonic(),
+            size,
+            target
+        ));
+        res
+    }
+}
+
+#[derive(Debug)]
+pub struct DirectIntersegment {
+    pub(crate) transfer: Transfer,
+    pub(crate) ip: u16,
+    pub(crate) cs: u16,
+}
+
+impl DirectIntersegment {
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let transfer = match bytes[0] {
+            0x9A => Transfer::Call,
+            0xEA => Transfer::Jmp,
+            _ => unreachable!(),
+        };
+
+        let ip = u16::from_le_bytes([bytes[1], bytes[2]]);
+        let cs = u16::from_le_bytes([bytes[3], bytes[4]]);
+
+        Self { transfer, ip, cs }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        5
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        res.push_str(&format!(
+            "{} {}:{}\r\n",
+            self.transfer.mnemonic(),
+            self.cs,
+            self.ip
+        ));
+        res
+    }
+}
+
+/// `jmp`/`call` through a register or memory operand (`FF /2` to `FF /5`).
+#[derive(Debug)]
+pub struct Indirect {
+    pub(crate) transfer: Transfer,
+    pub(crate) far: bool,
+    pub(crate) mode: Mode,
+    pub(crate) rm: Register,
+    pub(crate) disp_lo: Option<u8>,
+    pub(crate) disp_hi: Option<u8>,
+}
+
+impl Indirect {
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let (transfer, far) = match (bytes[1] & 0b0011_1000) >> 3 {
+            0b010 => (Transfer::Call, false),
+            0b011 => (Transfer::Call, true),
+            0b100 => (Transfer::Jmp, false),
+            0b101 => (Transfer::Jmp, true),
+            _ => unreachable!(),
+        };
+
+        let (mode, rm, disp_lo, disp_hi) = Mode::decode_rm(bytes, true);
+
+        Self {
+            transfer,
+            far,
+            mode,
+            rm,
+            disp_lo,
+            disp_hi,
+        }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        2 + self.mode.displacement_len()
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        let target = self.rm.rm_to_string(self.mode, self.disp_lo, self.disp_hi);
+        let size = match (self.far, self.mode) {
+            (true, _) => "far ",
+            (false, Mode::Reg) => "",
+            (false, _) => "word ",
+        };
+
+        res.push_str(&format!(
+            "{} {}{}\r\n",
+            self.transfer.mnemonic(),
+            size,
+            target
+        ));
+        res
+    }
+}
+
+#[derive(Debug)]
+pub struct Return {
+    pub(crate) far: bool,
+    pub(crate) data: Option<u16>,
+}
+
+impl Return {
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let far = (bytes[0] & 0b0000_1000) == 0b0000_1000;
+        let data = if (bytes[0] & 0b0000_0001) == 0 {
+            Some(u16::from_le_bytes([bytes[1], bytes[2]]))
+        } else {
+            None
+        };
+
+        Self { far, data }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        if self.data.is_some() {
+            3
+        } else {
+            1
+        }
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        let mnemonic = if self.far { "retf" } else { "ret" };
+
+        match self.data {
+            Some(data) => res.push_str(&format!("{} {}\r\n", mnemonic, data)),
+            None => res.push_str(&format!("{}\r\n", mnemonic)),
+        }
+        res
+    }
+}
@@ -1,33 +1,635 @@
-use std::error::Error;
+#![allow(clippy::inherent_to_string)]
 
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    ops::Range,
+    rc::Rc,
+};
+
+use bus::{Bus, BusConfig};
+use cfg::Graph;
+use clocks::CpuModel;
+use cpu::{Cpu, MAX_INSTRUCTION_LEN};
+use disk::Disk;
+use dos::Dos;
+use functions::Function;
+use handlers::Handlers;
+use image::SparseImage;
 use instruction::Instruction;
+use io::IoBus;
+use json::{DecodedInstruction, Listing};
+use loader::Load;
+use pic::Pic;
+use pit::Pit;
+use stats::Statistics;
+use video::Video;
 
-mod instruction;
-mod mode;
+mod arithmetic;
+pub mod bus;
+pub mod cfg;
+pub mod clocks;
+pub mod cpu;
+pub mod debugger;
+pub mod disk;
+pub mod dos;
+mod flags;
+pub mod functions;
+pub mod handlers;
+pub mod history;
+pub mod image;
+pub mod instruction;
+mod interrupt;
+pub mod io;
+pub mod json;
+mod jump;
+pub mod loader;
+pub mod memory;
+pub mod mode;
 mod mov;
+pub mod mz;
+pub mod pic;
+pub mod pit;
 mod register;
+mod shift;
+mod simple;
+mod stack;
+pub mod stats;
+mod string;
 mod tests;
+mod transfer;
+mod unary;
+pub mod video;
+
+/// Parses a decimal or `0x`-prefixed hexadecimal number.
+pub fn parse_number(value: &str) -> Option<usize> {
+    match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// Options for [`dissassemble_with`].
+#[derive(Debug, Clone, Default)]
+pub struct DisassembleOptions {
+    /// Append each instruction's best-case clocks on this processor as a
+    /// comment, and end the listing with the total of each basic block.
+    pub clocks: Option<CpuModel>,
+    /// Offset in the bytes to start disassembling at, e.g. the entry point of
+    /// an .EXE image.
+    pub start: usize,
+    /// Offsets of words the loader relocates, which are shown as `seg`
+    /// references rather than numbers.
+    pub relocations: BTreeSet<usize>,
+    /// Decode only the instructions reachable from `start` and `entries`
+    /// through branches, calls and fall-throughs, and list every other byte
+    /// as data, rather than sweeping from `start`. The listing then
+    /// assembles back to the same bytes.
+    pub recursive: bool,
+    /// More offsets a recursive traversal starts at, e.g. routines only
+    /// reached through pointers.
+    pub entries: BTreeSet<usize>,
+    /// Mark each function with its frame and epilogues, and name the
+    /// arguments and locals of BP frames with `%define`s.
+    pub functions: bool,
+}
 
 pub fn dissassemble(bytes: Vec<u8>) -> Result<String, Box<dyn Error>> {
+    dissassemble_with(bytes, &DisassembleOptions::default())
+}
+
+pub fn dissassemble_with(
+    bytes: Vec<u8>,
+    options: &DisassembleOptions,
+) -> Result<String, Box<dyn Error>> {
     let mut res = String::new();
     res.push_str("bits 16\r\n\r\n");
+    res.push_str(&disassemble_region(&bytes, options));
+
+    Ok(res)
+}
+
+/// Disassembles each region of a sparse image on its own, under a comment
+/// with its physical address range.
+pub fn dissassemble_image(
+    image: &SparseImage,
+    options: &DisassembleOptions,
+) -> Result<String, Box<dyn Error>> {
+    let mut res = String::new();
+    res.push_str("bits 16\r\n");
+
+    for (addr, bytes) in &image.regions {
+        let end = addr + bytes.len();
+        res.push_str(&format!("\r\n; {addr:#07x}-{end:#07x}\r\n"));
+        res.push_str(&disassemble_region(bytes, options));
+    }
+
+    Ok(res)
+}
+
+/// The instructions [`dissassemble_with`] would list, as a JSON
+/// [`Listing`].
+pub fn dissassemble_json(
+    bytes: &[u8],
+    options: &DisassembleOptions,
+) -> Result<String, Box<dyn Error>> {
+    let mut listing = Listing::new();
+    listing.instructions.extend(decode_json(bytes, 0, options));
+
+    listing.to_json()
+}
+
+/// The instructions of every region of a sparse image as a JSON
+/// [`Listing`], with physical addresses.
+pub fn dissassemble_image_json(
+    image: &SparseImage,
+    options: &DisassembleOptions,
+) -> Result<String, Box<dyn Error>> {
+    let mut listing = Listing::new();
+    for (addr, bytes) in &image.regions {
+        listing
+            .instructions
+            .extend(decode_json(bytes, *addr, options));
+    }
+
+    listing.to_json()
+}
+
+fn decode_json(
+    bytes: &[u8],
+    address: usize,
+    options: &DisassembleOptions,
+) -> Vec<DecodedInstruction> {
+    decode(bytes, options)
+        .into_iter()
+        .map(|(offset, instruction)| {
+            let end = offset + instruction.offset();
+            DecodedInstruction::new(address + offset, &bytes[offset..end], &instruction)
+        })
+        .collect()
+}
+
+/// Control-flow graph of the instructions [`dissassemble_with`] would list.
+pub fn control_flow_graph(bytes: &[u8], options: &DisassembleOptions) -> Graph {
+    let mut res = Graph::new();
+    res.add(&decode(bytes, options), 0);
+
+    res
+}
+
+/// Control-flow graph of every region of a sparse image, by physical
+/// address.
+pub fn control_flow_graph_image(image: &SparseImage, options: &DisassembleOptions) -> Graph {
+    let mut res = Graph::new();
+    for (addr, bytes) in &image.regions {
+        res.add(&decode(bytes, options), *addr);
+    }
+
+    res
+}
 
-    let mut offset = 0;
+/// The functions among the instructions [`dissassemble_with`] would list.
+pub fn functions(bytes: &[u8], options: &DisassembleOptions) -> Vec<Function> {
+    functions::find(&decode(bytes, options))
+}
+
+/// Statistics of the instructions a linear sweep of `bytes` decodes from
+/// [`DisassembleOptions::start`].
+pub fn statistics_with(bytes: &[u8], options: &DisassembleOptions) -> Statistics {
+    let mut res = Statistics::new();
+    for (_, instruction) in decode(bytes, options) {
+        res.add(&instruction);
+    }
+
+    res
+}
+
+/// Statistics over every region of a sparse image.
+pub fn statistics_image(image: &SparseImage, options: &DisassembleOptions) -> Statistics {
+    let mut res = Statistics::new();
+    for (_, bytes) in &image.regions {
+        for (_, instruction) in decode(bytes, options) {
+            res.add(&instruction);
+        }
+    }
+
+    res
+}
+
+/// The instructions in `bytes`, by a recursive traversal or a linear sweep
+/// as `options` ask.
+fn decode(bytes: &[u8], options: &DisassembleOptions) -> Vec<(usize, Instruction)> {
+    if options.recursive {
+        let entries = options.entries.iter().copied();
+        decode_reachable(bytes, entries.chain([options.start]).collect())
+    } else {
+        decode_region(bytes, options.start)
+    }
+}
+
+/// Recursive traversal of `bytes` from `entries`, following fall-throughs
+/// and branches relative to IP. A path ends at bytes that don't decode, at
+/// a truncated instruction, or at one that would overlap an instruction
+/// already decoded.
+fn decode_reachable(bytes: &[u8], mut pending: Vec<usize>) -> Vec<(usize, Instruction)> {
+    let mut res = BTreeMap::new();
+    // Bytes that belong to a decoded instruction.
+    let mut code = vec![false; bytes.len()];
+
+    // Padded so that decoding never reads past the end.
+    let padded = [bytes, &[0; MAX_INSTRUCTION_LEN]].concat();
+    while let Some(offset) = pending.pop() {
+        if offset >= bytes.len() || code[offset] {
+            continue;
+        }
+
+        let Ok(instruction) = Instruction::decode(&padded[offset..]) else {
+            continue;
+        };
+
+        let end = offset + instruction.offset();
+        if end > bytes.len() || code[offset..end].contains(&true) {
+            continue;
+        }
+        code[offset..end].fill(true);
+
+        if let Some(target) = instruction.relative_target(offset) {
+            pending.push(target);
+        }
+        if instruction.falls_through() {
+            pending.push(end);
+        }
+        res.insert(offset, instruction);
+    }
+
+    res.into_iter().collect()
+}
+
+/// Linear sweep of `bytes` from `start` until the end, or until bytes that
+/// don't decode or a truncated instruction.
+fn decode_region(bytes: &[u8], start: usize) -> Vec<(usize, Instruction)> {
+    let mut res = Vec::new();
+
+    // Padded so that decoding never reads past the end.
+    let padded = [bytes, &[0; MAX_INSTRUCTION_LEN]].concat();
+    let mut offset = start;
     loop {
         if offset >= bytes.len() {
             break;
         }
 
-        let instruction_bytes = bytes[offset..].to_vec();
-        let Ok(instruction) = Instruction::decode(&instruction_bytes) else {break};
+        let Ok(instruction) = Instruction::decode(&padded[offset..]) else {
+            break;
+        };
+
+        let len = instruction.offset();
+        if offset + len > bytes.len() {
+            break;
+        }
+        res.push((offset, instruction));
+        offset += len;
+    }
+
+    res
+}
+
+fn disassemble_region(bytes: &[u8], options: &DisassembleOptions) -> String {
+    let mut res = String::new();
+
+    let instructions = decode(bytes, options);
+
+    let functions = match options.functions {
+        true => functions::find(&instructions),
+        false => Vec::new(),
+    };
+
+    // The `%define`s in a function's header only hold from there until the
+    // next function starts, which may redefine them, so only its instructions
+    // listed in between are renamed.
+    let owner = |offset: usize| {
+        functions
+            .iter()
+            .rev()
+            .find(|f| f.start <= offset)
+            .filter(|f| f.framed && f.instructions.contains(&offset))
+    };
+
+    // A recursive traversal lists the bytes between instructions as data.
+    let mut next = 0;
+    for (offset, instruction) in &instructions {
+        if options.recursive {
+            res.push_str(&data_to_string(bytes, next..*offset, &options.relocations));
+            next = offset + instruction.offset();
+        }
+
+        for function in functions.iter().filter(|f| f.start == *offset) {
+            let mut names = BTreeMap::new();
+            for (offset, instruction) in &instructions {
+                if owner(*offset).is_some_and(|owner| owner.start == function.start) {
+                    names.extend(function.rename(&instruction.to_string()).1);
+                }
+            }
+            res.push_str(&function.header(&names));
+        }
+        if functions.iter().any(|f| f.epilogues.contains(offset)) {
+            res.push_str("; epilogue\r\n");
+        }
+
+        let relocation = options
+            .relocations
+            .range(*offset..offset + instruction.offset())
+            .next();
+        let text = match relocation {
+            Some(relocation) => instruction.to_segment_string(relocation - offset),
+            None => instruction.to_string(),
+        };
+        let text = match owner(*offset) {
+            Some(function) => function.rename(&text).0,
+            None => text,
+        };
+        // What NASM would assemble differently, `seg` references included,
+        // is listed as its bytes so the listing assembles back to the same
+        // bytes.
+        let end = offset + instruction.offset();
+        let text = if options.recursive
+            && (relocation.is_some() || !instruction.nasm_encodes(&bytes[*offset..end]))
+        {
+            format!(
+                "{} ; {}\r\n",
+                bytes_to_db(&bytes[*offset..end]),
+                text.trim_end()
+            )
+        } else {
+            text
+        };
+
+        match options.clocks {
+            Some(model) => res.push_str(&format!(
+                "{} ; {}\r\n",
+                text.trim_end(),
+                clocks::annotation(instruction, model)
+            )),
+            None => res.push_str(&text),
+        }
+    }
+
+    if options.recursive {
+        res.push_str(&data_to_string(
+            bytes,
+            next..bytes.len(),
+            &options.relocations,
+        ));
+    }
+
+    if let Some(model) = options.clocks {
+        res.push_str(&clocks::basic_block_summary(&instructions, model));
+    }
 
-        println!("{instruction:?}");
-        println!();
+    res
+}
 
-        offset += instruction.offset();
+/// `db` lines for the bytes in `range`, at most 16 to a line, with runs of
+/// four or more printable characters as strings. Relocated words are lines
+/// of their own, commented with their `seg` reference.
+fn data_to_string(bytes: &[u8], range: Range<usize>, relocations: &BTreeSet<usize>) -> String {
+    let mut res = String::new();
+    let mut items: Vec<String> = Vec::new();
+    let mut len = 0;
 
-        res.push_str(&instruction.to_string())
+    let flush = |res: &mut String, items: &mut Vec<String>| {
+        if !items.is_empty() {
+            res.push_str(&format!("db {}\r\n", items.join(", ")));
+            items.clear();
+        }
+    };
+
+    let mut offset = range.start;
+    while offset < range.end {
+        if relocations.contains(&offset) && offset + 1 < range.end {
+            flush(&mut res, &mut items);
+            len = 0;
+            let segment = u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+            res.push_str(&format!(
+                "{} ; dw seg {segment:#06x}\r\n",
+                bytes_to_db(&bytes[offset..offset + 2])
+            ));
+            offset += 2;
+            continue;
+        }
+
+        let printable = bytes[offset..range.end]
+            .iter()
+            .take_while(|&&byte| (0x20..0x7F).contains(&byte) && byte != b'\'')
+            .count();
+        let count = if printable >= 4 { printable } else { 1 };
+        if len + count > 16 && len > 0 {
+            flush(&mut res, &mut items);
+            len = 0;
+        }
+
+        if printable >= 4 {
+            let text = String::from_utf8_lossy(&bytes[offset..offset + printable]);
+            items.push(format!("'{text}'"));
+        } else {
+            items.push(format!("{:#04x}", bytes[offset]));
+        }
+        len += count;
+        offset += count;
     }
+    flush(&mut res, &mut items);
+
+    res
+}
+
+/// A `db` of `bytes`.
+fn bytes_to_db(bytes: &[u8]) -> String {
+    let items: Vec<String> = bytes.iter().map(|byte| format!("{byte:#04x}")).collect();
+    format!("db {}", items.join(", "))
+}
+
+/// Options for [`simulate_with`].
+#[derive(Debug, Clone)]
+pub struct SimulateOptions {
+    /// Stop after this many instructions, so programs that never halt still
+    /// terminate.
+    pub instruction_limit: usize,
+    /// Processor whose timing is used for clock estimates.
+    pub model: CpuModel,
+    /// Prefix each trace line with the instruction's estimated clocks and the
+    /// running total, e.g. `Clocks: +13 = 17 (8 + 5ea) |`.
+    pub show_clocks: bool,
+    pub format: TraceFormat,
+    /// How the program is placed in memory.
+    pub load: Load,
+    /// DOS services for the program, see [`Cpu::dos`].
+    pub dos: Option<Rc<RefCell<Dos>>>,
+    /// Disk served through INT 13h, see [`Cpu::disk`].
+    pub disk: Option<Rc<RefCell<Disk>>>,
+    /// Display adapter served through INT 10h, see [`Cpu::video`].
+    pub video: Option<Video>,
+    /// Devices on the I/O bus, see [`Cpu::io`]. Unclaimed port accesses are
+    /// noted in the trace after the instruction that made them.
+    pub io: IoBus,
+    /// Timer and interrupt controller, see [`Cpu::pit`].
+    pub pit: Option<Rc<RefCell<Pit>>>,
+    pub pic: Option<Rc<RefCell<Pic>>>,
+    /// Vectors serviced in Rust, see [`Cpu::handlers`].
+    pub handlers: Handlers,
+    /// Time execution with the prefetch queue and bus cycle model, see
+    /// [`Cpu::bus`]. Clocks in the trace are then T-states, and with
+    /// [`BusConfig::trace`] each instruction is followed by its T-states.
+    pub bus: Option<BusConfig>,
+}
+
+/// Text format of the trace returned by [`simulate_with`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    #[default]
+    Native,
+    /// Exactly what the course's sim86 tool prints for the program `name`,
+    /// so traces can be diffed against its reference `.txt` files.
+    Sim86 { name: String },
+}
+
+impl Default for SimulateOptions {
+    fn default() -> Self {
+        Self {
+            instruction_limit: 1_000_000,
+            model: CpuModel::default(),
+            show_clocks: false,
+            format: TraceFormat::default(),
+            load: Load::default(),
+            dos: None,
+            disk: None,
+            video: None,
+            io: IoBus::new(),
+            pit: None,
+            pic: None,
+            handlers: Handlers::new(),
+            bus: None,
+        }
+    }
+}
+
+/// Executes `bytes` with the default [`SimulateOptions`].
+pub fn simulate(bytes: Vec<u8>) -> Result<String, Box<dyn Error>> {
+    simulate_with(bytes, &SimulateOptions::default())
+}
+
+/// Loads `bytes` as chosen by [`SimulateOptions::load`] and executes from
+/// CS:IP until `hlt`, until IP leaves a raw image, or until the instruction
+/// limit is reached.
+/// Returns a trace with one line per instruction, e.g.
+/// `mov cx, bx ; cx:0x0->0x2 ip:0x3->0x5`, followed by the final register
+/// state.
+pub fn simulate_with(bytes: Vec<u8>, options: &SimulateOptions) -> Result<String, Box<dyn Error>> {
+    let (_, res) = run(bytes, options)?;
 
     Ok(res)
 }
+
+/// Same as [`simulate_with`], but also returns the CPU so that its final
+/// memory can be inspected or dumped.
+pub fn run(bytes: Vec<u8>, options: &SimulateOptions) -> Result<(Cpu, String), Box<dyn Error>> {
+    let mut cpu = Cpu::new();
+    cpu.model = options.model;
+    cpu.dos = options.dos.clone();
+    cpu.disk = options.disk.clone();
+    cpu.video = options.video;
+    cpu.io = options.io.clone();
+    cpu.pit = options.pit.clone();
+    cpu.pic = options.pic.clone();
+    cpu.handlers = options.handlers.clone();
+    cpu.bus = options.bus.map(|config| Bus::new(config, options.model));
+    let end = loader::load(&mut cpu, &bytes, &options.load)?;
+
+    let mut res = String::new();
+
+    if let TraceFormat::Sim86 { name } = &options.format {
+        if options.show_clocks {
+            let model = match options.model {
+                CpuModel::I8086 => "8086",
+                CpuModel::I8088 => "8088",
+            };
+
+            res.push_str("**************\r\n");
+            res.push_str(&format!("**** {model} ****\r\n"));
+            res.push_str("**************\r\n\r\n");
+            res.push_str(concat!(
+                "WARNING: Clocks reported by this utility are strictly from the 8086 manual.\r\n",
+                "They will be inaccurate, both because the manual clocks are estimates, and because\r\n",
+                "some of the entries in the manual look highly suspicious and are probably typos.\r\n\r\n",
+            ));
+        }
+
+        res.push_str(&format!("--- {name} execution ---\r\n"));
+    }
+
+    let mut count = 0;
+    while !cpu.halted && cpu.code_address() < end {
+        if count == options.instruction_limit {
+            res.push_str("; Instruction limit reached\r\n");
+            break;
+        }
+
+        let (before, clocks_before) = (cpu.registers, cpu.clocks);
+        let instruction = cpu.step()?;
+        count += 1;
+
+        let clocks = if options.show_clocks && cpu.bus.is_some() {
+            format!(
+                "Cycles: +{} = {} | ",
+                cpu.clocks - clocks_before,
+                cpu.clocks
+            )
+        } else if options.show_clocks {
+            let clocks = cpu.instruction_clocks;
+            format!(
+                "Clocks: +{} = {}{} | ",
+                clocks.total(),
+                cpu.clocks,
+                clocks.breakdown()
+            )
+        } else {
+            String::new()
+        };
+
+        let changes = before.changes(&cpu.registers);
+        match options.format {
+            TraceFormat::Native => res.push_str(&format!(
+                "{} ; {}{}\r\n",
+                instruction.to_string().trim_end(),
+                clocks,
+                changes
+            )),
+            // sim86 ends every change with a space, including the last one.
+            TraceFormat::Sim86 { .. } => res.push_str(&format!(
+                "{} ; {}{} \r\n",
+                instruction.to_sim86_string().trim_end(),
+                clocks,
+                changes
+            )),
+        }
+
+        for access in cpu.io.take_unclaimed() {
+            res.push_str(&format!("; {}\r\n", access.to_string()));
+        }
+        if let Some(bus) = &mut cpu.bus {
+            for line in bus.take_trace() {
+                res.push_str(&format!(";{line}\r\n"));
+            }
+        }
+    }
+
+    res.push_str("\r\nFinal registers:\r\n");
+    match options.format {
+        TraceFormat::Native => res.push_str(&cpu.registers.to_string()),
+        TraceFormat::Sim86 { .. } => {
+            res.push_str(&cpu.registers.to_sim86_string());
+            res.push_str("\r\n");
+        }
+    }
+
+    Ok((cpu, res))
+}
@@ -0,0 +1,340 @@
+use std::error::Error;
+
+use crate::{
+    cpu::Cpu,
+    disk::SECTOR_SIZE,
+    image::SparseImage,
+    memory::{Memory, MEMORY_SIZE},
+    mz::Executable,
+    pic, pit,
+    register::{Register, SegmentRegister},
+};
+
+/// Size of the Program Segment Prefix that DOS builds in front of a program.
+pub const PSP_SIZE: u16 = 0x100;
+
+/// Largest .COM image that fits in its 64 KiB segment after the PSP.
+pub const MAX_COM_SIZE: usize = 0x10000 - PSP_SIZE as usize;
+
+/// Segment just past the conventional memory DOS hands to a program, stored
+/// in the PSP as the memory-top word.
+pub(crate) const MEMORY_TOP: u16 = 0xA000;
+
+/// Where the `cli; hlt` that ends a .COM program is placed, in the low
+/// memory DOS would otherwise occupy. Interrupts are disabled first so that
+/// the timer doesn't wake it.
+pub(crate) const EXIT_STUB: (u16, u16) = (0x0050, 0x0000);
+pub(crate) const EXIT_CODE: [u8; 2] = [0xFA, 0xF4];
+
+/// Where the BIOS loads the boot sector, in segment 0.
+pub const BOOT_ADDRESS: u16 = 0x7C00;
+
+/// What services implemented in Rust place at their entry point: `sti`, as
+/// DOS and BIOS handlers start with, and `retf 2`, which returns to the
+/// caller with the flags the service leaves rather than those INT pushed.
+pub(crate) const SERVICE_STUB: [u8; 4] = [0xFB, 0xCA, 0x02, 0x00];
+
+/// Where the IBM PC BIOS has its INT 08h timer handler.
+const TIMER_HANDLER: (u16, u16) = (0xF000, 0xFEA5);
+
+const TIMER_HANDLER_CODE: [u8; 24] = [
+    0x1E, // push ds
+    0x50, // push ax
+    0xB8, 0x40, 0x00, // mov ax, 0x40
+    0x8E, 0xD8, // mov ds, ax
+    0xFF, 0x06, 0x6C, 0x00, // inc word [0x6c]
+    0x75, 0x04, // jnz eoi
+    0xFF, 0x06, 0x6E, 0x00, // inc word [0x6e]
+    0xB0, 0x20, // eoi: mov al, 0x20
+    0xE6, 0x20, // out 0x20, al
+    0x58, // pop ax
+    0x1F, // pop ds
+    0xCF, // iret
+];
+
+/// How a program image is placed in memory before it runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Load {
+    /// The bytes are copied to physical address 0 and run from 0000:0000
+    /// until IP leaves them.
+    #[default]
+    Raw,
+    /// A DOS .COM program, loaded at `segment:0100` behind a PSP holding
+    /// `tail` as its command line.
+    Com { segment: u16, tail: String },
+    /// A DOS MZ .EXE program, with its PSP at `segment:0000` and its image,
+    /// relocated, from the paragraph after it.
+    Exe { segment: u16, tail: String },
+    /// A ROM image in Intel HEX form, run from its start address.
+    IntelHex,
+    /// A ROM image in Motorola S-record form, run from its start address.
+    SRecord,
+    /// A disk image whose boot sector is run the way the BIOS starts it,
+    /// from BIOS drive `drive`.
+    Boot { drive: u8 },
+}
+
+/// Loads `bytes` as described by `load` and returns the physical address at
+/// which execution is considered to have run off the end of the program.
+/// .COM programs have no such address and run until they exit.
+///
+/// Whichever of [`Cpu::dos`], [`Cpu::disk`] and [`Cpu::video`] are attached
+/// are installed for the program.
+pub fn load(cpu: &mut Cpu, bytes: &[u8], load: &Load) -> Result<usize, Box<dyn Error>> {
+    let (end, psp) = match load {
+        Load::Raw => {
+            cpu.memory.load(0, bytes);
+
+            (bytes.len(), 0)
+        }
+        Load::Com { segment, tail } => {
+            load_com(cpu, bytes, *segment, tail)?;
+
+            (MEMORY_SIZE, *segment)
+        }
+        Load::Exe { segment, tail } => {
+            load_exe(cpu, &Executable::parse(bytes)?, *segment, tail)?;
+
+            (MEMORY_SIZE, *segment)
+        }
+        Load::IntelHex | Load::SRecord => {
+            let text = std::str::from_utf8(bytes)?;
+            let image = if *load == Load::IntelHex {
+                SparseImage::parse_intel_hex(text)?
+            } else {
+                SparseImage::parse_srecord(text)?
+            };
+            load_image(cpu, &image);
+
+            (MEMORY_SIZE, 0)
+        }
+        Load::Boot { drive } => {
+            load_boot_sector(cpu, bytes, *drive)?;
+
+            (MEMORY_SIZE, 0)
+        }
+    };
+
+    if let Some(disk) = cpu.disk.clone() {
+        disk.borrow().install(cpu);
+    }
+    if let Some(video) = cpu.video {
+        video.install(cpu);
+    }
+    if let Some(pic) = cpu.pic.clone() {
+        cpu.io.attach(pic::PORTS, pic)?;
+    }
+    if let Some(pit) = cpu.pit.clone() {
+        cpu.io.attach(pit::PORTS, pit)?;
+        install_timer_handler(&mut cpu.memory);
+    }
+
+    if let Some(dos) = cpu.dos.clone() {
+        dos.borrow_mut().install(cpu, psp);
+    }
+
+    Ok(end)
+}
+
+/// Checks the 0x55AA signature of the boot sector at the start of the disk
+/// image `bytes`, copies it to 0000:7C00 and jumps to it with the boot drive
+/// in DL, as the BIOS does.
+pub fn load_boot_sector(cpu: &mut Cpu, bytes: &[u8], drive: u8) -> Result<(), Box<dyn Error>> {
+    let Some(sector) = bytes.get(..SECTOR_SIZE) else {
+        return Err("Disk image is shorter than a sector".into());
+    };
+    if sector[SECTOR_SIZE - 2..] != [0x55, 0xAA] {
+        return Err("Boot sector doesn't end with the 0x55AA signature".into());
+    }
+
+    cpu.memory.load(BOOT_ADDRESS as usize, sector);
+
+    let registers = &mut cpu.registers;
+    registers.set_segment(SegmentRegister::CS, 0);
+    registers.ip = BOOT_ADDRESS;
+    registers.set(Register::DL, drive as u16);
+    // A stack just below the boot sector, where many BIOSes leave it.
+    registers.set_segment(SegmentRegister::SS, 0);
+    registers.set(Register::SP, BOOT_ADDRESS);
+
+    Ok(())
+}
+
+/// Copies every region of `image` to its address and starts at its entry
+/// point.
+pub fn load_image(cpu: &mut Cpu, image: &SparseImage) {
+    for (addr, bytes) in &image.regions {
+        for (i, byte) in bytes.iter().enumerate() {
+            cpu.memory.write_physical(addr + i, *byte);
+        }
+    }
+
+    let (cs, ip) = image.entry();
+    cpu.registers.set_segment(SegmentRegister::CS, cs);
+    cpu.registers.ip = ip;
+}
+
+/// Loads a .COM image at `segment:0100`, builds its PSP at `segment:0000`
+/// and sets up the registers the way DOS does before jumping to it: CS, DS,
+/// ES and SS all point at the PSP, SP at the top of the segment with a zero
+/// word pushed so a final `ret` lands on the `int 20h` at PSP:0000, and IP
+/// at 0x100.
+pub fn load_com(
+    cpu: &mut Cpu,
+    bytes: &[u8],
+    segment: u16,
+    tail: &str,
+) -> Result<(), Box<dyn Error>> {
+    if bytes.len() > MAX_COM_SIZE {
+        return Err(format!(
+            "COM image is {} bytes, the limit is {MAX_COM_SIZE}",
+            bytes.len()
+        )
+        .into());
+    }
+
+    build_psp(cpu, segment, tail)?;
+
+    let memory = &mut cpu.memory;
+    memory.load(Memory::physical_address(segment, PSP_SIZE), bytes);
+
+    memory.write_word(segment, 0xFFFE, 0);
+
+    let registers = &mut cpu.registers;
+    for sr in [
+        SegmentRegister::CS,
+        SegmentRegister::DS,
+        SegmentRegister::ES,
+        SegmentRegister::SS,
+    ] {
+        registers.set_segment(sr, segment);
+    }
+    registers.set(Register::SP, 0xFFFE);
+    registers.ip = PSP_SIZE;
+
+    Ok(())
+}
+
+/// Loads an .EXE image in the paragraph after a PSP at `segment:0000`, adds
+/// the load segment to every relocated word, and starts it at its initial
+/// CS:IP and SS:SP, with DS and ES pointing at the PSP.
+pub fn load_exe(
+    cpu: &mut Cpu,
+    exe: &Executable,
+    segment: u16,
+    tail: &str,
+) -> Result<(), Box<dyn Error>> {
+    let load_segment = segment.wrapping_add(PSP_SIZE / 16);
+    let needed = exe.image.len().div_ceil(16) + exe.header.min_alloc as usize;
+    if load_segment as usize + needed > MEMORY_TOP as usize {
+        return Err(format!(
+            "EXE needs {needed} paragraphs at segment {load_segment:#06x}, past the end of memory"
+        )
+        .into());
+    }
+
+    build_psp(cpu, segment, tail)?;
+
+    let memory = &mut cpu.memory;
+    memory.load(Memory::physical_address(load_segment, 0), &exe.image);
+
+    for (offset, relocation_segment) in &exe.relocations {
+        let relocation_segment = load_segment.wrapping_add(*relocation_segment);
+        let value = memory.read_word(relocation_segment, *offset);
+        memory.write_word(
+            relocation_segment,
+            *offset,
+            value.wrapping_add(load_segment),
+        );
+    }
+
+    let header = &exe.header;
+    let registers = &mut cpu.registers;
+    registers.set_segment(SegmentRegister::DS, segment);
+    registers.set_segment(SegmentRegister::ES, segment);
+    registers.set_segment(SegmentRegister::SS, load_segment.wrapping_add(header.ss));
+    registers.set(Register::SP, header.sp);
+    registers.set_segment(SegmentRegister::CS, load_segment.wrapping_add(header.cs));
+    registers.ip = header.ip;
+
+    Ok(())
+}
+
+/// Builds the 256-byte PSP at `segment:0000`, with `tail` as the command
+/// line, and points INT 20h at a `hlt`, so that without DOS, program
+/// termination halts right away.
+fn build_psp(cpu: &mut Cpu, segment: u16, tail: &str) -> Result<(), Box<dyn Error>> {
+    if segment >= MEMORY_TOP {
+        return Err(format!(
+            "PSP segment {segment:#06x} is past the end of memory at {MEMORY_TOP:#06x}"
+        )
+        .into());
+    }
+
+    // The tail is stored with its leading space and a trailing carriage
+    // return, which the length byte doesn't count.
+    let tail = if tail.is_empty() {
+        Vec::new()
+    } else {
+        format!(" {tail}").into_bytes()
+    };
+    if tail.len() > 126 {
+        return Err("Command tail is longer than 126 bytes".into());
+    }
+
+    let memory = &mut cpu.memory;
+    for offset in 0..PSP_SIZE {
+        memory.write_byte(segment, offset, 0);
+    }
+
+    // int 20h
+    memory.write_byte(segment, 0x00, 0xCD);
+    memory.write_byte(segment, 0x01, 0x20);
+    memory.write_word(segment, 0x02, MEMORY_TOP);
+    // int 21h; retf, the CP/M-style far call entry to DOS.
+    memory.write_byte(segment, 0x50, 0xCD);
+    memory.write_byte(segment, 0x51, 0x21);
+    memory.write_byte(segment, 0x52, 0xCB);
+    // Blank file names of the two default FCBs.
+    for offset in (0x5D..0x68).chain(0x6D..0x78) {
+        memory.write_byte(segment, offset, b' ');
+    }
+
+    memory.write_byte(segment, 0x80, tail.len() as u8);
+    for (i, byte) in tail.iter().enumerate() {
+        memory.write_byte(segment, 0x81 + i as u16, *byte);
+    }
+    memory.write_byte(segment, 0x81 + tail.len() as u16, 0x0D);
+
+    let (stub_segment, stub_offset) = EXIT_STUB;
+    for (i, byte) in EXIT_CODE.iter().enumerate() {
+        memory.write_byte(stub_segment, stub_offset + i as u16, *byte);
+    }
+    memory.write_word(0, 4 * 0x20, stub_offset);
+    memory.write_word(0, 4 * 0x20 + 2, stub_segment);
+
+    Ok(())
+}
+
+/// Points INT 08h at a handler like the BIOS's: it counts ticks in the
+/// BIOS data area's word pair at 0040:006C and sends the PIC an EOI.
+fn install_timer_handler(memory: &mut Memory) {
+    let (segment, offset) = TIMER_HANDLER;
+
+    for (i, byte) in TIMER_HANDLER_CODE.iter().enumerate() {
+        memory.write_byte(segment, offset + i as u16, *byte);
+    }
+    memory.write_word(0, 4 * 0x08, offset);
+    memory.write_word(0, 4 * 0x08 + 2, segment);
+}
+
+/// Places [`SERVICE_STUB`] at `entry` and points `vector` at it.
+pub(crate) fn install_service(memory: &mut Memory, vector: u8, entry: (u16, u16)) {
+    let (segment, offset) = entry;
+
+    for (i, byte) in SERVICE_STUB.iter().enumerate() {
+        memory.write_byte(segment, offset + i as u16, *byte);
+    }
+    memory.write_word(0, 4 * vector as u16, offset);
+    memory.write_word(0, 4 * vector as u16 + 2, segment);
+}
@@ -0,0 +1,372 @@
+use std::{
+    cell::RefCell,
+    env,
+    error::Error,
+    fs,
+    io::{self, BufReader, Write},
+    process,
+    rc::Rc,
+};
+
+use computer_enhance::{
+    bus::BusConfig,
+    clocks::CpuModel,
+    control_flow_graph, control_flow_graph_image,
+    debugger::Debugger,
+    disk::Disk,
+    dissassemble_image, dissassemble_image_json, dissassemble_json, dissassemble_with,
+    dos::Dos,
+    image::SparseImage,
+    loader::Load,
+    memory::{PixelFormat, MEMORY_SIZE},
+    mz::Executable,
+    parse_number,
+    pic::Pic,
+    pit::Pit,
+    run, statistics_image, statistics_with,
+    video::{self, Video},
+    DisassembleOptions, SimulateOptions, TraceFormat,
+};
+
+const USAGE: &str = "usage: computer_enhance <disasm|stats|exec|debug> <file> [options]
+
+disasm and stats options:
+    --clocks <8086|8088>     annotate best-case clocks for this processor
+    --load <exe|ihex|srec>   decode an .EXE image from its entry point, or
+                             each region of an Intel HEX or S-record file
+    --traversal <linear|recursive>
+                             sweep linearly from the entry point (default),
+                             or decode only code reached from it through
+                             branches and calls, listing the rest as data
+    --entry <offset>         another entry point for a recursive traversal
+    --functions <on|off>     mark functions found from calls and bp frame
+                             prologues, naming arguments and locals
+                             (default off)
+    --output <nasm|json|dot> disassemble as NASM source (default), as JSON
+                             (see computer_enhance::json::Listing for the
+                             schema), or as a Graphviz control-flow graph
+
+stats reports how often each mnemonic, instruction length, addressing mode
+and register occurs, and the share of prefixed instructions.
+
+exec and debug options:
+    --load <format>          raw, loaded at address 0 (default), a DOS com or
+                             exe, an ihex or srec ROM image, or a boot disk
+    --drive <number>         BIOS drive a boot disk is (default 0, 0x80 for
+                             the first hard disk)
+    --segment <segment>      segment of a DOS program's PSP (default 0x1000)
+    --args <tail>            command tail passed to a DOS program
+    --dos <directory>        emulate DOS, with files confined to <directory>
+    --video <cga|mda>        emulate a text mode display adapter and INT 10h
+    --timer <on|off>         emulate the 8253 timer and 8259 interrupt
+                             controller, interrupting with IRQ0 (default off)
+    --bus <timing|trace>     time execution with the prefetch queue and bus
+                             cycles of the --clocks processor, and with trace
+                             list every T-state
+    --wait-states <count>    wait states of each memory bus cycle (default 0)
+
+exec options:
+    --limit <instructions>   stop after this many instructions
+    --clocks <8086|8088>     show estimated clocks for this processor
+    --sim86 <name>           print the trace exactly as sim86 does for <name>
+    --dump <path>            write memory to a raw binary file
+    --image <path>           write memory as a PPM image
+    --at <address>           physical address to dump from (default 0)
+    --len <bytes>            bytes to dump (default all of memory)
+    --size <width>x<height>  image size in pixels (default 64x64)
+    --format <rgba|rgb>      image pixel format (default rgba)
+    --screen <ansi|text>     print the text mode screen at exit, in colour or
+                             as plain text (implies --video cga)
+
+debug options:
+    --limit <instructions>   stop `continue` after this many instructions
+    --clocks <8086|8088>     processor whose timing is estimated";
+
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    process::exit(2);
+}
+
+/// Reads debugger commands from stdin until `quit` or end of input.
+fn debug(bytes: &[u8], options: &SimulateOptions) -> Result<(), Box<dyn Error>> {
+    let mut debugger = Debugger::new(bytes, options)?;
+    print!("{}", debugger.location());
+
+    let mut lines = io::stdin().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let line = line?;
+
+        if matches!(line.trim(), "q" | "quit") {
+            break;
+        }
+
+        match debugger.command(&line) {
+            Ok(res) => print!("{res}"),
+            Err(e) => println!("error: {e}"),
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let [command, path, flags @ ..] = args.as_slice() else {
+        usage();
+    };
+
+    let mut disassemble_options = DisassembleOptions::default();
+    let mut options = SimulateOptions::default();
+    let mut dump = None;
+    let mut image = None;
+    let mut at = 0;
+    let mut len = MEMORY_SIZE;
+    let (mut width, mut height) = (64, 64);
+    let mut format = PixelFormat::Rgba;
+    let mut load = "raw";
+    let mut segment = 0x1000;
+    let mut tail = String::new();
+    let mut sandbox = None;
+    let mut drive = 0;
+    let mut screen = None;
+    let mut output = "nasm";
+
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        let Some(value) = flags.next() else {
+            usage();
+        };
+
+        match flag.as_str() {
+            "--limit" => options.instruction_limit = parse_number(value).unwrap_or_else(|| usage()),
+            "--clocks" => {
+                options.show_clocks = true;
+                options.model = match value.as_str() {
+                    "8086" => CpuModel::I8086,
+                    "8088" => CpuModel::I8088,
+                    _ => usage(),
+                };
+                disassemble_options.clocks = Some(options.model);
+            }
+            "--sim86" => {
+                options.format = TraceFormat::Sim86 {
+                    name: value.clone(),
+                }
+            }
+            "--dump" => dump = Some(value),
+            "--image" => image = Some(value),
+            "--at" => at = parse_number(value).unwrap_or_else(|| usage()),
+            "--len" => len = parse_number(value).unwrap_or_else(|| usage()),
+            "--size" => {
+                let Some((w, h)) = value.split_once('x') else {
+                    usage();
+                };
+                width = parse_number(w).unwrap_or_else(|| usage());
+                height = parse_number(h).unwrap_or_else(|| usage());
+            }
+            "--format" => {
+                format = match value.as_str() {
+                    "rgba" => PixelFormat::Rgba,
+                    "rgb" => PixelFormat::Rgb,
+                    _ => usage(),
+                }
+            }
+            "--load" => {
+                load = match value.as_str() {
+                    "raw" | "com" | "exe" | "ihex" | "srec" | "boot" => value,
+                    _ => usage(),
+                }
+            }
+            "--segment" => {
+                segment = parse_number(value)
+                    .and_then(|segment| u16::try_from(segment).ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--args" => tail = value.clone(),
+            "--dos" => sandbox = Some(value),
+            "--video" => {
+                options.video = match value.as_str() {
+                    "cga" => Some(Video::cga()),
+                    "mda" => Some(Video::mda()),
+                    _ => usage(),
+                }
+            }
+            "--screen" => {
+                screen = match value.as_str() {
+                    "ansi" | "text" => Some(value.as_str()),
+                    _ => usage(),
+                };
+                options.video.get_or_insert(Video::cga());
+            }
+            "--timer" => match value.as_str() {
+                "on" => {
+                    options.pit = Some(Rc::new(RefCell::new(Pit::new())));
+                    options.pic = Some(Rc::new(RefCell::new(Pic::new())));
+                }
+                "off" => (options.pit, options.pic) = (None, None),
+                _ => usage(),
+            },
+            "--bus" => {
+                let config = options.bus.get_or_insert_with(BusConfig::default);
+                config.trace = match value.as_str() {
+                    "timing" => false,
+                    "trace" => true,
+                    _ => usage(),
+                };
+            }
+            "--wait-states" => {
+                options
+                    .bus
+                    .get_or_insert_with(BusConfig::default)
+                    .wait_states = parse_number(value)
+                    .and_then(|count| u32::try_from(count).ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--traversal" => {
+                disassemble_options.recursive = match value.as_str() {
+                    "linear" => false,
+                    "recursive" => true,
+                    _ => usage(),
+                }
+            }
+            "--entry" => {
+                let entry = parse_number(value).unwrap_or_else(|| usage());
+                disassemble_options.entries.insert(entry);
+                disassemble_options.recursive = true;
+            }
+            "--functions" => {
+                disassemble_options.functions = match value.as_str() {
+                    "on" => true,
+                    "off" => false,
+                    _ => usage(),
+                }
+            }
+            "--output" => {
+                output = match value.as_str() {
+                    "nasm" | "json" | "dot" => value.as_str(),
+                    _ => usage(),
+                }
+            }
+            "--drive" => {
+                drive = parse_number(value)
+                    .and_then(|drive| u8::try_from(drive).ok())
+                    .unwrap_or_else(|| usage())
+            }
+            _ => usage(),
+        }
+    }
+
+    options.load = match load {
+        "com" => Load::Com { segment, tail },
+        "exe" => Load::Exe { segment, tail },
+        "ihex" => Load::IntelHex,
+        "srec" => Load::SRecord,
+        "boot" => Load::Boot { drive },
+        _ => Load::Raw,
+    };
+
+    if load == "boot" {
+        options.disk = Some(Rc::new(RefCell::new(Disk::open(path, drive)?)));
+    }
+
+    let dos = sandbox.map(|sandbox| {
+        let dos = Dos::new(sandbox)
+            .with_input(Box::new(BufReader::new(io::stdin())))
+            .with_output(Box::new(io::stdout()));
+
+        Rc::new(RefCell::new(dos))
+    });
+    options.dos = dos.clone();
+
+    let bytes = fs::read(path)?;
+
+    match command.as_str() {
+        "disasm" | "stats" if load == "exe" => {
+            let exe = Executable::parse(&bytes)?;
+            disassemble_options.start = exe.entry();
+            disassemble_options.relocations = exe.relocation_offsets().into_iter().collect();
+
+            match (command.as_str(), output) {
+                ("stats", _) => print!(
+                    "{}",
+                    statistics_with(&exe.image, &disassemble_options).to_string()
+                ),
+                (_, "json") => println!("{}", dissassemble_json(&exe.image, &disassemble_options)?),
+                (_, "dot") => print!(
+                    "{}",
+                    control_flow_graph(&exe.image, &disassemble_options).to_dot()
+                ),
+                _ => print!("{}", dissassemble_with(exe.image, &disassemble_options)?),
+            }
+        }
+        "disasm" | "stats" if load == "ihex" || load == "srec" => {
+            let text = String::from_utf8(bytes)?;
+            let image = if load == "ihex" {
+                SparseImage::parse_intel_hex(&text)?
+            } else {
+                SparseImage::parse_srecord(&text)?
+            };
+
+            match (command.as_str(), output) {
+                ("stats", _) => print!(
+                    "{}",
+                    statistics_image(&image, &disassemble_options).to_string()
+                ),
+                (_, "json") => {
+                    println!("{}", dissassemble_image_json(&image, &disassemble_options)?)
+                }
+                (_, "dot") => print!(
+                    "{}",
+                    control_flow_graph_image(&image, &disassemble_options).to_dot()
+                ),
+                _ => print!("{}", dissassemble_image(&image, &disassemble_options)?),
+            }
+        }
+        "disasm" if output == "json" => {
+            println!("{}", dissassemble_json(&bytes, &disassemble_options)?)
+        }
+        "disasm" if output == "dot" => print!(
+            "{}",
+            control_flow_graph(&bytes, &disassemble_options).to_dot()
+        ),
+        "disasm" => print!("{}", dissassemble_with(bytes, &disassemble_options)?),
+        "stats" => print!(
+            "{}",
+            statistics_with(&bytes, &disassemble_options).to_string()
+        ),
+        "exec" => {
+            let (cpu, res) = run(bytes, &options)?;
+            print!("{res}");
+
+            if let Some(dump) = dump {
+                cpu.memory.dump(at, len, dump)?;
+            }
+
+            if let Some(image) = image {
+                cpu.memory.export_ppm(at, width, height, format, image)?;
+            }
+
+            match screen {
+                Some("ansi") => print!("{}", video::screen_ansi(&cpu.memory)),
+                Some(_) => print!("{}", video::screen_text(&cpu.memory)),
+                None => {}
+            }
+        }
+        "debug" => debug(&bytes, &options)?,
+        _ => usage(),
+    }
+
+    // Like DOS, pass the program's exit code on.
+    if let Some(code) = dos.and_then(|dos| dos.borrow().exit_code()) {
+        process::exit(code as i32);
+    }
+
+    Ok(())
+}
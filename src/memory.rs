@@ -0,0 +1,168 @@
+use std::{error::Error, fs, path::Path};
+
+/// Size of the 8086 physical address space.
+pub const MEMORY_SIZE: usize = 0x100000;
+
+/// Layout of the pixels being exported by [`Memory::export_ppm`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgb,
+    /// Four bytes per pixel; alpha is dropped since PPM has no alpha channel.
+    Rgba,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgb => 3,
+            PixelFormat::Rgba => 4,
+        }
+    }
+}
+
+/// The full 1 MiB physical address space, addressed either physically or as
+/// `segment:offset`.
+#[derive(Debug, Clone)]
+pub struct Memory {
+    bytes: Vec<u8>,
+    /// Physical address and previous value of every byte written since
+    /// [`Memory::start_journal`], so the writes can be undone.
+    journal: Option<Vec<(usize, u8)>>,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self {
+            bytes: vec![0; MEMORY_SIZE],
+            journal: None,
+        }
+    }
+
+    /// Translates `segment:offset` to a physical address, wrapping at the
+    /// 1 MiB boundary like the 8086's 20-bit address bus.
+    pub fn physical_address(segment: u16, offset: u16) -> usize {
+        (((segment as usize) << 4) + offset as usize) % MEMORY_SIZE
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn read_physical(&self, addr: usize) -> u8 {
+        self.bytes[addr % MEMORY_SIZE]
+    }
+
+    pub fn write_physical(&mut self, addr: usize, value: u8) {
+        let addr = addr % MEMORY_SIZE;
+
+        if let Some(journal) = &mut self.journal {
+            journal.push((addr, self.bytes[addr]));
+        }
+        self.bytes[addr] = value;
+    }
+
+    /// Starts recording the previous value of every byte written.
+    pub fn start_journal(&mut self) {
+        self.journal = Some(Vec::new());
+    }
+
+    /// Stops recording and returns the writes since [`Memory::start_journal`],
+    /// oldest first.
+    pub fn take_journal(&mut self) -> Vec<(usize, u8)> {
+        self.journal.take().unwrap_or_default()
+    }
+
+    /// Copies `image` into memory starting at physical address `addr`.
+    pub fn load(&mut self, addr: usize, image: &[u8]) {
+        for (i, byte) in image.iter().enumerate() {
+            self.write_physical(addr + i, *byte);
+        }
+    }
+
+    pub fn read_byte(&self, segment: u16, offset: u16) -> u8 {
+        self.read_physical(Self::physical_address(segment, offset))
+    }
+
+    pub fn write_byte(&mut self, segment: u16, offset: u16, value: u8) {
+        self.write_physical(Self::physical_address(segment, offset), value);
+    }
+
+    /// Reads a little-endian word. A word at offset 0xFFFF takes its high byte
+    /// from offset 0 of the same segment.
+    pub fn read_word(&self, segment: u16, offset: u16) -> u16 {
+        u16::from_le_bytes([
+            self.read_byte(segment, offset),
+            self.read_byte(segment, offset.wrapping_add(1)),
+        ])
+    }
+
+    pub fn write_word(&mut self, segment: u16, offset: u16, value: u16) {
+        let [lo, hi] = value.to_le_bytes();
+
+        self.write_byte(segment, offset, lo);
+        self.write_byte(segment, offset.wrapping_add(1), hi);
+    }
+
+    pub fn read(&self, segment: u16, offset: u16, w: bool) -> u16 {
+        if w {
+            self.read_word(segment, offset)
+        } else {
+            self.read_byte(segment, offset) as u16
+        }
+    }
+
+    pub fn write(&mut self, segment: u16, offset: u16, w: bool, value: u16) {
+        if w {
+            self.write_word(segment, offset, value);
+        } else {
+            self.write_byte(segment, offset, value as u8);
+        }
+    }
+
+    /// Copies `len` bytes starting at physical address `addr`, wrapping at the
+    /// 1 MiB boundary.
+    pub fn range(&self, addr: usize, len: usize) -> Vec<u8> {
+        (0..len).map(|i| self.read_physical(addr + i)).collect()
+    }
+
+    /// Writes `len` bytes starting at physical address `addr` to a raw binary
+    /// file.
+    pub fn dump(
+        &self,
+        addr: usize,
+        len: usize,
+        path: impl AsRef<Path>,
+    ) -> Result<(), Box<dyn Error>> {
+        fs::write(path, self.range(addr, len))?;
+
+        Ok(())
+    }
+
+    /// Writes the `width` x `height` image stored row by row at physical
+    /// address `addr` as a binary PPM file.
+    pub fn export_ppm(
+        &self,
+        addr: usize,
+        width: usize,
+        height: usize,
+        format: PixelFormat,
+        path: impl AsRef<Path>,
+    ) -> Result<(), Box<dyn Error>> {
+        let pixels = self.range(addr, width * height * format.bytes_per_pixel());
+
+        let mut res = format!("P6\n{width} {height}\n255\n").into_bytes();
+        for pixel in pixels.chunks_exact(format.bytes_per_pixel()) {
+            res.extend_from_slice(&pixel[..3]);
+        }
+
+        fs::write(path, res)?;
+
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use crate::register::Register;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Mode {
     Mem,
@@ -16,4 +18,33 @@ impl Mode {
             _ => unreachable!(),
         }
     }
+
+    /// Decodes the mod and r/m fields of the ModR/M byte at `bytes[1]`, along
+    /// with the displacement bytes that follow it.
+    pub(crate) fn decode_rm(bytes: &[u8], w: bool) -> (Self, Register, Option<u8>, Option<u8>) {
+        let rm = Register::decode_reg(bytes[1] & 0b0000_0111, w);
+        let mut mode = Mode::decode((bytes[1] & 0b1100_0000) >> 6);
+
+        if mode == Mode::Mem && (rm == Register::DH || rm == Register::SI) {
+            mode = Mode::DirectAddress
+        }
+
+        let (disp_lo, disp_hi) = match mode {
+            Mode::Reg | Mode::Mem => (None, None),
+            Mode::Mem8 => (Some(bytes[2]), None),
+            Mode::Mem16 | Mode::DirectAddress => (Some(bytes[2]), Some(bytes[3])),
+        };
+
+        (mode, rm, disp_lo, disp_hi)
+    }
+
+    pub(crate) fn displacement_len(&self) -> usize {
+        match self {
+            Mode::Reg => 0,
+            Mode::Mem => 0,
+            Mode::Mem8 => 1,
+            Mode::Mem16 => 2,
+            Mode::DirectAddress => 2,
+        }
+    }
 }
@@ -1,14 +1,17 @@
-use crate::{mode::Mode, register::Register};
+use crate::{
+    mode::Mode,
+    register::{Register, SegmentRegister},
+};
 
 #[derive(Debug)]
 pub struct RegisterMemoryToFromRegister {
-    d: bool,
-    w: bool,
-    mode: Mode,
-    reg: Register,
-    rm: Register,
-    disp_lo: Option<u8>,
-    disp_hi: Option<u8>,
+    pub(crate) d: bool,
+    pub(crate) w: bool,
+    pub(crate) mode: Mode,
+    pub(crate) reg: Register,
+    pub(crate) rm: Register,
+    pub(crate) disp_lo: Option<u8>,
+    pub(crate) disp_hi: Option<u8>,
 }
 
 impl RegisterMemoryToFromRegister {
@@ -16,14 +19,12 @@ impl RegisterMemoryToFromRegister {
         let d = (bytes[0] & 0b0000_0010) == 0b0000_0010;
         let w = (bytes[0] & 0b0000_0001) == 0b0000_0001;
 
-        let rm = Register::decode_reg((bytes[1] & 0b0000_0111) >> 0, w);
+        let rm = Register::decode_reg(bytes[1] & 0b0000_0111, w);
         let mode = {
             let mut mode = Mode::decode((bytes[1] & 0b1100_0000) >> 6);
 
-            if mode == Mode::Mem {
-                if rm == Register::DH || rm == Register::SI {
-                    mode = Mode::DirectAddress
-                }
+            if mode == Mode::Mem && (rm == Register::DH || rm == Register::SI) {
+                mode = Mode::DirectAddress
             }
 
             mode
@@ -81,74 +82,56 @@ impl RegisterMemoryToFromRegister {
             Mode::Mem => {
                 if self.d {
                     (
-                        self.rm.memory_mode_to_string(self.mode, None, None, self.w),
+                        self.rm.memory_mode_to_string(self.mode, None, None),
                         self.reg.register_mode_to_string(),
                     )
                 } else {
                     (
                         self.reg.register_mode_to_string(),
-                        self.rm.memory_mode_to_string(self.mode, None, None, self.w),
+                        self.rm.memory_mode_to_string(self.mode, None, None),
                     )
                 }
             }
             Mode::Mem8 => {
                 if self.d {
                     (
-                        self.rm
-                            .memory_mode_to_string(self.mode, self.disp_lo, None, self.w),
+                        self.rm.memory_mode_to_string(self.mode, self.disp_lo, None),
                         self.reg.register_mode_to_string(),
                     )
                 } else {
                     (
                         self.reg.register_mode_to_string(),
-                        self.rm
-                            .memory_mode_to_string(self.mode, self.disp_lo, None, self.w),
+                        self.rm.memory_mode_to_string(self.mode, self.disp_lo, None),
                     )
                 }
             }
             Mode::Mem16 => {
                 if self.d {
                     (
-                        self.rm.memory_mode_to_string(
-                            self.mode,
-                            self.disp_lo,
-                            self.disp_hi,
-                            self.w,
-                        ),
+                        self.rm
+                            .memory_mode_to_string(self.mode, self.disp_lo, self.disp_hi),
                         self.reg.register_mode_to_string(),
                     )
                 } else {
                     (
                         self.reg.register_mode_to_string(),
-                        self.rm.memory_mode_to_string(
-                            self.mode,
-                            self.disp_lo,
-                            self.disp_hi,
-                            self.w,
-                        ),
+                        self.rm
+                            .memory_mode_to_string(self.mode, self.disp_lo, self.disp_hi),
                     )
                 }
             }
             Mode::DirectAddress => {
                 if self.d {
                     (
-                        self.rm.memory_mode_to_string(
-                            self.mode,
-                            self.disp_lo,
-                            self.disp_hi,
-                            self.w,
-                        ),
+                        self.rm
+                            .memory_mode_to_string(self.mode, self.disp_lo, self.disp_hi),
                         self.reg.register_mode_to_string(),
                     )
                 } else {
                     (
                         self.reg.register_mode_to_string(),
-                        self.rm.memory_mode_to_string(
-                            self.mode,
-                            self.disp_lo,
-                            self.disp_hi,
-                            self.w,
-                        ),
+                        self.rm
+                            .memory_mode_to_string(self.mode, self.disp_lo, self.disp_hi),
                     )
                 }
             }
@@ -161,20 +144,20 @@ impl RegisterMemoryToFromRegister {
 
 #[derive(Debug)]
 pub struct ImmediateToRegister {
-    w: bool,
-    reg: Register,
-    data: u16,
+    pub(crate) w: bool,
+    pub(crate) reg: Register,
+    pub(crate) data: u16,
 }
 
 impl ImmediateToRegister {
     pub(crate) fn decode(bytes: &[u8]) -> Self {
         let w = bytes[0] & 0b0000_1000 == 0b0000_1000;
-        let reg = Register::decode_reg((bytes[0] & 0b0000_0111) >> 0, w);
+        let reg = Register::decode_reg(bytes[0] & 0b0000_0111, w);
 
         let data = if w {
             u16::from_le_bytes([bytes[1], bytes[2]])
         } else {
-            (bytes[1] as i8) as u16
+            bytes[1] as u16
         };
 
         Self { w, reg, data }
@@ -203,26 +186,24 @@ impl ImmediateToRegister {
 
 #[derive(Debug)]
 pub struct ImmediateToRegisterMemory {
-    w: bool,
-    mode: Mode,
-    rm: Register,
-    disp_lo: Option<u8>,
-    disp_hi: Option<u8>,
-    data: u16,
+    pub(crate) w: bool,
+    pub(crate) mode: Mode,
+    pub(crate) rm: Register,
+    pub(crate) disp_lo: Option<u8>,
+    pub(crate) disp_hi: Option<u8>,
+    pub(crate) data: u16,
 }
 
 impl ImmediateToRegisterMemory {
     pub(crate) fn decode(bytes: &[u8]) -> Self {
         let w = (bytes[0] & 0b0000_0001) == 0b0000_0001;
 
-        let rm = Register::decode_reg((bytes[1] & 0b0000_0111) >> 0, w);
+        let rm = Register::decode_reg(bytes[1] & 0b0000_0111, w);
         let mode = {
             let mut mode = Mode::decode((bytes[1] & 0b1100_0000) >> 6);
 
-            if mode == Mode::Mem {
-                if rm == Register::DH || rm == Register::SI {
-                    mode = Mode::DirectAddress
-                }
+            if mode == Mode::Mem && (rm == Register::DH || rm == Register::SI) {
+                mode = Mode::DirectAddress
             }
 
             mode
@@ -237,9 +218,7 @@ impl ImmediateToRegisterMemory {
         let data = if w {
             u16::from_le_bytes([bytes[2 + disp_offset], bytes[2 + disp_offset + 1]])
         } else {
-            let res = bytes[2 + disp_offset] as i8;
-            let res = res as i16;
-            res as u16
+            bytes[2 + disp_offset] as u16
         };
 
         Self {
@@ -273,26 +252,23 @@ impl ImmediateToRegisterMemory {
     pub(crate) fn to_string(&self) -> String {
         let mut res = String::new();
 
-        let dst = self
-            .rm
-            .memory_mode_to_string(self.mode, self.disp_lo, self.disp_hi, self.w);
-
-        let src = if self.w {
-            format!("word + {}", self.data)
-        } else {
-            format!("byte + {}", self.data)
+        let dst = self.rm.rm_to_string(self.mode, self.disp_lo, self.disp_hi);
+        let size = match (self.mode, self.w) {
+            (Mode::Reg, _) => "",
+            (_, true) => "word ",
+            (_, false) => "byte ",
         };
 
-        res.push_str(&format!("mov {}, {}\r\n", dst, src));
+        res.push_str(&format!("mov {}{}, {}\r\n", size, dst, self.data));
         res
     }
 }
 
 #[derive(Debug)]
 pub struct MemoryToAccumulator {
-    w: bool,
-    addr_lo: u8,
-    addr_hi: u8,
+    pub(crate) w: bool,
+    pub(crate) addr_lo: u8,
+    pub(crate) addr_hi: u8,
 }
 
 impl MemoryToAccumulator {
@@ -326,9 +302,9 @@ impl MemoryToAccumulator {
 }
 #[derive(Debug)]
 pub struct AccumulatorToMemory {
-    w: bool,
-    addr_lo: u8,
-    addr_hi: u8,
+    pub(crate) w: bool,
+    pub(crate) addr_lo: u8,
+    pub(crate) addr_hi: u8,
 }
 
 impl AccumulatorToMemory {
@@ -361,32 +337,117 @@ impl AccumulatorToMemory {
     }
 }
 #[derive(Debug)]
-pub struct RegisterMemoryToSegmentRegister {}
+pub struct RegisterMemoryToSegmentRegister {
+    pub(crate) mode: Mode,
+    pub(crate) sr: SegmentRegister,
+    pub(crate) rm: Register,
+    pub(crate) disp_lo: Option<u8>,
+    pub(crate) disp_hi: Option<u8>,
+}
 
 impl RegisterMemoryToSegmentRegister {
-    pub(crate) fn decode(_bytes: &[u8]) -> Self {
-        todo!()
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let rm = Register::decode_reg(bytes[1] & 0b0000_0111, true);
+        let mode = {
+            let mut mode = Mode::decode((bytes[1] & 0b1100_0000) >> 6);
+
+            if mode == Mode::Mem && rm == Register::SI {
+                mode = Mode::DirectAddress
+            }
+
+            mode
+        };
+        let sr = SegmentRegister::decode((bytes[1] & 0b0001_1000) >> 3);
+
+        let (disp_lo, disp_hi) = match mode {
+            Mode::Reg | Mode::Mem => (None, None),
+            Mode::Mem8 => (Some(bytes[2]), None),
+            Mode::Mem16 | Mode::DirectAddress => (Some(bytes[2]), Some(bytes[3])),
+        };
+
+        Self {
+            mode,
+            sr,
+            rm,
+            disp_lo,
+            disp_hi,
+        }
     }
 
     pub(crate) fn offset(&self) -> usize {
-        todo!()
+        let mut res = 2;
+
+        res += match self.mode {
+            Mode::Reg => 0,
+            Mode::Mem => 0,
+            Mode::Mem8 => 1,
+            Mode::Mem16 => 2,
+            Mode::DirectAddress => 2,
+        };
+
+        res
     }
+
     pub(crate) fn to_string(&self) -> String {
-        todo!()
+        let mut res = String::new();
+
+        let dst = self.sr.register_mode_to_string();
+        let src = self.rm.rm_to_string(self.mode, self.disp_lo, self.disp_hi);
+
+        res.push_str(&format!("mov {}, {}\r\n", dst, src));
+        res
     }
 }
+
 #[derive(Debug)]
-pub struct SegmentRegisterToRegisterMemory {}
+pub struct SegmentRegisterToRegisterMemory {
+    pub(crate) mode: Mode,
+    pub(crate) sr: SegmentRegister,
+    pub(crate) rm: Register,
+    pub(crate) disp_lo: Option<u8>,
+    pub(crate) disp_hi: Option<u8>,
+}
 
 impl SegmentRegisterToRegisterMemory {
-    pub(crate) fn decode(_bytes: &[u8]) -> Self {
-        todo!()
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let RegisterMemoryToSegmentRegister {
+            mode,
+            sr,
+            rm,
+            disp_lo,
+            disp_hi,
+        } = RegisterMemoryToSegmentRegister::decode(bytes);
+
+        Self {
+            mode,
+            sr,
+            rm,
+            disp_lo,
+            disp_hi,
+        }
     }
 
     pub(crate) fn offset(&self) -> usize {
-        todo!()
+        let mut res = 2;
+
+        res += match self.mode {
+            Mode::Reg => 0,
+            Mode::Mem => 0,
+            Mode::Mem8 => 1,
+            Mode::Mem16 => 2,
+            Mode::DirectAddress => 2,
+        };
+
+        res
     }
+
     pub(crate) fn to_string(&self) -> String {
-        todo!()
+        let mut res = String::new();
+
+        let dst = self.rm.rm_to_string(self.mode, self.disp_lo, self.disp_hi);
+        let src = self.sr.register_mode_to_string();
+
+        res.push_str(&format!("mov {}, {}\r\n", dst, src));
+        res
     }
 }
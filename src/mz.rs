@@ -0,0 +1,116 @@
+use std::error::Error;
+
+/// Size of the fixed part of the MZ header, up to the overlay number.
+const HEADER_SIZE: usize = 0x1C;
+
+/// The fixed part of an MZ .EXE header. Sizes are in the units DOS uses:
+/// 512-byte pages and 16-byte paragraphs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MzHeader {
+    /// Bytes used in the last page, or 0 if it is full.
+    pub last_page_bytes: u16,
+    /// Pages in the file, including the header and the partial last page.
+    pub pages: u16,
+    pub relocation_count: u16,
+    pub header_paragraphs: u16,
+    /// Paragraphs the program needs beyond its image, and would like.
+    pub min_alloc: u16,
+    pub max_alloc: u16,
+    /// Initial SS and CS are relative to the start of the image.
+    pub ss: u16,
+    pub sp: u16,
+    pub checksum: u16,
+    pub ip: u16,
+    pub cs: u16,
+    /// File offset of the relocation table.
+    pub relocation_offset: u16,
+    pub overlay: u16,
+}
+
+/// A parsed .EXE: its header, the `offset:segment` of every word the loader
+/// adds the load segment to, and the load image that follows the header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Executable {
+    pub header: MzHeader,
+    /// Relocations as `(offset, segment)`, relative to the start of the
+    /// image.
+    pub relocations: Vec<(u16, u16)>,
+    pub image: Vec<u8>,
+}
+
+impl Executable {
+    pub fn parse(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if bytes.len() < HEADER_SIZE || !matches!(&bytes[..2], b"MZ" | b"ZM") {
+            return Err("Not an MZ executable".into());
+        }
+
+        let word = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        let header = MzHeader {
+            last_page_bytes: word(0x02),
+            pages: word(0x04),
+            relocation_count: word(0x06),
+            header_paragraphs: word(0x08),
+            min_alloc: word(0x0A),
+            max_alloc: word(0x0C),
+            ss: word(0x0E),
+            sp: word(0x10),
+            checksum: word(0x12),
+            ip: word(0x14),
+            cs: word(0x16),
+            relocation_offset: word(0x18),
+            overlay: word(0x1A),
+        };
+
+        let file_size = header.file_size();
+        let header_size = 16 * header.header_paragraphs as usize;
+        if file_size > bytes.len() || header_size > file_size {
+            return Err(format!(
+                "MZ header describes {file_size} bytes with a {header_size} byte header, the file has {}",
+                bytes.len()
+            )
+            .into());
+        }
+
+        let table = header.relocation_offset as usize;
+        let table_end = table + 4 * header.relocation_count as usize;
+        if table_end > header_size {
+            return Err("MZ relocation table extends past the header".into());
+        }
+
+        let relocations = (table..table_end)
+            .step_by(4)
+            .map(|entry| (word(entry), word(entry + 2)))
+            .collect();
+
+        Ok(Self {
+            header,
+            relocations,
+            image: bytes[header_size..file_size].to_vec(),
+        })
+    }
+
+    /// Offset of the entry point CS:IP in the image.
+    pub fn entry(&self) -> usize {
+        16 * self.header.cs as usize + self.header.ip as usize
+    }
+
+    /// Offsets of the relocated words in the image.
+    pub fn relocation_offsets(&self) -> Vec<usize> {
+        self.relocations
+            .iter()
+            .map(|(offset, segment)| 16 * *segment as usize + *offset as usize)
+            .collect()
+    }
+}
+
+impl MzHeader {
+    /// Size of the file DOS loads, header included.
+    pub fn file_size(&self) -> usize {
+        let size = 512 * self.pages as usize;
+
+        match self.last_page_bytes {
+            0 => size,
+            bytes => size.saturating_sub(512) + bytes as usize,
+        }
+    }
+}
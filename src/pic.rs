@@ -0,0 +1,193 @@
+use crate::io::IoDevice;
+
+/// Ports of the command and data registers.
+pub const PORTS: std::ops::RangeInclusive<u16> = 0x20..=0x21;
+
+/// Which initialization command word the data port expects next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Init {
+    Done,
+    Icw2,
+    Icw3,
+    Icw4,
+}
+
+/// An 8259 programmable interrupt controller on ports 20h-21h, as the
+/// single master of a PC. It takes ICW1-ICW4, the mask in OCW1, EOIs and
+/// priority rotation in OCW2, and the IRR/ISR read select of OCW3.
+/// Requests are edge triggered; polling and special mask mode aren't
+/// emulated.
+///
+/// It starts the way the BIOS leaves it: IRQs 0-7 on vectors 08h-0Fh, none
+/// masked, fully nested with IRQ0 the highest priority.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pic {
+    /// Interrupt request, in-service and mask registers, bit n for IRQn.
+    irr: u8,
+    isr: u8,
+    imr: u8,
+    /// Vector of IRQ0, set by ICW2.
+    base: u8,
+    /// IRQ with the lowest priority; the one after it has the highest.
+    lowest: u8,
+    init: Init,
+    single: bool,
+    needs_icw4: bool,
+    auto_eoi: bool,
+    /// Whether reading the command port returns the ISR rather than the IRR.
+    read_isr: bool,
+}
+
+impl Default for Pic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pic {
+    pub fn new() -> Self {
+        Self {
+            irr: 0,
+            isr: 0,
+            imr: 0,
+            base: 0x08,
+            lowest: 7,
+            init: Init::Done,
+            single: true,
+            needs_icw4: true,
+            auto_eoi: false,
+            read_isr: false,
+        }
+    }
+
+    /// Latches a request on `irq`, as a rising edge on its input does.
+    pub fn request(&mut self, irq: u8) {
+        self.irr |= 1 << irq;
+    }
+
+    /// Whether `irq` is masked.
+    pub fn masked(&self, irq: u8) -> bool {
+        self.imr & (1 << irq) != 0
+    }
+
+    /// The unmasked request the PIC would raise INTR for: the highest
+    /// priority one, if it outranks every interrupt in service.
+    pub fn pending(&self) -> Option<u8> {
+        let requests = self.irr & !self.imr;
+
+        for irq in self.by_priority() {
+            if self.isr & (1 << irq) != 0 {
+                return None;
+            }
+            if requests & (1 << irq) != 0 {
+                return Some(irq);
+            }
+        }
+
+        None
+    }
+
+    /// The processor's interrupt acknowledge: moves the pending request in
+    /// service and returns its vector.
+    pub fn acknowledge(&mut self) -> Option<u8> {
+        let irq = self.pending()?;
+
+        self.irr &= !(1 << irq);
+        if !self.auto_eoi {
+            self.isr |= 1 << irq;
+        }
+
+        Some(self.base.wrapping_add(irq))
+    }
+
+    /// IRQs from the highest priority to the lowest.
+    fn by_priority(&self) -> impl Iterator<Item = u8> {
+        let highest = (self.lowest + 1) % 8;
+
+        (0..8).map(move |i| (highest + i) % 8)
+    }
+
+    /// The in-service IRQ with the highest priority.
+    fn highest_in_service(&self) -> Option<u8> {
+        self.by_priority().find(|irq| self.isr & (1 << irq) != 0)
+    }
+
+    /// OCW2: the EOI and rotation commands in bits 7-5, with the IRQ in bits
+    /// 2-0 for the specific ones.
+    fn ocw2(&mut self, value: u8) {
+        let level = value & 0b111;
+
+        match value >> 5 {
+            // Non-specific EOI, and with rotation.
+            0b001 | 0b101 => {
+                if let Some(irq) = self.highest_in_service() {
+                    self.isr &= !(1 << irq);
+                    if value >> 5 == 0b101 {
+                        self.lowest = irq;
+                    }
+                }
+            }
+            // Specific EOI, and with rotation.
+            0b011 | 0b111 => {
+                self.isr &= !(1 << level);
+                if value >> 5 == 0b111 {
+                    self.lowest = level;
+                }
+            }
+            // Set priority.
+            0b110 => self.lowest = level,
+            _ => {}
+        }
+    }
+}
+
+impl IoDevice for Pic {
+    fn read_byte(&mut self, port: u16) -> u8 {
+        match port & 1 {
+            0 if self.read_isr => self.isr,
+            0 => self.irr,
+            _ => self.imr,
+        }
+    }
+
+    fn write_byte(&mut self, port: u16, value: u8) {
+        match (port & 1, self.init) {
+            (0, _) if value & 0x10 != 0 => {
+                // ICW1 resets the controller and starts the sequence.
+                *self = Self {
+                    single: value & 0x02 != 0,
+                    needs_icw4: value & 0x01 != 0,
+                    init: Init::Icw2,
+                    ..Self::new()
+                };
+            }
+            (0, _) if value & 0x08 != 0 => {
+                // OCW3.
+                if value & 0x02 != 0 {
+                    self.read_isr = value & 0x01 != 0;
+                }
+            }
+            (0, _) => self.ocw2(value),
+            (_, Init::Icw2) => {
+                self.base = value & 0xF8;
+                self.init = match (self.single, self.needs_icw4) {
+                    (false, _) => Init::Icw3,
+                    (true, true) => Init::Icw4,
+                    (true, false) => Init::Done,
+                };
+            }
+            (_, Init::Icw3) => {
+                self.init = if self.needs_icw4 {
+                    Init::Icw4
+                } else {
+                    Init::Done
+                };
+            }
+            (_, Init::Icw4) => {
+                self.auto_eoi = value & 0x02 != 0;
+                self.init = Init::Done;
+            }
+            (_, Init::Done) => self.imr = value,
+        }
+    }
+}
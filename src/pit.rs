@@ -0,0 +1,312 @@
+use crate::io::IoDevice;
+
+/// The PIT counts at 1.193182 MHz, a quarter of the 4.77 MHz processor
+/// clock of the IBM PC.
+pub const CLOCKS_PER_TICK: u64 = 4;
+
+/// Ports of the counters and the control word register.
+pub const PORTS: std::ops::RangeInclusive<u16> = 0x40..=0x43;
+
+/// How a counter's count is read and written through its port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Lo,
+    Hi,
+    /// Low byte, then high byte.
+    LoHi,
+}
+
+/// One of the three counters of an 8253.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Counter {
+    mode: u8,
+    access: Access,
+    /// Count written by the program; 0 counts 65536.
+    reload: u16,
+    /// Ticks left until the counter next reaches its terminal count, or the
+    /// current half period in mode 3.
+    count: u32,
+    /// Whether a count has been loaded since the mode was programmed.
+    running: bool,
+    output: bool,
+    /// Count frozen by a latch command until it has been read.
+    latch: Option<u16>,
+    /// Whether the next byte written, and read, is the high byte of a
+    /// [`Access::LoHi`] count.
+    write_hi: bool,
+    read_hi: bool,
+    /// Low byte of a [`Access::LoHi`] count being written.
+    lo: u8,
+}
+
+impl Counter {
+    fn new(mode: u8, access: Access) -> Self {
+        Self {
+            mode,
+            access,
+            reload: 0,
+            count: 0,
+            running: false,
+            output: mode != 0,
+            latch: None,
+            write_hi: false,
+            read_hi: false,
+            lo: 0,
+        }
+    }
+
+    fn period(&self) -> u32 {
+        match self.reload {
+            0 => 0x10000,
+            reload => reload as u32,
+        }
+    }
+
+    /// Length of the half of the square wave the output is in: the high
+    /// half gets the extra tick of an odd count.
+    fn half_period(&self) -> u32 {
+        let period = self.period();
+
+        if self.output {
+            period.div_ceil(2)
+        } else {
+            period / 2
+        }
+    }
+
+    fn load(&mut self, reload: u16) {
+        self.reload = reload;
+
+        match self.mode {
+            0 => {
+                self.count = self.period();
+                self.output = false;
+                self.running = true;
+            }
+            // A running rate generator or square wave picks the new count
+            // up when it next reloads.
+            2 | 3 if self.running => {}
+            2 => {
+                self.count = self.period();
+                self.running = true;
+            }
+            3 => {
+                self.output = true;
+                self.count = self.half_period();
+                self.running = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// The value a read returns: the count in modes 0 and 2, and in mode 3
+    /// the count that decrements by two each tick within a half period.
+    fn value(&self) -> u16 {
+        match self.mode {
+            3 => (2 * self.count) as u16,
+            _ => self.count as u16,
+        }
+    }
+
+    /// Counts `ticks` down and returns how many times the output rose.
+    fn advance(&mut self, ticks: u64) -> u64 {
+        if !self.running {
+            return 0;
+        }
+
+        let mut edges = 0;
+        let mut ticks = ticks;
+
+        while ticks > 0 {
+            let step = ticks.min(self.count as u64);
+            ticks -= step;
+            self.count -= step as u32;
+            if self.count > 0 {
+                break;
+            }
+
+            match self.mode {
+                0 => {
+                    // After its terminal count the counter wraps around
+                    // and keeps counting, with the output left high.
+                    if !self.output {
+                        self.output = true;
+                        edges += 1;
+                    }
+                    self.count = 0x10000;
+                }
+                2 => {
+                    // The output pulses low for the last tick of each
+                    // period, rising again as the count reloads.
+                    edges += 1;
+                    self.count = self.period();
+                }
+                _ => {
+                    self.output = !self.output;
+                    if self.output {
+                        edges += 1;
+                    }
+                    self.count = self.half_period();
+                }
+            }
+        }
+
+        edges
+    }
+
+    /// Ticks until the output next rises, if it ever will.
+    fn until_edge(&self) -> Option<u64> {
+        match self.mode {
+            _ if !self.running => None,
+            0 if self.output => None,
+            3 if self.output => {
+                let next = Self {
+                    output: false,
+                    ..self.clone()
+                };
+
+                Some(self.count as u64 + next.half_period() as u64)
+            }
+            0 | 2 | 3 => Some(self.count as u64),
+            _ => None,
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        let value = self.latch.unwrap_or_else(|| self.value());
+        let [lo, hi] = value.to_le_bytes();
+
+        match self.access {
+            Access::Lo => {
+                self.latch = None;
+                lo
+            }
+            Access::Hi => {
+                self.latch = None;
+                hi
+            }
+            Access::LoHi => {
+                self.read_hi = !self.read_hi;
+                if self.read_hi {
+                    lo
+                } else {
+                    self.latch = None;
+                    hi
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, value: u8) {
+        match self.access {
+            Access::Lo => self.load(value as u16),
+            Access::Hi => self.load((value as u16) << 8),
+            Access::LoHi => {
+                self.write_hi = !self.write_hi;
+                if self.write_hi {
+                    self.lo = value;
+                    // Writing the first byte stops a mode 0 count until the
+                    // second arrives.
+                    if self.mode == 0 {
+                        self.running = false;
+                    }
+                } else {
+                    self.load(u16::from_le_bytes([self.lo, value]));
+                }
+            }
+        }
+    }
+}
+
+/// An 8253 programmable interval timer on ports 40h-43h, driven by the
+/// simulator's clock estimate. Counters support mode 0 (interrupt on
+/// terminal count), 2 (rate generator) and 3 (square wave); 6 and 7 are
+/// the same as 2 and 3, and the gated modes 1, 4 and 5 never start, as
+/// nothing drives the gates. Counts are binary: the BCD bit is ignored.
+///
+/// Counter 0's output is IRQ0. It starts the way the BIOS leaves it, in
+/// mode 3 with a count of 65536, for the 18.2 Hz clock tick.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pit {
+    counters: [Counter; 3],
+}
+
+impl Default for Pit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pit {
+    pub fn new() -> Self {
+        let mut timer = Counter::new(3, Access::LoHi);
+        timer.load(0);
+
+        Self {
+            counters: [
+                timer,
+                Counter::new(0, Access::LoHi),
+                Counter::new(0, Access::LoHi),
+            ],
+        }
+    }
+
+    /// Counts `ticks` down on every counter and returns whether counter 0's
+    /// output rose, requesting IRQ0.
+    pub fn advance(&mut self, ticks: u64) -> bool {
+        let edges = self.counters[0].advance(ticks);
+        for counter in &mut self.counters[1..] {
+            counter.advance(ticks);
+        }
+
+        edges > 0
+    }
+
+    /// Ticks until counter 0 next requests IRQ0, if it ever will.
+    pub fn until_irq(&self) -> Option<u64> {
+        self.counters[0].until_edge()
+    }
+
+    fn control(&mut self, value: u8) {
+        let select = (value >> 6) as usize;
+        // The 8254's read-back command; the 8253 doesn't have it.
+        if select == 3 {
+            return;
+        }
+
+        let counter = &mut self.counters[select];
+        let access = match (value >> 4) & 0b11 {
+            0 => {
+                if counter.latch.is_none() {
+                    counter.latch = Some(counter.value());
+                }
+                return;
+            }
+            1 => Access::Lo,
+            2 => Access::Hi,
+            _ => Access::LoHi,
+        };
+        let mode = match (value >> 1) & 0b111 {
+            mode @ 6..=7 => mode - 4,
+            mode => mode,
+        };
+
+        *counter = Counter::new(mode, access);
+    }
+}
+
+impl IoDevice for Pit {
+    fn read_byte(&mut self, port: u16) -> u8 {
+        match port & 0b11 {
+            3 => 0xFF,
+            counter => self.counters[counter as usize].read(),
+        }
+    }
+
+    fn write_byte(&mut self, port: u16, value: u8) {
+        match port & 0b11 {
+            3 => self.control(value),
+            counter => self.counters[counter as usize].write(value),
+        }
+    }
+}
@@ -83,6 +83,28 @@ impl Register {
         }
     }
 
+    /// Looks up a register by its assembly name, e.g. `al` or `bp`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        (0..8)
+            .flat_map(|bits| [Self::decode_reg(bits, false), Self::decode_reg(bits, true)])
+            .find(|reg| reg.register_mode_to_string() == name)
+    }
+
+    /// Index of the 16-bit register this register is part of, in encoding
+    /// order (ax, cx, dx, bx, sp, bp, si, di).
+    pub fn index(&self) -> usize {
+        match self {
+            Register::AL | Register::AH | Register::AX => 0,
+            Register::CL | Register::CH | Register::CX => 1,
+            Register::DL | Register::DH | Register::DX => 2,
+            Register::BL | Register::BH | Register::BX => 3,
+            Register::SP => 4,
+            Register::BP => 5,
+            Register::SI => 6,
+            Register::DI => 7,
+        }
+    }
+
     pub fn register_mode_to_string(&self) -> String {
         match self {
             Register::AL => "al".to_string(),
@@ -104,59 +126,46 @@ impl Register {
         }
     }
 
-    pub(crate) fn memory_mode_to_string(
+    pub(crate) fn rm_to_string(
         &self,
         mode: Mode,
         disp_lo: Option<u8>,
         disp_hi: Option<u8>,
-        w: bool,
     ) -> String {
         match mode {
-            Mode::Mem => match self {
-                Register::AL | Register::AX => "[bx + si]".to_string(),
-                Register::CL | Register::CX => "[bx + di]".to_string(),
-                Register::DL | Register::DX => "[bp + si]".to_string(),
-                Register::BL | Register::BX => "[bp + di]".to_string(),
-                Register::AH | Register::SP => "[si]".to_string(),
-                Register::CH | Register::BP => "[di]".to_string(),
-                Register::DH | Register::SI => "[bp]".to_string(),
-                Register::BH | Register::DI => "[bx]".to_string(),
-            },
-            Mode::Mem8 => {
-                let d8 = if w {
-                    let d8 = disp_lo.unwrap() as i8;
-                    let d8 = d8 as i16;
-                    let d8 = d8 as i8;
-                    d8 as u16
-                } else {
-                    disp_lo.unwrap() as u16
-                };
+            Mode::Reg => self.register_mode_to_string(),
+            _ => self.memory_mode_to_string(mode, disp_lo, disp_hi),
+        }
+    }
 
-                match self {
-                    Register::AL | Register::AX => format!("[bx + si + {d8}]"),
-                    Register::CL | Register::CX => format!("[bx + di + {d8}]"),
-                    Register::DL | Register::DX => format!("[bp + si + {d8}]"),
-                    Register::BL | Register::BX => format!("[bp + di + {d8}]"),
-                    Register::AH | Register::SP => format!("[si + {d8}]"),
-                    Register::CH | Register::BP => format!("[di + {d8}]"),
-                    Register::DH | Register::SI => format!("[bp + {d8}]"),
-                    Register::BH | Register::DI => format!("[bx + {d8}]"),
-                }
-            }
-            Mode::Mem16 => {
-                let d16 = u16::from_le_bytes([disp_lo.unwrap(), disp_hi.unwrap()]);
-                match self {
-                    Register::AL | Register::AX => format!("[bx + si + {d16}]"),
-                    Register::CL | Register::CX => format!("[bx + di + {d16}]"),
-                    Register::DL | Register::DX => format!("[bp + si + {d16}]"),
-                    Register::BL | Register::BX => format!("[bp + di + {d16}]"),
-                    Register::AH | Register::SP => format!("[si + {d16}]"),
-                    Register::CH | Register::BP => format!("[di + {d16}]"),
-                    Register::DH | Register::SI => format!("[bp + {d16}]"),
-                    Register::BH | Register::DI => format!("[bx + {d16}]"),
-                }
-            }
+    /// Memory operand for `mode`, e.g. `[bp + di - 37]`. Displacements are
+    /// sign-extended, so they are shown as signed offsets from the base.
+    pub(crate) fn memory_mode_to_string(
+        &self,
+        mode: Mode,
+        disp_lo: Option<u8>,
+        disp_hi: Option<u8>,
+    ) -> String {
+        let base = match self {
+            Register::AL | Register::AX => "bx + si",
+            Register::CL | Register::CX => "bx + di",
+            Register::DL | Register::DX => "bp + si",
+            Register::BL | Register::BX => "bp + di",
+            Register::AH | Register::SP => "si",
+            Register::CH | Register::BP => "di",
+            Register::DH | Register::SI => "bp",
+            Register::BH | Register::DI => "bx",
+        };
+
+        let displaced = |disp: i16| {
+            let sign = if disp < 0 { '-' } else { '+' };
+            format!("[{base} {sign} {}]", disp.unsigned_abs())
+        };
 
+        match mode {
+            Mode::Mem => format!("[{base}]"),
+            Mode::Mem8 => displaced(disp_lo.unwrap() as i8 as i16),
+            Mode::Mem16 => displaced(i16::from_le_bytes([disp_lo.unwrap(), disp_hi.unwrap()])),
             Mode::Reg => unreachable!(),
             Mode::DirectAddress => format!(
                 "[{}]",
@@ -165,3 +174,39 @@ impl Register {
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentRegister {
+    ES,
+    CS,
+    SS,
+    DS,
+}
+
+impl SegmentRegister {
+    pub fn decode(bits: u8) -> Self {
+        match bits {
+            0b00 => Self::ES,
+            0b01 => Self::CS,
+            0b10 => Self::SS,
+            0b11 => Self::DS,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Looks up a segment register by its assembly name, e.g. `ds`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        (0..4)
+            .map(Self::decode)
+            .find(|sr| sr.register_mode_to_string() == name)
+    }
+
+    pub fn register_mode_to_string(&self) -> String {
+        match self {
+            SegmentRegister::ES => "es".to_string(),
+            SegmentRegister::CS => "cs".to_string(),
+            SegmentRegister::SS => "ss".to_string(),
+            SegmentRegister::DS => "ds".to_string(),
+        }
+    }
+}
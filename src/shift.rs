@@ -0,0 +1,99 @@
+use crate::{mode::Mode, register::Register};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShiftOp {
+    Rol,
+    Ror,
+    Rcl,
+    Rcr,
+    Shl,
+    Shr,
+    Sar,
+}
+
+impl ShiftOp {
+    /// Decodes the reg field of `110100vw`. `110` is an undocumented alias
+    /// of `shl` that assemblers never emit, so it is left undecoded.
+    pub fn decode(bits: u8) -> Option<Self> {
+        match bits {
+            0b000 => Some(Self::Rol),
+            0b001 => Some(Self::Ror),
+            0b010 => Some(Self::Rcl),
+            0b011 => Some(Self::Rcr),
+            0b100 => Some(Self::Shl),
+            0b101 => Some(Self::Shr),
+            0b111 => Some(Self::Sar),
+            _ => None,
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            ShiftOp::Rol => "rol",
+            ShiftOp::Ror => "ror",
+            ShiftOp::Rcl => "rcl",
+            ShiftOp::Rcr => "rcr",
+            ShiftOp::Shl => "shl",
+            ShiftOp::Shr => "shr",
+            ShiftOp::Sar => "sar",
+        }
+    }
+}
+
+/// Shifts and rotates of a register or memory operand by 1 or by CL
+/// (`110100vw`).
+#[derive(Debug)]
+pub struct Shift {
+    pub(crate) op: ShiftOp,
+    /// Shift by CL rather than by 1.
+    pub(crate) v: bool,
+    pub(crate) w: bool,
+    pub(crate) mode: Mode,
+    pub(crate) rm: Register,
+    pub(crate) disp_lo: Option<u8>,
+    pub(crate) disp_hi: Option<u8>,
+}
+
+impl Shift {
+    pub(crate) fn decode(bytes: &[u8], op: ShiftOp) -> Self {
+        let v = (bytes[0] & 0b0000_0010) == 0b0000_0010;
+        let w = (bytes[0] & 0b0000_0001) == 0b0000_0001;
+
+        let (mode, rm, disp_lo, disp_hi) = Mode::decode_rm(bytes, w);
+
+        Self {
+            op,
+            v,
+            w,
+            mode,
+            rm,
+            disp_lo,
+            disp_hi,
+        }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        2 + self.mode.displacement_len()
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        let size = match (self.mode, self.w) {
+            (Mode::Reg, _) => "",
+            (_, true) => "word ",
+            (_, false) => "byte ",
+        };
+        let rm = self.rm.rm_to_string(self.mode, self.disp_lo, self.disp_hi);
+        let count = if self.v { "cl" } else { "1" };
+
+        res.push_str(&format!(
+            "{} {}{}, {}\r\n",
+            self.op.mnemonic(),
+            size,
+            rm,
+            count
+        ));
+        res
+    }
+}
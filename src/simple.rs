@@ -0,0 +1,89 @@
+/// Instructions encoded in a single byte without operands, apart from `hlt`,
+/// which has its own [`crate::instruction::Instruction::Halt`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimpleOp {
+    Nop,
+    Xlat,
+    Lahf,
+    Sahf,
+    Pushf,
+    Popf,
+    Cbw,
+    Cwd,
+    Aaa,
+    Daa,
+    Aas,
+    Das,
+    Int3,
+    Into,
+    Iret,
+    Clc,
+    Cmc,
+    Stc,
+    Cld,
+    Std,
+    Cli,
+    Sti,
+    Wait,
+}
+
+impl SimpleOp {
+    pub fn decode(byte: u8) -> Option<Self> {
+        let res = match byte {
+            0x90 => Self::Nop,
+            0xD7 => Self::Xlat,
+            0x9F => Self::Lahf,
+            0x9E => Self::Sahf,
+            0x9C => Self::Pushf,
+            0x9D => Self::Popf,
+            0x98 => Self::Cbw,
+            0x99 => Self::Cwd,
+            0x37 => Self::Aaa,
+            0x27 => Self::Daa,
+            0x3F => Self::Aas,
+            0x2F => Self::Das,
+            0xCC => Self::Int3,
+            0xCE => Self::Into,
+            0xCF => Self::Iret,
+            0xF8 => Self::Clc,
+            0xF5 => Self::Cmc,
+            0xF9 => Self::Stc,
+            0xFC => Self::Cld,
+            0xFD => Self::Std,
+            0xFA => Self::Cli,
+            0xFB => Self::Sti,
+            0x9B => Self::Wait,
+            _ => return None,
+        };
+
+        Some(res)
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            SimpleOp::Nop => "nop",
+            SimpleOp::Xlat => "xlatb",
+            SimpleOp::Lahf => "lahf",
+            SimpleOp::Sahf => "sahf",
+            SimpleOp::Pushf => "pushf",
+            SimpleOp::Popf => "popf",
+            SimpleOp::Cbw => "cbw",
+            SimpleOp::Cwd => "cwd",
+            SimpleOp::Aaa => "aaa",
+            SimpleOp::Daa => "daa",
+            SimpleOp::Aas => "aas",
+            SimpleOp::Das => "das",
+            SimpleOp::Int3 => "int3",
+            SimpleOp::Into => "into",
+            SimpleOp::Iret => "iret",
+            SimpleOp::Clc => "clc",
+            SimpleOp::Cmc => "cmc",
+            SimpleOp::Stc => "stc",
+            SimpleOp::Cld => "cld",
+            SimpleOp::Std => "std",
+            SimpleOp::Cli => "cli",
+            SimpleOp::Sti => "sti",
+            SimpleOp::Wait => "wait",
+        }
+    }
+}
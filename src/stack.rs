@@ -0,0 +1,134 @@
+use crate::{
+    mode::Mode,
+    register::{Register, SegmentRegister},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackOp {
+    Push,
+    Pop,
+}
+
+impl StackOp {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            StackOp::Push => "push",
+            StackOp::Pop => "pop",
+        }
+    }
+}
+
+/// `push`/`pop` of a register or memory word (`FF /6`, `8F /0`).
+#[derive(Debug)]
+pub struct RegisterMemory {
+    pub(crate) op: StackOp,
+    pub(crate) mode: Mode,
+    pub(crate) rm: Register,
+    pub(crate) disp_lo: Option<u8>,
+    pub(crate) disp_hi: Option<u8>,
+}
+
+impl RegisterMemory {
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let op = match bytes[0] {
+            0xFF => StackOp::Push,
+            0x8F => StackOp::Pop,
+            _ => unreachable!(),
+        };
+
+        let (mode, rm, disp_lo, disp_hi) = Mode::decode_rm(bytes, true);
+
+        Self {
+            op,
+            mode,
+            rm,
+            disp_lo,
+            disp_hi,
+        }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        2 + self.mode.displacement_len()
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        let size = if self.mode == Mode::Reg { "" } else { "word " };
+        let rm = self.rm.rm_to_string(self.mode, self.disp_lo, self.disp_hi);
+
+        res.push_str(&format!("{} {}{}\r\n", self.op.mnemonic(), size, rm));
+        res
+    }
+}
+
+/// `push`/`pop` of a word register encoded in the opcode (`01010reg`,
+/// `01011reg`).
+#[derive(Debug)]
+pub struct WordRegister {
+    pub(crate) op: StackOp,
+    pub(crate) reg: Register,
+}
+
+impl WordRegister {
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let op = if bytes[0] & 0b0000_1000 == 0 {
+            StackOp::Push
+        } else {
+            StackOp::Pop
+        };
+        let reg = Register::decode_reg(bytes[0] & 0b0000_0111, true);
+
+        Self { op, reg }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        1
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        res.push_str(&format!(
+            "{} {}\r\n",
+            self.op.mnemonic(),
+            self.reg.register_mode_to_string()
+        ));
+        res
+    }
+}
+
+/// `push`/`pop` of a segment register (`000sr110`, `000sr111`).
+#[derive(Debug)]
+pub struct Segment {
+    pub(crate) op: StackOp,
+    pub(crate) sr: SegmentRegister,
+}
+
+impl Segment {
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let op = if bytes[0] & 0b0000_0001 == 0 {
+            StackOp::Push
+        } else {
+            StackOp::Pop
+        };
+        let sr = SegmentRegister::decode((bytes[0] & 0b0001_1000) >> 3);
+
+        Self { op, sr }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        1
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        res.push_str(&format!(
+            "{} {}\r\n",
+            self.op.mnemonic(),
+            self.sr.register_mode_to_string()
+        ));
+        res
+    }
+}
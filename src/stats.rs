@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+
+use crate::{instruction::Instruction, mode::Mode};
+
+/// Register names as operands spell them.
+const REGISTERS: [&str; 20] = [
+    "ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "al", "cl", "dl", "bl", "ah", "ch", "dh", "bh",
+    "es", "cs", "ss", "ds",
+];
+
+/// Static statistics of a stream of decoded instructions: how often each
+/// mnemonic, length, addressing mode and register occurs, and how many
+/// instructions are prefixed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statistics {
+    pub instructions: usize,
+    pub bytes: usize,
+    /// Instructions by mnemonic, with prefixes left out (`rep movsb` is a
+    /// `movsb`).
+    pub mnemonics: BTreeMap<String, usize>,
+    /// Instructions by length in bytes, prefixes included.
+    pub lengths: BTreeMap<usize, usize>,
+    /// Instructions by the addressing mode of their ModR/M or direct address
+    /// operand, in the order `Reg`, `Mem`, `Mem8`, `Mem16`, `DirectAddress`.
+    pub modes: Vec<(Mode, usize)>,
+    /// Instructions that name each register in their operands, addresses
+    /// and segment overrides included. Implicit operands, like `si` and `di`
+    /// of string instructions, aren't counted.
+    pub registers: BTreeMap<String, usize>,
+    /// Instructions with a segment override, repeat or lock prefix.
+    pub prefixed: usize,
+}
+
+impl Default for Statistics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Statistics {
+    pub fn new() -> Self {
+        Self {
+            instructions: 0,
+            bytes: 0,
+            mnemonics: BTreeMap::new(),
+            lengths: BTreeMap::new(),
+            modes: [
+                Mode::Reg,
+                Mode::Mem,
+                Mode::Mem8,
+                Mode::Mem16,
+                Mode::DirectAddress,
+            ]
+            .into_iter()
+            .map(|mode| (mode, 0))
+            .collect(),
+            registers: BTreeMap::new(),
+            prefixed: 0,
+        }
+    }
+
+    pub(crate) fn add(&mut self, instruction: &Instruction) {
+        let len = instruction.offset();
+        self.instructions += 1;
+        self.bytes += len;
+
+        *self.mnemonics.entry(instruction.mnemonic()).or_default() += 1;
+        *self.lengths.entry(len).or_default() += 1;
+
+        if let Some(mode) = instruction.mode() {
+            if let Some((_, count)) = self.modes.iter_mut().find(|(m, _)| *m == mode) {
+                *count += 1;
+            }
+        }
+
+        let text = instruction.to_string();
+        let mut named: Vec<&str> = text
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|word| REGISTERS.contains(word))
+            .collect();
+        named.sort_unstable();
+        named.dedup();
+        for register in named {
+            *self.registers.entry(register.to_string()).or_default() += 1;
+        }
+
+        if instruction.is_prefixed() {
+            self.prefixed += 1;
+        }
+    }
+
+    /// Share of the instructions, in percent.
+    fn percent(&self, count: usize) -> f64 {
+        if self.instructions == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / self.instructions as f64
+        }
+    }
+
+    fn row(&self, name: &str, count: usize) -> String {
+        format!("{name:<14}{count:>8}{:>8.1}%\r\n", self.percent(count))
+    }
+
+    /// The report: totals, then a table of each count with its share of the
+    /// instructions. Mnemonics and registers are listed most frequent first.
+    pub fn to_string(&self) -> String {
+        let mut res = String::new();
+        res.push_str(&format!(
+            "instructions: {} ({} bytes)\r\n",
+            self.instructions, self.bytes
+        ));
+        res.push_str(&format!(
+            "prefixed: {} ({:.1}%)\r\n",
+            self.prefixed,
+            self.percent(self.prefixed)
+        ));
+
+        res.push_str("\r\nmnemonic         count\r\n");
+        for (mnemonic, count) in by_frequency(&self.mnemonics) {
+            res.push_str(&self.row(mnemonic, count));
+        }
+
+        res.push_str("\r\nlength           count\r\n");
+        for (len, count) in &self.lengths {
+            res.push_str(&self.row(&len.to_string(), *count));
+        }
+
+        res.push_str("\r\nmode             count\r\n");
+        for (mode, count) in &self.modes {
+            res.push_str(&self.row(&format!("{mode:?}"), *count));
+        }
+
+        res.push_str("\r\nregister         count\r\n");
+        for (register, count) in by_frequency(&self.registers) {
+            res.push_str(&self.row(register, count));
+        }
+
+        res
+    }
+}
+
+/// Entries from the highest count down, ties by name.
+fn by_frequency(counts: &BTreeMap<String, usize>) -> Vec<(&str, usize)> {
+    let mut res: Vec<_> = counts
+        .iter()
+        .map(|(name, count)| (name.as_str(), *count))
+        .collect();
+    res.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    res
+}
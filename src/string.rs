@@ -0,0 +1,76 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringOp {
+    Movs,
+    Cmps,
+    Stos,
+    Lods,
+    Scas,
+}
+
+impl StringOp {
+    pub fn decode(byte: u8) -> Self {
+        match byte & 0b1111_1110 {
+            0xA4 => Self::Movs,
+            0xA6 => Self::Cmps,
+            0xAA => Self::Stos,
+            0xAC => Self::Lods,
+            0xAE => Self::Scas,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            StringOp::Movs => "movs",
+            StringOp::Cmps => "cmps",
+            StringOp::Stos => "stos",
+            StringOp::Lods => "lods",
+            StringOp::Scas => "scas",
+        }
+    }
+
+    /// Whether the operation compares, so that `repe`/`repne` also stop on
+    /// ZF.
+    pub fn compares(&self) -> bool {
+        matches!(self, StringOp::Cmps | StringOp::Scas)
+    }
+
+    /// Whether the operation reads from DS:SI.
+    pub fn uses_source(&self) -> bool {
+        matches!(self, StringOp::Movs | StringOp::Cmps | StringOp::Lods)
+    }
+
+    /// Whether the operation reads or writes ES:DI.
+    pub fn uses_destination(&self) -> bool {
+        !matches!(self, StringOp::Lods)
+    }
+}
+
+/// A string instruction on bytes or words, e.g. `movsb` (`1010010w`).
+#[derive(Debug)]
+pub struct StringInstruction {
+    pub(crate) op: StringOp,
+    pub(crate) w: bool,
+}
+
+impl StringInstruction {
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let op = StringOp::decode(bytes[0]);
+        let w = (bytes[0] & 0b0000_0001) == 0b0000_0001;
+
+        Self { op, w }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        1
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        let size = if self.w { "w" } else { "b" };
+
+        res.push_str(&format!("{}{}\r\n", self.op.mnemonic(), size));
+        res
+    }
+}
@@ -1,21 +1,54 @@
 #![cfg(test)]
 
 use std::{
+    cell::RefCell,
+    collections::BTreeSet,
     env::temp_dir,
+    error::Error,
     fs,
     io::{Read, Write},
     process::{Command, Stdio},
+    rc::Rc,
 };
 
-use crate::dissassemble;
+use crate::{
+    arithmetic::ArithmeticOp,
+    bus::BusConfig,
+    cfg::{Edge, EdgeKind},
+    clocks::{Clocks, CpuModel},
+    control_flow_graph,
+    cpu::Cpu,
+    debugger::Debugger,
+    disk::Disk,
+    dissassemble, dissassemble_image, dissassemble_json, dissassemble_with,
+    dos::Dos,
+    flags::Flags,
+    functions,
+    handlers::Handlers,
+    history::History,
+    image::SparseImage,
+    instruction::Instruction,
+    io::{IoBus, IoDevice, PortAccess},
+    json::{Listing, Operand},
+    loader::{self, Load},
+    memory::{Memory, PixelFormat, MEMORY_SIZE},
+    mode::Mode,
+    mz::Executable,
+    pic::Pic,
+    pit::Pit,
+    register::{Register, SegmentRegister},
+    run as run_program, simulate, simulate_with, statistics_with,
+    video::{self, Video},
+    DisassembleOptions, SimulateOptions, TraceFormat,
+};
 
 use paste::paste;
 
 pub fn get_file_as_byte_vec(filename: &str) -> Vec<u8> {
-    let mut f = fs::File::open(&filename).expect("no file found");
-    let metadata = fs::metadata(&filename).expect("unable to read metadata");
+    let mut f = fs::File::open(filename).expect("no file found");
+    let metadata = fs::metadata(filename).expect("unable to read metadata");
     let mut buffer = vec![0; metadata.len() as usize];
-    f.read(&mut buffer).expect("buffer overflow");
+    f.read_exact(&mut buffer).expect("buffer overflow");
 
     buffer
 }
@@ -84,3 +117,2193 @@ create_test!(37);
 create_test!(38);
 create_test!(39);
 create_test!(40);
+
+/// Writes `source` to `out/<name>_out.asm` and assembles it with NASM.
+fn assemble(source: &str, name: &str) -> Vec<u8> {
+    fs::create_dir("out").ok();
+    let out_path = format!("out/{name}_out.asm");
+    fs::remove_file(&out_path).ok();
+    let mut out = fs::File::create(&out_path).expect("Failed to create output file");
+    out.write_all(source.as_bytes())
+        .expect("Failed to write to output file");
+
+    let temp = temp_dir()
+        .join(format!("temp_{name}.out"))
+        .to_string_lossy()
+        .to_string();
+
+    Command::new("nasm")
+        .args([&out_path, &format!("-o {}", &temp)])
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .expect("NASM command failed");
+
+    let binary = get_file_as_byte_vec(&temp);
+    fs::remove_file(&temp).ok();
+
+    binary
+}
+
+// The recursive listings assemble back to the same bytes too. Like the tests
+// above they need NASM, and they are only run where it is installed, with
+// `cargo test -- --include-ignored`.
+macro_rules! create_recursive_test {
+    ($num:expr) => {
+        paste! {
+            #[test]
+            #[ignore = "needs NASM"]
+            fn [<listing_ $num _recursive>]() {
+                let file = include_bytes!(concat!("../listings/listing_", stringify!($num))).to_vec();
+                let options = DisassembleOptions {
+                    recursive: true,
+                    ..Default::default()
+                };
+
+                let res = dissassemble_with(file, &options).expect("Failed to disassemble");
+                let binary = assemble(&res, &format!("listing_{}_recursive", $num));
+
+                assert_eq!(
+                    binary,
+                    include_bytes!(concat!("../listings/listing_", stringify!($num)))
+                );
+            }
+        }
+    };
+}
+
+create_recursive_test!(37);
+create_recursive_test!(38);
+create_recursive_test!(39);
+create_recursive_test!(40);
+
+/// Recursive listings of data, `seg` references and encodings NASM would
+/// assemble differently, which are all listed as bytes.
+#[test]
+#[ignore = "needs NASM"]
+fn recursive_listing_round_trip() {
+    let mut bytes = vec![
+        0xEB, 0x06, // jmp short $+8
+        0x34, 0x12, // relocated word
+        b'H', b'i', b'!', b'!', // data
+        0xB8, 0x34, 0x12, // mov ax, seg 0x1234, relocated
+        0x8B, 0xC3, // mov ax, bx with the d bit set
+        0x82, 0xC0, 0x05, // add al, 5 with the 0x82 alias
+        0x80, 0xC0, 0x05, // add al, 5 without the accumulator form
+        0x05, 0x05, 0x00, // add ax, 5 with a word immediate
+        0x89, 0x06, 0x00, 0x01, // mov [256], ax without the accumulator form
+        0x8B, 0x47, 0x00, // mov ax, [bx + 0]
+        0x8B, 0x87, 0x10, 0x00, // mov ax, [bx + 16] with a word displacement
+        0x8B, 0x87, 0xFF, 0xFF, // mov ax, [bx - 1] with a word displacement
+        0xC7, 0xC0, 0x01, 0x00, // mov ax, 1 through the ModR/M form
+        0xC7, 0x0F, 0x01, 0x00, // mov word [bx], 1 with reg bits set
+        0x8E, 0xE0, // mov es, ax with the top sr bit set
+        0xFF, 0xF0, // push ax through the ModR/M form
+        0x87, 0xC1, // xchg ax, cx through the ModR/M form
+        0x26, 0xF3, 0xA4, // segment override before rep
+        0x2E, 0x74, 0x00, // cs je $+2
+        0x8B, 0x46, 0x00, // mov ax, [bp + 0]
+        0x83, 0xC0, 0x05, // add ax, 5
+        0xF4, // hlt
+    ];
+    bytes.extend([0xFF; 3]);
+    let options = DisassembleOptions {
+        recursive: true,
+        relocations: BTreeSet::from([2, 9]),
+        ..Default::default()
+    };
+
+    let res = dissassemble_with(bytes.clone(), &options).expect("Failed to disassemble");
+    assert_eq!(assemble(&res, "recursive"), bytes);
+}
+
+fn execute(bytes: &[u8]) -> Cpu {
+    let mut cpu = Cpu::new();
+
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let instruction = Instruction::decode(&bytes[offset..]).expect("Failed to decode");
+        offset += instruction.offset();
+
+        cpu.execute(&instruction).expect("Failed to execute");
+    }
+
+    cpu
+}
+
+#[test]
+fn execute_listing_43() {
+    let cpu = execute(include_bytes!("../listings/listing_43"));
+
+    assert_eq!(cpu.registers.general, [1, 3, 4, 2, 5, 6, 7, 8]);
+}
+
+#[test]
+fn execute_listing_44() {
+    let cpu = execute(include_bytes!("../listings/listing_44"));
+
+    assert_eq!(cpu.registers.get(Register::AX), 4);
+    assert_eq!(cpu.registers.get(Register::BX), 3);
+    assert_eq!(cpu.registers.get(Register::CX), 2);
+    assert_eq!(cpu.registers.get(Register::DX), 1);
+    assert_eq!(cpu.registers.get(Register::SP), 1);
+    assert_eq!(cpu.registers.get(Register::BP), 2);
+    assert_eq!(cpu.registers.get(Register::SI), 3);
+    assert_eq!(cpu.registers.get(Register::DI), 4);
+}
+
+#[test]
+fn execute_listing_45() {
+    let cpu = execute(include_bytes!("../listings/listing_45"));
+
+    assert_eq!(cpu.registers.get(Register::AX), 0x4411);
+    assert_eq!(cpu.registers.get(Register::BX), 0x3344);
+    assert_eq!(cpu.registers.get(Register::CX), 0x6677);
+    assert_eq!(cpu.registers.get(Register::DX), 0x7788);
+    assert_eq!(cpu.registers.get(Register::SP), 0x4411);
+    assert_eq!(cpu.registers.get(Register::BP), 0x3344);
+    assert_eq!(cpu.registers.get(Register::SI), 0x6677);
+    assert_eq!(cpu.registers.get(Register::DI), 0x7788);
+    assert_eq!(cpu.registers.get_segment(SegmentRegister::ES), 0x6677);
+    assert_eq!(cpu.registers.get_segment(SegmentRegister::SS), 0x4411);
+    assert_eq!(cpu.registers.get_segment(SegmentRegister::DS), 0x3344);
+}
+
+#[test]
+fn execute_memory_movs() {
+    // mov word [1000], 513
+    // mov bx, 1000
+    // mov cl, [bx + 1]
+    // mov [bx - 2], cl
+    // mov ax, [998]
+    let cpu = execute(&[
+        0xC7, 0x06, 0xE8, 0x03, 0x01, 0x02, 0xBB, 0xE8, 0x03, 0x8A, 0x4F, 0x01, 0x88, 0x4F, 0xFE,
+        0xA1, 0xE6, 0x03,
+    ]);
+
+    assert_eq!(cpu.memory.read_word(0, 1000), 513);
+    assert_eq!(cpu.registers.get(Register::CL), 2);
+    assert_eq!(cpu.registers.get(Register::AX), 2);
+}
+
+#[test]
+fn simulate_trace() {
+    let res =
+        simulate(include_bytes!("../listings/listing_44").to_vec()).expect("Failed to simulate");
+    let mut lines = res.lines();
+
+    assert_eq!(lines.next(), Some("mov ax, 1 ; ax:0x0->0x1 ip:0x0->0x3"));
+    assert_eq!(lines.nth(3), Some("mov sp, ax ; sp:0x0->0x1 ip:0xc->0xe"));
+    assert!(res.contains("      di: 0x0004 (4)\r\n"));
+}
+
+#[test]
+fn execute_listing_46() {
+    let cpu = execute(include_bytes!("../listings/listing_46"));
+
+    assert_eq!(cpu.registers.get(Register::BX), 0xE102);
+    assert_eq!(cpu.registers.get(Register::CX), 0x0F01);
+    assert_eq!(cpu.registers.get(Register::SP), 0x03E6);
+    assert_eq!(cpu.registers.get(Register::BP), 0);
+    assert_eq!(cpu.registers.flags.to_string(), "PZ");
+}
+
+#[test]
+fn arithmetic_flags() {
+    use ArithmeticOp::*;
+
+    // (op, dst, src, w, carry in, result, flags)
+    let table = [
+        (Add, 0x00FF, 0x0001, false, false, 0x0000, "CPAZ"),
+        (Add, 0x00FF, 0x0001, true, false, 0x0100, "PA"),
+        (Add, 0x7FFF, 0x0001, true, false, 0x8000, "PASO"),
+        (Add, 0x0080, 0x0080, false, false, 0x0000, "CPZO"),
+        (Add, 0x0003, 0x0004, true, false, 0x0007, ""),
+        (Adc, 0x00FE, 0x0001, false, true, 0x0000, "CPAZ"),
+        (Adc, 0xFFFF, 0x0000, true, true, 0x0000, "CPAZ"),
+        (Sub, 0x0000, 0x0001, false, false, 0x00FF, "CPAS"),
+        (Sub, 0x8000, 0x0001, true, false, 0x7FFF, "PAO"),
+        (Sub, 0x0010, 0x0001, true, false, 0x000F, "PA"),
+        (Sbb, 0x0005, 0x0004, true, true, 0x0000, "PZ"),
+        (Sbb, 0x0000, 0x0000, false, true, 0x00FF, "CPAS"),
+        (Cmp, 0x0005, 0x0005, true, false, 0x0000, "PZ"),
+        (Cmp, 0x0080, 0x0001, false, false, 0x007F, "AO"),
+        (And, 0xF0F0, 0x0F0F, true, true, 0x0000, "PZ"),
+        (Or, 0x0080, 0x0001, false, true, 0x0081, "PS"),
+        (Xor, 0x1234, 0x1234, true, false, 0x0000, "PZ"),
+        (Test, 0x8000, 0x8001, true, false, 0x8000, "PS"),
+    ];
+
+    for (op, dst, src, w, carry, expected, expected_flags) in table {
+        let mut flags = Flags(Flags::AF);
+        flags.set(Flags::CF, carry);
+
+        let res = flags.arithmetic(op, dst, src, w);
+
+        assert_eq!(res, expected, "{op:?} {dst:#x}, {src:#x}");
+        assert_eq!(
+            flags.to_string(),
+            expected_flags,
+            "{op:?} {dst:#x}, {src:#x}"
+        );
+    }
+}
+
+#[test]
+fn decode_arithmetic() {
+    let cases: [(&[u8], &str); 8] = [
+        (&[0x03, 0x18], "add bx, [bx + si]"),
+        (&[0x83, 0xC6, 0x02], "add si, 2"),
+        (&[0x80, 0x07, 0x22], "add byte [bx], 34"),
+        (&[0x2D, 0xE8, 0x03], "sub ax, 1000"),
+        (&[0x83, 0x3E, 0xE2, 0x12, 0xFE], "cmp word [4834], -2"),
+        (&[0x31, 0xC0], "xor ax, ax"),
+        (&[0x85, 0xCB], "test bx, cx"),
+        (&[0xF6, 0xC3, 0x01], "test bl, 1"),
+    ];
+
+    for (bytes, expected) in cases {
+        let instruction = Instruction::decode(bytes).expect("Failed to decode");
+
+        assert_eq!(instruction.offset(), bytes.len(), "{expected}");
+        assert_eq!(instruction.to_string().trim_end(), expected);
+    }
+}
+
+fn run(bytes: &[u8]) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.memory.load(0, bytes);
+
+    while !cpu.halted && cpu.code_address() < bytes.len() {
+        cpu.step().expect("Failed to step");
+    }
+
+    cpu
+}
+
+#[test]
+fn run_listing_48() {
+    let cpu = run(include_bytes!("../listings/listing_48"));
+
+    assert_eq!(cpu.registers.get(Register::BX), 0x07D0);
+    assert_eq!(cpu.registers.get(Register::CX), 0xFCE0);
+    assert_eq!(cpu.registers.ip, 0x000E);
+    assert_eq!(cpu.registers.flags.to_string(), "CS");
+}
+
+#[test]
+fn run_listing_49() {
+    let cpu = run(include_bytes!("../listings/listing_49"));
+
+    assert_eq!(cpu.registers.get(Register::BX), 0x0406);
+    assert_eq!(cpu.registers.get(Register::CX), 0);
+    assert_eq!(cpu.registers.ip, 0x000E);
+    assert_eq!(cpu.registers.flags.to_string(), "PZ");
+}
+
+#[test]
+fn run_call_loop_hlt() {
+    //     mov sp, 0x100
+    //     mov cx, 4
+    // l:  call f
+    //     loop l
+    //     hlt
+    // f:  add ax, 3
+    //     ret
+    let cpu = run(&[
+        0xBC, 0x00, 0x01, 0xB9, 0x04, 0x00, 0xE8, 0x03, 0x00, 0xE2, 0xFB, 0xF4, 0x83, 0xC0, 0x03,
+        0xC3,
+    ]);
+
+    assert!(cpu.halted);
+    assert_eq!(cpu.registers.get(Register::AX), 12);
+    assert_eq!(cpu.registers.get(Register::CX), 0);
+    assert_eq!(cpu.registers.get(Register::SP), 0x100);
+    assert_eq!(cpu.registers.ip, 12);
+}
+
+#[test]
+fn simulate_instruction_limit() {
+    // jmp short $
+    let options = SimulateOptions {
+        instruction_limit: 3,
+        ..Default::default()
+    };
+    let res = simulate_with(vec![0xEB, 0xFE], &options).expect("Failed to simulate");
+
+    assert_eq!(res.matches("jmp short $+0").count(), 3);
+    assert!(res.contains("; Instruction limit reached"));
+}
+
+#[test]
+fn decode_branches() {
+    let cases: [(&[u8], &str); 9] = [
+        (&[0x75, 0xF8], "jne $-6"),
+        (&[0xE3, 0x02], "jcxz $+4"),
+        (&[0xE2, 0xFE], "loop $+0"),
+        (&[0xE9, 0x00, 0x01], "jmp near $+259"),
+        (&[0xE8, 0xFD, 0xFF], "call $+0"),
+        (&[0xEA, 0x34, 0x12, 0x00, 0xF0], "jmp 61440:4660"),
+        (&[0xFF, 0x17], "call word [bx]"),
+        (&[0xFF, 0xE3], "jmp bx"),
+        (&[0xCA, 0x04, 0x00], "retf 4"),
+    ];
+
+    for (bytes, expected) in cases {
+        let instruction = Instruction::decode(bytes).expect("Failed to decode");
+
+        assert_eq!(instruction.offset(), bytes.len(), "{expected}");
+        assert_eq!(instruction.to_string().trim_end(), expected);
+    }
+}
+
+#[test]
+fn memory_segmentation() {
+    assert_eq!(Memory::physical_address(0x1234, 0x0010), 0x12350);
+    assert_eq!(Memory::physical_address(0xFFFF, 0x0010), 0x00000);
+    assert_eq!(Memory::physical_address(0xFFFF, 0xFFFF), 0x0FFEF);
+
+    let mut memory = Memory::new();
+
+    // A word at the end of a segment wraps to the start of the same segment.
+    memory.write_word(0x1000, 0xFFFF, 0xBEEF);
+    assert_eq!(memory.read_physical(0x1FFFF), 0xEF);
+    assert_eq!(memory.read_physical(0x10000), 0xBE);
+    assert_eq!(memory.read_word(0x1000, 0xFFFF), 0xBEEF);
+
+    // The same physical byte is reachable through different segments.
+    memory.write_byte(0x0001, 0x0000, 0x42);
+    assert_eq!(memory.read_byte(0x0000, 0x0010), 0x42);
+
+    // Loading wraps at the 1 MiB boundary.
+    memory.load(MEMORY_SIZE - 1, &[0x11, 0x22]);
+    assert_eq!(memory.read_physical(MEMORY_SIZE - 1), 0x11);
+    assert_eq!(memory.read_physical(0), 0x22);
+}
+
+#[test]
+fn execute_segmented_memory() {
+    let mut cpu = Cpu::new();
+    cpu.registers.set_segment(SegmentRegister::DS, 0x1000);
+    cpu.registers.set_segment(SegmentRegister::SS, 0x2000);
+    cpu.registers.set_segment(SegmentRegister::ES, 0x3000);
+    cpu.registers.set(Register::BX, 0x10);
+    cpu.registers.set(Register::BP, 0x20);
+
+    // mov word [bx], 1
+    // mov word [bp + 2], 2
+    // mov word [es:bx], 3
+    // mov word [ds:bp], 4
+    // mov [es:8], ax
+    let bytes = [
+        0xC7, 0x07, 0x01, 0x00, 0xC7, 0x46, 0x02, 0x02, 0x00, 0x26, 0xC7, 0x07, 0x03, 0x00, 0x3E,
+        0xC7, 0x46, 0x00, 0x04, 0x00, 0x26, 0xA3, 0x08, 0x00,
+    ];
+    cpu.registers.set(Register::AX, 5);
+
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let instruction = Instruction::decode(&bytes[offset..]).expect("Failed to decode");
+        offset += instruction.offset();
+        cpu.execute(&instruction).expect("Failed to execute");
+    }
+
+    assert_eq!(cpu.memory.read_word(0x1000, 0x10), 1);
+    assert_eq!(cpu.memory.read_word(0x2000, 0x22), 2);
+    assert_eq!(cpu.memory.read_word(0x3000, 0x10), 3);
+    assert_eq!(cpu.memory.read_word(0x1000, 0x20), 4);
+    assert_eq!(cpu.memory.read_word(0x3000, 0x08), 5);
+}
+
+#[test]
+fn decode_segment_override() {
+    let instruction = Instruction::decode(&[0x26, 0x8B, 0x47, 0x04]).expect("Failed to decode");
+
+    assert_eq!(instruction.offset(), 4);
+    assert_eq!(instruction.to_string().trim_end(), "mov ax, [es:bx + 4]");
+}
+
+#[test]
+fn dump_and_export_image() {
+    // mov word [0x100], 0x2211
+    // mov word [0x102], 0xFF33
+    // mov word [0x104], 0x5544
+    // mov word [0x106], 0xFF66
+    let bytes = vec![
+        0xC7, 0x06, 0x00, 0x01, 0x11, 0x22, 0xC7, 0x06, 0x02, 0x01, 0x33, 0xFF, 0xC7, 0x06, 0x04,
+        0x01, 0x44, 0x55, 0xC7, 0x06, 0x06, 0x01, 0x66, 0xFF,
+    ];
+    let (cpu, _) = run_program(bytes, &SimulateOptions::default()).expect("Failed to run");
+
+    let dump_path = temp_dir().join("computer_enhance_dump.data");
+    cpu.memory
+        .dump(0x100, 8, &dump_path)
+        .expect("Failed to dump");
+    assert_eq!(
+        fs::read(&dump_path).expect("Failed to read dump"),
+        [0x11, 0x22, 0x33, 0xFF, 0x44, 0x55, 0x66, 0xFF]
+    );
+    fs::remove_file(&dump_path).ok();
+
+    let image_path = temp_dir().join("computer_enhance_image.ppm");
+    cpu.memory
+        .export_ppm(0x100, 2, 1, PixelFormat::Rgba, &image_path)
+        .expect("Failed to export image");
+    let mut expected = b"P6\n2 1\n255\n".to_vec();
+    expected.extend_from_slice(&[0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+    assert_eq!(
+        fs::read(&image_path).expect("Failed to read image"),
+        expected
+    );
+    fs::remove_file(&image_path).ok();
+
+    cpu.memory
+        .export_ppm(0x100, 2, 1, PixelFormat::Rgb, &image_path)
+        .expect("Failed to export image");
+    let mut expected = b"P6\n2 1\n255\n".to_vec();
+    expected.extend_from_slice(&[0x11, 0x22, 0x33, 0xFF, 0x44, 0x55]);
+    assert_eq!(
+        fs::read(&image_path).expect("Failed to read image"),
+        expected
+    );
+    fs::remove_file(&image_path).ok();
+}
+
+#[test]
+fn clocks_listing_56() {
+    let bytes = include_bytes!("../listings/listing_56").to_vec();
+
+    let mut options = SimulateOptions {
+        show_clocks: true,
+        ..Default::default()
+    };
+    let (cpu, res) = run_program(bytes.clone(), &options).expect("Failed to run");
+
+    assert_eq!(cpu.clocks, 196);
+    assert!(res.contains("mov cx, [bx] ; Clocks: +13 = 49 (8 + 5ea) | "));
+    assert!(res.contains("add [di + 1000], cx ; Clocks: +25 = 192 (16 + 9ea) | "));
+
+    options.model = CpuModel::I8088;
+    let (cpu, res) = run_program(bytes, &options).expect("Failed to run");
+
+    assert_eq!(cpu.clocks, 240);
+    assert!(res.contains("mov dx, [1000] ; Clocks: +18 = 40 (8 + 6ea + 4p) | "));
+    assert!(res.contains("add [di + 1000], cx ; Clocks: +33 = 236 (16 + 9ea + 8p) | "));
+}
+
+#[test]
+fn clocks_odd_address_penalty() {
+    // mov si, 1001
+    // mov cx, [si]
+    // mov [si], cl
+    let mut cpu = Cpu::new();
+    cpu.memory
+        .load(0, &[0xBE, 0xE9, 0x03, 0x8B, 0x0C, 0x88, 0x0C]);
+
+    cpu.step().expect("Failed to step");
+    cpu.step().expect("Failed to step");
+    assert_eq!(
+        cpu.instruction_clocks,
+        Clocks {
+            base: 8,
+            ea: 5,
+            penalty: 4
+        }
+    );
+
+    // Byte transfers are never penalised.
+    cpu.step().expect("Failed to step");
+    assert_eq!(cpu.instruction_clocks.penalty, 0);
+}
+
+#[test]
+fn clocks_effective_address() {
+    // (bytes, base, ea)
+    let cases: [(&[u8], u32, u32); 6] = [
+        (&[0x8B, 0x00], 8, 7), // mov ax, [bx + si]
+        (&[0x8B, 0x03], 8, 7), // mov ax, [bp + di]
+        (&[0x8B, 0x01], 8, 8), // mov ax, [bx + di]
+        // 11 as in the manual, not the 12 some references give.
+        (&[0x8B, 0x43, 0x10], 8, 11),       // mov ax, [bp + di + 16]
+        (&[0x89, 0x82, 0x00, 0x01], 9, 12), // mov [bp + si + 256], ax
+        (&[0x26, 0x03, 0x06, 0x00, 0x01], 9, 8), // add ax, [es:256]
+    ];
+
+    for (bytes, base, ea) in cases {
+        let instruction = Instruction::decode(bytes).expect("Failed to decode");
+        let clocks = Clocks::estimate(&instruction, false);
+
+        assert_eq!((clocks.base, clocks.ea), (base, ea), "{bytes:02x?}");
+    }
+}
+
+#[test]
+fn disassemble_with_clocks() {
+    let bytes = include_bytes!("../listings/listing_49").to_vec();
+    let options = DisassembleOptions {
+        clocks: Some(CpuModel::I8086),
+        ..Default::default()
+    };
+
+    let res = dissassemble_with(bytes, &options).expect("Failed to disassemble");
+
+    assert_eq!(
+        res,
+        "bits 16\r\n\r\n\
+         mov cx, 3 ; clocks: 4\r\n\
+         mov bx, 1000 ; clocks: 4\r\n\
+         add bx, 10 ; clocks: 4\r\n\
+         sub cx, 1 ; clocks: 4\r\n\
+         jne $-6 ; clocks: 16 taken, 4 not taken\r\n\
+         \r\n\
+         ; Static clocks per basic block (best case):\r\n\
+         ; 0x0000-0x0006: 8 clocks\r\n\
+         ; 0x0006-0x000e: 12 clocks, 24 if the branch is taken\r\n"
+    );
+
+    let bytes = include_bytes!("../listings/listing_56").to_vec();
+    let options = DisassembleOptions {
+        clocks: Some(CpuModel::I8088),
+        ..Default::default()
+    };
+
+    let res = dissassemble_with(bytes, &options).expect("Failed to disassemble");
+
+    assert!(res.contains("add [di + 1000], cx ; clocks: 33 (16 + 9ea + 8p)\r\n"));
+    assert!(res.contains("; 0x0000-0x0037: 240 clocks\r\n"));
+}
+
+#[test]
+fn decode_displacements_and_byte_immediates() {
+    let cases: [(&[u8], &str); 6] = [
+        (&[0x8B, 0x41, 0xDB], "mov ax, [bx + di - 37]"),
+        (&[0x8A, 0x41, 0xDB], "mov al, [bx + di - 37]"),
+        (&[0x8B, 0x86, 0x0C, 0xFE], "mov ax, [bp - 500]"),
+        (&[0xB0, 0xC8], "mov al, 200"),
+        (&[0xC6, 0x03, 0x07], "mov byte [bp + di], 7"),
+        (
+            &[0xC7, 0x85, 0x85, 0x03, 0x5B, 0x01],
+            "mov word [di + 901], 347",
+        ),
+    ];
+
+    for (bytes, expected) in cases {
+        let instruction = Instruction::decode(bytes).expect("Failed to decode");
+
+        assert_eq!(instruction.offset(), bytes.len(), "{expected}");
+        assert_eq!(instruction.to_string().trim_end(), expected);
+    }
+}
+
+/// Runs `bytes` and returns the trace sim86 would print for `name`.
+fn sim86_trace(bytes: &[u8], name: &str, clocks: Option<CpuModel>) -> String {
+    let options = SimulateOptions {
+        model: clocks.unwrap_or_default(),
+        show_clocks: clocks.is_some(),
+        format: TraceFormat::Sim86 {
+            name: name.to_string(),
+        },
+        ..Default::default()
+    };
+
+    simulate_with(bytes.to_vec(), &options).expect("Failed to simulate")
+}
+
+/// Reads the course's reference trace `listings/<name>.txt`, as printed by
+/// sim86 itself, with line endings normalised.
+fn sim86_reference(name: &str) -> String {
+    let path = format!("listings/{name}.txt");
+    let trace = fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!("Missing {path}: copy {name}.txt from the course's part1 listings")
+    });
+
+    trace.replace("\r\n", "\n")
+}
+
+#[test]
+fn sim86_golden_listing_48() {
+    let name = "listing_0048_ip_register";
+    let trace = sim86_trace(
+        include_bytes!("../listings/listing_48"),
+        &format!("test\\{name}"),
+        None,
+    );
+
+    assert_eq!(trace.replace("\r\n", "\n"), sim86_reference(name));
+}
+
+#[test]
+fn sim86_golden_listing_49() {
+    let name = "listing_0049_conditional_jumps";
+    let trace = sim86_trace(
+        include_bytes!("../listings/listing_49"),
+        &format!("test\\{name}"),
+        None,
+    );
+
+    assert_eq!(trace.replace("\r\n", "\n"), sim86_reference(name));
+}
+
+#[test]
+fn sim86_golden_listing_56() {
+    let name = "listing_0056_estimating_cycles";
+    let bytes = include_bytes!("../listings/listing_56");
+    let reference = sim86_reference(name);
+
+    // The reference holds the 8086 run followed by the 8088 run.
+    for model in [CpuModel::I8086, CpuModel::I8088] {
+        let trace = sim86_trace(bytes, &format!("test\\{name}"), Some(model));
+
+        assert!(
+            reference.contains(&trace.replace("\r\n", "\n")),
+            "{model:?} trace differs from {name}.txt:\n{trace}"
+        );
+    }
+}
+
+#[test]
+fn sim86_instruction_text() {
+    let cases: [(&[u8], &str, &str); 4] = [
+        (
+            &[0x8B, 0x41, 0xDB],
+            "mov ax, [bx + di - 37]",
+            "mov ax, [bx + di - 37]",
+        ),
+        (
+            &[0xC6, 0x03, 0x07],
+            "mov byte [bp + di], 7",
+            "mov byte [bp + di], 7",
+        ),
+        (&[0x8B, 0x56, 0x00], "mov dx, [bp + 0]", "mov dx, [bp]"),
+        (&[0x26, 0x8B, 0x07], "mov ax, [es:bx]", "mov ax, es:[bx]"),
+    ];
+
+    for (bytes, native, sim86) in cases {
+        let instruction = Instruction::decode(bytes).expect("Failed to decode");
+
+        assert_eq!(instruction.to_string().trim_end(), native);
+        assert_eq!(instruction.to_sim86_string().trim_end(), sim86);
+    }
+}
+
+#[test]
+fn debugger_commands() {
+    // Same program as `run_call_loop_hlt`.
+    let bytes = [
+        0xBC, 0x00, 0x01, 0xB9, 0x04, 0x00, 0xE8, 0x03, 0x00, 0xE2, 0xFB, 0xF4, 0x83, 0xC0, 0x03,
+        0xC3,
+    ];
+    let mut debugger = Debugger::new(&bytes, &SimulateOptions::default()).unwrap();
+    let mut command = |line: &str| debugger.command(line).expect("Command failed");
+
+    assert_eq!(
+        command("step 2"),
+        "0000:0000  mov sp, 256 ; sp:0x0->0x100 ip:0x0->0x3\r\n\
+         0000:0003  mov cx, 4 ; cx:0x0->0x4 ip:0x3->0x6\r\n"
+    );
+
+    // Stops on the breakpoint inside `f`, then `finish` returns to the loop.
+    assert_eq!(command("break 0xc"), "breakpoint at 0x0000c\r\n");
+    assert_eq!(command("c"), "breakpoint\r\n0000:000c  add ax, 3\r\n");
+    assert_eq!(command("finish"), "returned\r\n0000:0009  loop $-3\r\n");
+
+    command("set ax 0x100");
+    command("set flags -");
+    command("set zf 1");
+    assert!(command("regs").contains("      ax: 0x0100 (256)\r\n"));
+    assert!(command("regs").ends_with("   flags: Z\r\n"));
+
+    assert!(command("u 1").starts_with("   0000:0000  mov sp, 256\r\n"));
+    assert!(command("u 1").ends_with("=> 0000:0009  loop $-3\r\n"));
+    assert!(command("x 0:6 3").starts_with("0x00006  e8 03 00 "));
+
+    command("delete 0xc");
+    assert_eq!(command("c"), "halted\r\n");
+    assert_eq!(debugger.cpu.registers.get(Register::AX), 0x100 + 9);
+
+    assert!(debugger.command("frobnicate").is_err());
+}
+
+#[test]
+fn history_step_back() {
+    //     mov sp, 0x100
+    //     mov cx, 20
+    // l:  mov [0x300], cx
+    //     add word [0x200], 3
+    //     loop l
+    //     hlt
+    let bytes = [
+        0xBC, 0x00, 0x01, 0xB9, 0x14, 0x00, 0x89, 0x0E, 0x00, 0x03, 0x83, 0x06, 0x00, 0x02, 0x03,
+        0xE2, 0xF5, 0xF4,
+    ];
+    let mut cpu = Cpu::new();
+    cpu.memory.load(0, &bytes);
+
+    // Reference states after each instruction, executed without history.
+    let mut states = vec![cpu.clone()];
+    while !cpu.halted {
+        cpu.step().expect("Failed to step");
+        states.push(cpu.clone());
+    }
+
+    // A small undo log forces stepping back further to go through snapshots.
+    let mut cpu = states[0].clone();
+    let mut history = History::new(&cpu, 4, 5, 3);
+    while !cpu.halted {
+        history.step(&mut cpu).expect("Failed to step");
+    }
+    assert_eq!(history.executed() as usize, states.len() - 1);
+    assert_eq!(history.available(), 13);
+
+    for count in [1, 3, 6, 2] {
+        assert_eq!(history.step_back(&mut cpu, count).unwrap(), count);
+
+        let expected = &states[history.executed() as usize];
+        assert_eq!(cpu.registers, expected.registers);
+        assert_eq!(cpu.clocks, expected.clocks);
+        assert_eq!(
+            cpu.memory.range(0, 0x200 + 2),
+            expected.memory.range(0, 0x200 + 2)
+        );
+    }
+
+    // Only as far back as the oldest snapshot.
+    assert_eq!(history.step_back(&mut cpu, 100).unwrap(), 1);
+    assert_eq!(history.available(), 0);
+    assert_eq!(cpu.registers, states[history.executed() as usize].registers);
+}
+
+#[test]
+fn history_without_replay() {
+    //     mov cx, 10
+    // l:  int3
+    //     loop l
+    //     hlt
+    let bytes = [0xB9, 0x0A, 0x00, 0xCC, 0xE2, 0xFD, 0xF4];
+    let calls = Rc::new(RefCell::new(0));
+    let count = calls.clone();
+    let handler = Rc::new(RefCell::new(
+        move |_: &mut Cpu, _: u8| -> Result<bool, Box<dyn Error>> {
+            *count.borrow_mut() += 1;
+            Ok(true)
+        },
+    ));
+    let mut cpu = Cpu::new();
+    cpu.memory.load(0, &bytes);
+    cpu.handlers.attach(3, handler);
+
+    let mut history = History::new(&cpu, 4, 5, 3);
+    while !cpu.halted {
+        history.step(&mut cpu).expect("Failed to step");
+    }
+    assert_eq!(*calls.borrow(), 10);
+
+    // What the handler did can't be taken back, so stepping back stops
+    // past its last call, after `loop` and `hlt`.
+    assert_eq!(history.available(), 2);
+    assert_eq!(history.step_back(&mut cpu, 100).unwrap(), 2);
+    assert_eq!(history.available(), 0);
+    assert_eq!(cpu.registers.ip, 4);
+    assert_eq!(*calls.borrow(), 10);
+}
+
+#[test]
+fn history_dos_calls() {
+    let bytes = [
+        0xBA, 0x0E, 0x01, // mov dx, message
+        0xB4, 0x09, // mov ah, 9
+        0xCD, 0x21, // int 21h
+        0xB8, 0x01, 0x00, // mov ax, 1
+        0xBB, 0x02, 0x00, // mov bx, 2
+        0xF4, // hlt
+        b'h', b'e', b'l', b'l', b'o', b'$', // message
+    ];
+    let dos = Rc::new(RefCell::new(Dos::new(temp_dir())));
+    let mut cpu = Cpu::new();
+    cpu.dos = Some(dos.clone());
+    let load = Load::Com {
+        segment: 0x1000,
+        tail: String::new(),
+    };
+    loader::load(&mut cpu, &bytes, &load).expect("Failed to load");
+
+    let mut history = History::new(&cpu, 16, 4, 4);
+    while !cpu.halted {
+        history.step(&mut cpu).expect("Failed to step");
+    }
+    assert_eq!(dos.borrow().captured(), b"hello");
+
+    // Stepping back stops at the `retf 2` of the service stub, just past
+    // the call, so it isn't made again.
+    assert_eq!(history.step_back(&mut cpu, 100).unwrap(), 4);
+    assert_eq!(history.available(), 0);
+    assert!(!dos.borrow().is_entry(cpu.code_address()));
+    assert_eq!(
+        cpu.memory.read_byte(
+            cpu.registers.get_segment(SegmentRegister::CS),
+            cpu.registers.ip
+        ),
+        0xCA
+    );
+
+    while !cpu.halted {
+        history.step(&mut cpu).expect("Failed to step");
+    }
+    assert_eq!(dos.borrow().captured(), b"hello");
+    assert_eq!(cpu.registers.get(Register::BX), 2);
+}
+
+#[test]
+fn history_interrupt_latches() {
+    let bytes = [
+        0xB8, 0x00, 0x00, // mov ax, 0
+        0x8E, 0xD0, // mov ss, ax
+        0x90, // nop
+        0x90, // nop
+        0xF4, // hlt
+    ];
+    let mut cpu = Cpu::new();
+    cpu.memory.load(0x10000, &bytes);
+    cpu.registers.set_segment(SegmentRegister::CS, 0x1000);
+    cpu.registers.set(Register::SP, 0x400);
+    // The NMI handler at 0000:0500 counts in DX.
+    cpu.memory.load(0x500, &[0x42, 0xCF]); // inc dx; iret
+    cpu.memory.write_word(0, 4 * 2, 0x500);
+
+    let mut history = History::new(&cpu, 16, 100, 1);
+    for _ in 0..2 {
+        history.step(&mut cpu).expect("Failed to step");
+    }
+    // Held off by mov ss until after the first nop.
+    cpu.request_nmi();
+    for _ in 0..2 {
+        history.step(&mut cpu).expect("Failed to step");
+    }
+    let registers = cpu.registers;
+    assert_eq!(cpu.registers.get(Register::DX), 1);
+    assert_eq!(cpu.memory.read_word(0, 0x3FA), 6);
+
+    // Taking both steps back brings back the pending NMI and the shadow of
+    // mov ss, so it is taken at the same place again.
+    assert_eq!(history.step_back(&mut cpu, 2).unwrap(), 2);
+    assert_eq!(cpu.registers.get(Register::DX), 0);
+    for _ in 0..2 {
+        history.step(&mut cpu).expect("Failed to step");
+    }
+    assert_eq!(cpu.registers, registers);
+    assert_eq!(cpu.memory.read_word(0, 0x3FA), 6);
+}
+
+#[test]
+fn debugger_reverse_stepping() {
+    // Same program as `history_step_back`.
+    let bytes = [
+        0xBC, 0x00, 0x01, 0xB9, 0x14, 0x00, 0x89, 0x0E, 0x00, 0x03, 0x83, 0x06, 0x00, 0x02, 0x03,
+        0xE2, 0xF5, 0xF4,
+    ];
+    let mut debugger = Debugger::new(&bytes, &SimulateOptions::default()).unwrap();
+    let mut command = |line: &str| debugger.command(line).expect("Command failed");
+
+    assert_eq!(command("c"), "halted\r\n");
+    assert_eq!(command("step-back 2"), "0000:000f  loop $-9\r\n");
+
+    command("watch 0x200");
+    assert_eq!(
+        command("reverse-continue"),
+        "watchpoint 0x00200 changed\r\n0000:000a  add word [512], 3\r\n"
+    );
+    assert_eq!(
+        command("x 0x200 2"),
+        format!("0x00200  {:<47}  9.\r\n", "39 00")
+    );
+
+    command("unwatch 0x200");
+    command("break 6");
+    assert_eq!(command("rc"), "breakpoint\r\n0000:0006  mov [768], cx\r\n");
+    assert!(command("regs").contains("      cx: 0x0001 (1)\r\n"));
+
+    command("delete 6");
+    assert_eq!(
+        command("rc"),
+        "start of history\r\n0000:0000  mov sp, 256\r\n"
+    );
+}
+
+#[test]
+fn debugger_reverse_stepping_bus() {
+    // Same program as `history_step_back`.
+    let bytes = [
+        0xBC, 0x00, 0x01, 0xB9, 0x14, 0x00, 0x89, 0x0E, 0x00, 0x03, 0x83, 0x06, 0x00, 0x02, 0x03,
+        0xE2, 0xF5, 0xF4,
+    ];
+    let options = SimulateOptions {
+        bus: Some(BusConfig::default()),
+        ..Default::default()
+    };
+    let mut debugger = Debugger::new(&bytes, &options).unwrap();
+    let mut expected = Debugger::new(&bytes, &options).unwrap();
+
+    debugger.command("s 30").unwrap();
+    debugger.command("sb 10").unwrap();
+    expected.command("s 20").unwrap();
+    assert_eq!(debugger.cpu.bus, expected.cpu.bus);
+    assert_eq!(debugger.cpu.clocks, expected.cpu.clocks);
+
+    // Executing on from there counts the same T-states.
+    debugger.command("s 10").unwrap();
+    expected.command("s 10").unwrap();
+    assert_eq!(debugger.cpu.bus, expected.cpu.bus);
+    assert_eq!(debugger.cpu.clocks, expected.cpu.clocks);
+}
+
+#[test]
+fn decode_remaining_instructions() {
+    let cases: [(&[u8], &str); 22] = [
+        (&[0x50], "push ax"),
+        (&[0xFF, 0x37], "push word [bx]"),
+        (&[0x1F], "pop ds"),
+        (&[0x87, 0xD8], "xchg bx, ax"),
+        (&[0x93], "xchg ax, bx"),
+        (&[0xE4, 0x60], "in al, 96"),
+        (&[0xEF], "out dx, ax"),
+        (&[0x8D, 0x47, 0x02], "lea ax, [bx + 2]"),
+        (&[0xC4, 0x1E, 0x00, 0x02], "les bx, [512]"),
+        (&[0xFE, 0xC0], "inc al"),
+        (&[0x4B], "dec bx"),
+        (&[0xF7, 0xE3], "mul bx"),
+        (&[0xF6, 0x3F], "idiv byte [bx]"),
+        (&[0xD1, 0xE0], "shl ax, 1"),
+        (&[0xD3, 0x2F], "shr word [bx], cl"),
+        (&[0xF3, 0xA4], "rep movsb"),
+        (&[0xF2, 0xAE], "repne scasb"),
+        (&[0x26, 0xAC], "es lodsb"),
+        (&[0xCD, 0x21], "int 33"),
+        (&[0xD4, 0x0A], "aam"),
+        (&[0x98], "cbw"),
+        (&[0xFC], "cld"),
+    ];
+
+    for (bytes, expected) in cases {
+        let instruction = Instruction::decode(bytes).expect("Failed to decode");
+
+        assert_eq!(instruction.offset(), bytes.len(), "{expected}");
+        assert_eq!(instruction.to_string().trim_end(), expected);
+    }
+}
+
+#[test]
+fn execute_shift_multiply_divide() {
+    let cpu = execute(&[
+        0xB8, 0x81, 0x00, // mov ax, 0x81
+        0xD0, 0xE0, // shl al, 1
+        0xB1, 0x04, // mov cl, 4
+        0xD3, 0xC8, // ror ax, cl
+        0xBB, 0xF0, 0xFF, // mov bx, -16
+        0xB8, 0x03, 0x00, // mov ax, 3
+        0xF7, 0xEB, // imul bx
+    ]);
+
+    assert_eq!(cpu.registers.get(Register::AX), 0xFFD0);
+    assert_eq!(cpu.registers.get(Register::DX), 0xFFFF);
+    assert!(!cpu.registers.flags.get(Flags::CF));
+
+    let cpu = execute(&[
+        0xB8, 0x64, 0x00, // mov ax, 100
+        0xB3, 0x07, // mov bl, 7
+        0xF6, 0xF3, // div bl
+    ]);
+
+    assert_eq!(cpu.registers.get(Register::AL), 14);
+    assert_eq!(cpu.registers.get(Register::AH), 2);
+
+    // Dividing by zero raises INT 0, with IP past the div.
+    let mut cpu = Cpu::new();
+    cpu.memory.load(0x10000, &[0xF6, 0xF3]); // div bl
+    cpu.memory.write_word(0, 0, 0x0100);
+    cpu.registers.set_segment(SegmentRegister::CS, 0x1000);
+    cpu.registers.set(Register::SP, 0x400);
+    cpu.step().expect("Failed to step");
+
+    assert_eq!(cpu.code_address(), 0x100);
+    assert_eq!(cpu.memory.read_word(0, 0x3FA), 2);
+    assert_eq!(cpu.memory.read_word(0, 0x3FC), 0x1000);
+}
+
+#[test]
+fn execute_rep_strings() {
+    let bytes = [
+        0xBE, 0x00, 0x02, // mov si, 0x200
+        0xBF, 0x00, 0x03, // mov di, 0x300
+        0xB9, 0x04, 0x00, // mov cx, 4
+        0xF3, 0xA4, // rep movsb
+        0xBF, 0x00, 0x03, // mov di, 0x300
+        0xB9, 0x04, 0x00, // mov cx, 4
+        0xB0, 0x33, // mov al, '3'
+        0xF2, 0xAE, // repne scasb
+        0xF4, // hlt
+    ];
+    let mut cpu = Cpu::new();
+    cpu.memory.load(0, &bytes);
+    cpu.memory.load(0x200, b"1234");
+
+    while !cpu.halted {
+        cpu.step().expect("Failed to step");
+    }
+
+    assert_eq!(cpu.memory.range(0x300, 4), b"1234");
+    assert_eq!(cpu.registers.get(Register::DI), 0x303);
+    assert_eq!(cpu.registers.get(Register::CX), 1);
+    assert!(cpu.registers.flags.get(Flags::ZF));
+}
+
+#[test]
+fn execute_stack_and_interrupts() {
+    let bytes = [
+        0xBC, 0x00, 0x04, // mov sp, 0x400
+        0xB8, 0x34, 0x12, // mov ax, 0x1234
+        0x50, // push ax
+        0x5B, // pop bx
+        0xF9, // stc
+        0xCD, 0x80, // int 0x80
+        0xF4, // hlt
+    ];
+    let mut cpu = Cpu::new();
+    cpu.memory.load(0, &bytes);
+    // The handler at 0000:0100 sets AX and returns with the caller's flags.
+    cpu.memory.load(0x100, &[0xB8, 0x07, 0x00, 0xCF]);
+    cpu.memory.write_word(0, 4 * 0x80, 0x100);
+
+    while !cpu.halted {
+        cpu.step().expect("Failed to step");
+    }
+
+    assert_eq!(cpu.registers.get(Register::BX), 0x1234);
+    assert_eq!(cpu.registers.get(Register::AX), 7);
+    assert_eq!(cpu.registers.get(Register::SP), 0x400);
+    assert!(cpu.registers.flags.get(Flags::CF));
+    assert_eq!(cpu.registers.ip, 0x0C);
+}
+
+#[test]
+fn load_com_with_psp() {
+    let mut cpu = Cpu::new();
+    loader::load_com(&mut cpu, &[0xC3], 0x1000, "hello.txt").expect("Failed to load");
+
+    assert_eq!(cpu.memory.range(0x10000, 4), [0xCD, 0x20, 0x00, 0xA0]);
+    assert_eq!(cpu.memory.read_byte(0x1000, 0x80), 10);
+    assert_eq!(cpu.memory.range(0x10081, 11), b" hello.txt\r");
+    assert_eq!(cpu.memory.read_byte(0x1000, 0x100), 0xC3);
+
+    for sr in [
+        SegmentRegister::CS,
+        SegmentRegister::DS,
+        SegmentRegister::ES,
+        SegmentRegister::SS,
+    ] {
+        assert_eq!(cpu.registers.get_segment(sr), 0x1000);
+    }
+    assert_eq!(cpu.registers.get(Register::SP), 0xFFFE);
+    assert_eq!(cpu.registers.ip, 0x100);
+
+    assert!(loader::load_com(&mut cpu, &vec![0; 0x10000], 0x1000, "").is_err());
+    assert!(loader::load_com(&mut cpu, &[0xC3], 0xA000, "").is_err());
+}
+
+#[test]
+fn run_com_program() {
+    let bytes = [
+        0xB8, 0x2A, 0x00, // mov ax, 42
+        0xC3, // ret
+    ];
+    let options = SimulateOptions {
+        load: Load::Com {
+            segment: 0x2000,
+            tail: String::new(),
+        },
+        ..Default::default()
+    };
+
+    let (cpu, _) = run_program(bytes.to_vec(), &options).expect("Failed to run");
+
+    assert!(cpu.halted);
+    assert_eq!(cpu.registers.get(Register::AX), 42);
+}
+
+fn run_dos(bytes: &[u8], dos: Dos) -> (Cpu, Rc<RefCell<Dos>>) {
+    let dos = Rc::new(RefCell::new(dos));
+    let options = SimulateOptions {
+        load: Load::Com {
+            segment: 0x1000,
+            tail: String::new(),
+        },
+        dos: Some(dos.clone()),
+        ..Default::default()
+    };
+
+    let (cpu, _) = run_program(bytes.to_vec(), &options).expect("Failed to run");
+
+    (cpu, dos)
+}
+
+#[test]
+fn dos_console_files_and_exit() {
+    let sandbox = temp_dir().join("computer_enhance_dos_files");
+    let _ = fs::remove_dir_all(&sandbox);
+    fs::create_dir_all(&sandbox).unwrap();
+
+    let bytes = [
+        0xBA, 0x25, 0x01, // mov dx, message
+        0xB4, 0x09, // mov ah, 9
+        0xCD, 0x21, // int 21h
+        0xB4, 0x3C, // mov ah, 3Ch
+        0x31, 0xC9, // xor cx, cx
+        0xBA, 0x2B, 0x01, // mov dx, name
+        0xCD, 0x21, // int 21h
+        0x89, 0xC3, // mov bx, ax
+        0xB4, 0x40, // mov ah, 40h
+        0xB9, 0x05, 0x00, // mov cx, 5
+        0xBA, 0x25, 0x01, // mov dx, message
+        0xCD, 0x21, // int 21h
+        0xB4, 0x3E, // mov ah, 3Eh
+        0xCD, 0x21, // int 21h
+        0xB8, 0x03, 0x4C, // mov ax, 4C03h
+        0xCD, 0x21, // int 21h
+        b'h', b'e', b'l', b'l', b'o', b'$', // message
+        b'o', b'u', b't', b'.', b't', b'x', b't', 0, // name
+    ];
+
+    let (cpu, dos) = run_dos(&bytes, Dos::new(&sandbox));
+
+    assert!(cpu.halted);
+    assert_eq!(dos.borrow().exit_code(), Some(3));
+    assert_eq!(dos.borrow().captured(), b"hello");
+    assert_eq!(fs::read(sandbox.join("out.txt")).unwrap(), b"hello");
+
+    fs::remove_dir_all(&sandbox).unwrap();
+}
+
+#[test]
+fn dos_buffered_input_and_memory() {
+    let bytes = [
+        0xC6, 0x06, 0x00, 0x02, 0x05, // mov byte [0x200], 5
+        0xBA, 0x00, 0x02, // mov dx, 0x200
+        0xB4, 0x0A, // mov ah, 0Ah
+        0xCD, 0x21, // int 21h
+        0xB4, 0x4A, // mov ah, 4Ah
+        0xBB, 0x00, 0x10, // mov bx, 0x1000
+        0xCD, 0x21, // int 21h
+        0xB4, 0x48, // mov ah, 48h
+        0xBB, 0x10, 0x00, // mov bx, 0x10
+        0xCD, 0x21, // int 21h
+        0x89, 0xC6, // mov si, ax
+        0xB4, 0x48, // mov ah, 48h
+        0xBB, 0xFF, 0xFF, // mov bx, 0xFFFF
+        0xCD, 0x21, // int 21h
+        0xF4, // hlt
+    ];
+    let dos = Dos::new(temp_dir()).with_input(Box::new(&b"abcdefg\r\n"[..]));
+
+    let (cpu, dos) = run_dos(&bytes, dos);
+
+    assert_eq!(cpu.memory.range(0x10201, 6), b"\x04abcd\r");
+    assert_eq!(dos.borrow().captured(), b"abcd\r");
+    assert_eq!(cpu.registers.get(Register::SI), 0x2000);
+    assert_eq!(cpu.registers.get(Register::AX), 8);
+    assert_eq!(cpu.registers.get(Register::BX), 0x7FF0);
+    assert!(cpu.registers.flags.get(Flags::CF));
+    assert_eq!(dos.borrow().exit_code(), None);
+}
+
+/// An .EXE whose code loads its data segment, relocated, into DS and reads
+/// the byte there.
+fn small_exe() -> Vec<u8> {
+    let mut bytes = vec![
+        b'M', b'Z', 49, 0, // 49 bytes in the only page
+        1, 0, // pages
+        1, 0, // relocations
+        2, 0, // header paragraphs
+        0, 0, 0xFF, 0xFF, // min and max alloc
+        2, 0, 0x00, 0x01, // ss:sp 0002:0100
+        0, 0, // checksum
+        0, 0, 0, 0, // cs:ip 0000:0000
+        0x1C, 0, 0, 0, // relocation table offset, overlay
+        1, 0, 0, 0, // relocation 0000:0001
+    ];
+    bytes.extend([
+        0xB8, 0x01, 0x00, // mov ax, seg 0x0001
+        0x8E, 0xD8, // mov ds, ax
+        0xA0, 0x00, 0x00, // mov al, [0]
+        0xF4, // hlt
+        0, 0, 0, 0, 0, 0, 0,  // padding
+        42, // data
+    ]);
+
+    bytes
+}
+
+#[test]
+fn parse_mz_header() {
+    let exe = Executable::parse(&small_exe()).expect("Failed to parse");
+
+    assert_eq!(exe.header.file_size(), 49);
+    assert_eq!(exe.header.header_paragraphs, 2);
+    assert_eq!((exe.header.ss, exe.header.sp), (2, 0x100));
+    assert_eq!(exe.relocations, [(1, 0)]);
+    assert_eq!(exe.image.len(), 17);
+    assert_eq!(exe.entry(), 0);
+
+    assert!(Executable::parse(b"not an exe").is_err());
+    assert!(Executable::parse(&small_exe()[..40]).is_err());
+}
+
+#[test]
+fn run_exe_with_relocations() {
+    let options = SimulateOptions {
+        load: Load::Exe {
+            segment: 0x1000,
+            tail: String::new(),
+        },
+        ..Default::default()
+    };
+
+    let (cpu, _) = run_program(small_exe(), &options).expect("Failed to run");
+
+    assert!(cpu.halted);
+    assert_eq!(cpu.memory.read_word(0x1010, 1), 0x1011);
+    assert_eq!(cpu.registers.get_segment(SegmentRegister::DS), 0x1011);
+    assert_eq!(cpu.registers.get(Register::AL), 42);
+    assert_eq!(cpu.registers.get_segment(SegmentRegister::ES), 0x1000);
+    assert_eq!(cpu.registers.get_segment(SegmentRegister::SS), 0x1012);
+    assert_eq!(cpu.registers.get(Register::SP), 0x100);
+    assert_eq!(cpu.memory.range(0x10000, 2), [0xCD, 0x20]);
+}
+
+#[test]
+fn disassemble_exe_segment_references() {
+    let exe = Executable::parse(&small_exe()).expect("Failed to parse");
+    let options = DisassembleOptions {
+        start: exe.entry(),
+        relocations: exe.relocation_offsets().into_iter().collect(),
+        ..Default::default()
+    };
+
+    let res = dissassemble_with(exe.image, &options).expect("Failed to disassemble");
+
+    assert!(res.starts_with("bits 16\r\n\r\nmov ax, seg 0x0001\r\nmov ds, ax\r\n"));
+
+    let far_call = Instruction::decode(&[0x9A, 0x10, 0x00, 0x02, 0x00]).unwrap();
+    assert_eq!(far_call.to_segment_string(3), "call seg 0x0002:16\r\n");
+}
+
+const INTEL_HEX: &str = "\
+:02000002F0000C
+:04000000B82A00F426
+:01010000906E
+:04000003F000000009
+:00000001FF
+";
+
+#[test]
+fn parse_intel_hex() {
+    let image = SparseImage::parse_intel_hex(INTEL_HEX).expect("Failed to parse");
+
+    assert_eq!(
+        image.regions,
+        [
+            (0xF0000, vec![0xB8, 0x2A, 0x00, 0xF4]),
+            (0xF0100, vec![0x90])
+        ]
+    );
+    assert_eq!(image.start, Some((0xF000, 0x0000)));
+
+    let corrupted = INTEL_HEX.replace(":01010000906E", ":01010000906F");
+    let error = SparseImage::parse_intel_hex(&corrupted).unwrap_err();
+    assert_eq!(error.to_string(), "Intel HEX line 3: bad checksum");
+}
+
+#[test]
+fn parse_srecord() {
+    let text = "S0060000726F6DAB\nS2070F0000B82A0007\nS2050F0003F4F4\nS8040F0000EC\n";
+    let image = SparseImage::parse_srecord(text).expect("Failed to parse");
+
+    assert_eq!(image.regions, [(0xF0000, vec![0xB8, 0x2A, 0x00, 0xF4])]);
+    assert_eq!(image.entry(), (0xF000, 0x0000));
+
+    assert!(SparseImage::parse_srecord("S2050F0003F4F5\n").is_err());
+}
+
+#[test]
+fn disassemble_and_run_intel_hex() {
+    let image = SparseImage::parse_intel_hex(INTEL_HEX).expect("Failed to parse");
+    let res = dissassemble_image(&image, &DisassembleOptions::default()).unwrap();
+
+    assert_eq!(
+        res,
+        "bits 16\r\n\
+         \r\n; 0xf0000-0xf0004\r\nmov ax, 42\r\nhlt\r\n\
+         \r\n; 0xf0100-0xf0101\r\nnop\r\n"
+    );
+
+    let options = SimulateOptions {
+        load: Load::IntelHex,
+        ..Default::default()
+    };
+    let (cpu, _) = run_program(INTEL_HEX.as_bytes().to_vec(), &options).expect("Failed to run");
+
+    assert!(cpu.halted);
+    assert_eq!(cpu.registers.get(Register::AX), 42);
+    assert_eq!(cpu.registers.get_segment(SegmentRegister::CS), 0xF000);
+}
+
+#[test]
+fn boot_sector_with_int13() {
+    let path = temp_dir().join("computer_enhance_floppy.img");
+    let mut bytes = vec![0; 1440 * 1024];
+    let code = [
+        0xB8, 0x00, 0x10, // mov ax, 0x1000
+        0x8E, 0xC0, // mov es, ax
+        0x31, 0xDB, // xor bx, bx
+        0xB8, 0x01, 0x02, // mov ax, 0x0201
+        0xB9, 0x02, 0x00, // mov cx, 0x0002
+        0xB6, 0x00, // mov dh, 0
+        0xCD, 0x13, // int 13h
+        0xB8, 0x01, 0x03, // mov ax, 0x0301
+        0xB9, 0x03, 0x00, // mov cx, 0x0003
+        0xCD, 0x13, // int 13h
+        0xB4, 0x08, // mov ah, 8
+        0xCD, 0x13, // int 13h
+        0xF4, // hlt
+    ];
+    bytes[..code.len()].copy_from_slice(&code);
+    bytes[510..512].copy_from_slice(&[0x55, 0xAA]);
+    bytes[512..522].copy_from_slice(b"sector two");
+    fs::write(&path, &bytes).unwrap();
+
+    let options = SimulateOptions {
+        load: Load::Boot { drive: 0 },
+        disk: Some(Rc::new(RefCell::new(Disk::open(&path, 0).unwrap()))),
+        ..Default::default()
+    };
+    let (cpu, _) = run_program(bytes.clone(), &options).expect("Failed to run");
+
+    assert!(cpu.halted);
+    assert_eq!(cpu.memory.range(0x10000, 10), b"sector two");
+    assert_eq!(&fs::read(&path).unwrap()[1024..1034], b"sector two");
+    assert_eq!(cpu.registers.get(Register::CX), 0x4F12);
+    assert_eq!(cpu.registers.get(Register::DX), 0x0101);
+    assert_eq!(cpu.registers.get(Register::BL), 4);
+    assert!(!cpu.registers.flags.get(Flags::CF));
+
+    bytes[511] = 0;
+    let error = run_program(bytes, &options).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Boot sector doesn't end with the 0x55AA signature"
+    );
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn video_text_mode() {
+    let bytes = [
+        0xB4, 0x0E, // mov ah, 0Eh
+        0xB0, b'H', 0xCD, 0x10, // mov al, 'H'; int 10h
+        0xB0, b'i', 0xCD, 0x10, // mov al, 'i'; int 10h
+        0xB0, 0x0D, 0xCD, 0x10, // mov al, 13; int 10h
+        0xB0, 0x0A, 0xCD, 0x10, // mov al, 10; int 10h
+        0xB0, b'!', 0xCD, 0x10, // mov al, '!'; int 10h
+        0xB4, 0x02, // mov ah, 2
+        0xB7, 0x00, // mov bh, 0
+        0xBA, 0x05, 0x02, // mov dx, 0x0205
+        0xCD, 0x10, // int 10h
+        0xB8, b'X', 0x09, // mov ax, 0x0958
+        0xB3, 0x1E, // mov bl, 0x1E
+        0xB9, 0x03, 0x00, // mov cx, 3
+        0xCD, 0x10, // int 10h
+        0xB8, 0x00, 0xB8, // mov ax, 0xB800
+        0x8E, 0xC0, // mov es, ax
+        0x26, 0xC6, 0x06, 0x00, 0x0F, b'Z', // mov byte [es:3840], 'Z'
+        0xB8, 0x01, 0x06, // mov ax, 0x0601
+        0xB7, 0x07, // mov bh, 7
+        0x31, 0xC9, // xor cx, cx
+        0xBA, 0x4F, 0x18, // mov dx, 0x184F
+        0xCD, 0x10, // int 10h
+        0xF4, // hlt
+    ];
+    let options = SimulateOptions {
+        load: Load::Com {
+            segment: 0x1000,
+            tail: String::new(),
+        },
+        video: Some(Video::cga()),
+        ..Default::default()
+    };
+
+    let (cpu, _) = run_program(bytes.to_vec(), &options).expect("Failed to run");
+
+    let mut expected = String::from("!\r\n     XXX\r\n");
+    expected.push_str(&"\r\n".repeat(21));
+    expected.push_str("Z\r\n\r\n");
+    assert_eq!(video::screen_text(&cpu.memory), expected);
+
+    assert_eq!(cpu.memory.read_byte(0xB800, 2 * (80 + 5) + 1), 0x1E);
+    assert_eq!(cpu.memory.range(0x450, 2), [5, 2]);
+    assert!(cpu.registers.flags.get(Flags::IF));
+    assert!(video::screen_ansi(&cpu.memory).starts_with("\x1b[37;40m!"));
+}
+
+/// A latch that reads back the last byte written to each of its ports.
+#[derive(Default)]
+struct Latch {
+    values: [u8; 4],
+}
+
+impl IoDevice for Latch {
+    fn read_byte(&mut self, port: u16) -> u8 {
+        self.values[(port & 3) as usize]
+    }
+
+    fn write_byte(&mut self, port: u16, value: u8) {
+        self.values[(port & 3) as usize] = value;
+    }
+}
+
+#[test]
+fn io_bus_devices() {
+    let bytes = [
+        0xB8, 0x34, 0x12, // mov ax, 0x1234
+        0xE7, 0x60, // out 0x60, ax
+        0xE4, 0x61, // in al, 0x61
+        0x88, 0xC3, // mov bl, al
+        0xBA, 0x00, 0x03, // mov dx, 0x300
+        0xEE, // out dx, al
+        0xED, // in ax, dx
+        0xF4, // hlt
+    ];
+    let latch = Rc::new(RefCell::new(Latch::default()));
+    let mut io = IoBus::new();
+    io.attach(0x60..=0x63, latch.clone()).unwrap();
+    assert!(io.attach(0x40..=0x60, latch.clone()).is_err());
+
+    let options = SimulateOptions {
+        io,
+        ..Default::default()
+    };
+    let (mut cpu, trace) = run_program(bytes.to_vec(), &options).expect("Failed to run");
+
+    assert_eq!(latch.borrow().values, [0x34, 0x12, 0, 0]);
+    assert_eq!(cpu.registers.get(Register::BL), 0x12);
+    assert_eq!(cpu.registers.get(Register::AX), 0xFFFF);
+    assert!(
+        trace.contains("out dx, al ; ip:0xc->0xd\r\n; unclaimed port 0x0300: byte write 0x12\r\n")
+    );
+    assert!(trace.contains("; unclaimed port 0x0300: word read\r\n"));
+    assert!(cpu.io.take_unclaimed().is_empty());
+
+    let mut bus = IoBus::new();
+    bus.write(0x20, false, 0x20);
+    assert_eq!(
+        bus.take_unclaimed(),
+        [PortAccess {
+            port: 0x20,
+            w: false,
+            written: Some(0x20),
+        }]
+    );
+}
+
+#[test]
+fn timer_interrupts() {
+    let bytes = [
+        0xFA, // cli
+        0x31, 0xC0, // xor ax, ax
+        0x8E, 0xD8, // mov ds, ax
+        0xC7, 0x06, 0x20, 0x00, 0x3A, 0x01, // mov word [0x20], handler
+        0x8C, 0x0E, 0x22, 0x00, // mov [0x22], cs
+        0x0E, 0x1F, // push cs; pop ds
+        0xB0, 0x13, 0xE6, 0x20, // mov al, 0x13; out 0x20, al
+        0xB0, 0x08, 0xE6, 0x21, // mov al, 8; out 0x21, al
+        0xB0, 0x01, 0xE6, 0x21, // mov al, 1; out 0x21, al
+        0xB0, 0xFE, 0xE6, 0x21, // mov al, 0xFE; out 0x21, al
+        0xB0, 0x34, 0xE6, 0x43, // mov al, 0x34; out 0x43, al
+        0xB0, 0x64, 0xE6, 0x40, // mov al, 100; out 0x40, al
+        0xB0, 0x00, 0xE6, 0x40, // mov al, 0; out 0x40, al
+        0xFB, // sti
+        0xF4, // wait: hlt
+        0x83, 0x3E, 0x38, 0x01, 0x03, // cmp word [ticks], 3
+        0x72, 0xF8, // jb wait
+        0xFA, 0xF4, // cli; hlt
+        0x00, 0x00, // ticks: dw 0
+        0xFF, 0x06, 0x38, 0x01, // handler: inc word [ticks]
+        0xB0, 0x20, 0xE6, 0x20, // mov al, 0x20; out 0x20, al
+        0xCF, // iret
+    ];
+    let pic = Rc::new(RefCell::new(Pic::new()));
+    let options = SimulateOptions {
+        load: Load::Com {
+            segment: 0x1000,
+            tail: String::new(),
+        },
+        pit: Some(Rc::new(RefCell::new(Pit::new()))),
+        pic: Some(pic.clone()),
+        ..Default::default()
+    };
+
+    let (cpu, trace) = run_program(bytes.to_vec(), &options).expect("Failed to run");
+
+    assert!(cpu.halted);
+    assert_eq!(cpu.memory.read_word(0x1000, 0x138), 3);
+    // Setting up, three periods of 100 ticks, then the instructions that
+    // follow the last interrupt.
+    assert!((1200..1600).contains(&cpu.clocks), "{}", cpu.clocks);
+    assert!(!trace.contains("unclaimed"));
+    assert_eq!(pic.borrow_mut().read_byte(0x21), 0xFE);
+    assert_eq!(pic.borrow_mut().read_byte(0x20), 0);
+
+    // The BIOS handler counts ticks at 0040:006C.
+    let options = SimulateOptions {
+        pit: Some(Rc::new(RefCell::new(Pit::new()))),
+        pic: Some(Rc::new(RefCell::new(Pic::new()))),
+        ..options
+    };
+    let bytes = [0xFB, 0xF4, 0xF4, 0xFA, 0xF4]; // sti; hlt; hlt; cli; hlt
+    let (cpu, _) = run_program(bytes.to_vec(), &options).expect("Failed to run");
+
+    assert_eq!(cpu.memory.read_word(0x40, 0x6C), 2);
+    assert!(cpu.clocks >= 2 * 65536 * 4);
+}
+
+#[test]
+fn step_back_with_timer() {
+    let bytes = [0xB9, 0x00, 0x10, 0xE2, 0xFE, 0xF4]; // mov cx, 0x1000; loop $; hlt
+    let timer = || SimulateOptions {
+        pit: Some(Rc::new(RefCell::new(Pit::new()))),
+        pic: Some(Rc::new(RefCell::new(Pic::new()))),
+        ..Default::default()
+    };
+
+    let mut debugger = Debugger::new(&bytes, &timer()).unwrap();
+    debugger.command("s 3000").unwrap();
+    // Far enough back to replay from a snapshot.
+    debugger.command("sb 2000").unwrap();
+    debugger.command("s 5").unwrap();
+
+    let mut expected = Debugger::new(&bytes, &timer()).unwrap();
+    expected.command("s 1005").unwrap();
+
+    let state = |debugger: &Debugger| {
+        let cpu = &debugger.cpu;
+        (
+            cpu.registers,
+            cpu.clocks,
+            cpu.pit_ticks,
+            cpu.pit.as_ref().unwrap().borrow().clone(),
+            cpu.pic.as_ref().unwrap().borrow().clone(),
+        )
+    };
+    assert_eq!(state(&debugger), state(&expected));
+
+    // And back within the undo log.
+    debugger.command("sb 3").unwrap();
+    debugger.command("s 3").unwrap();
+    assert_eq!(state(&debugger), state(&expected));
+}
+
+#[test]
+fn pit_and_pic_registers() {
+    let mut pit = Pit::new();
+    pit.write_byte(0x43, 0b1011_0000); // counter 2, lo/hi, mode 0
+    pit.write_byte(0x42, 0x10);
+    pit.write_byte(0x42, 0x02);
+    pit.advance(0x10);
+    pit.write_byte(0x43, 0b1000_0000); // latch counter 2
+    pit.advance(5);
+    assert_eq!(pit.read_byte(0x42), 0x00);
+    assert_eq!(pit.read_byte(0x42), 0x02);
+    assert_eq!(pit.read_byte(0x42), 0xFB);
+    assert_eq!(pit.read_byte(0x42), 0x01);
+
+    // Counter 0 in mode 3 with a count of 10 rises every 10 ticks.
+    pit.write_byte(0x43, 0b0001_0110);
+    pit.write_byte(0x40, 10);
+    assert_eq!(pit.until_irq(), Some(10));
+    assert!(!pit.advance(9));
+    assert!(pit.advance(1));
+    assert_eq!(pit.read_byte(0x40), 10);
+
+    let mut pic = Pic::new();
+    pic.request(3);
+    pic.request(1);
+    assert_eq!(pic.acknowledge(), Some(0x09));
+    // IRQ3 waits for the EOI of the higher priority IRQ1.
+    assert_eq!(pic.pending(), None);
+    pic.write_byte(0x20, 0x0B); // read the ISR
+    assert_eq!(pic.read_byte(0x20), 0b10);
+    pic.write_byte(0x20, 0x20);
+    assert_eq!(pic.acknowledge(), Some(0x0B));
+    pic.write_byte(0x20, 0x63); // specific EOI of IRQ3
+
+    // Rotating makes IRQ3 the lowest priority, and masked requests wait.
+    pic.write_byte(0x20, 0xC3);
+    pic.write_byte(0x21, 0b0001_0000);
+    pic.request(4);
+    pic.request(2);
+    pic.request(6);
+    assert_eq!(pic.acknowledge(), Some(0x0E));
+    pic.write_byte(0x20, 0x20);
+    assert_eq!(pic.acknowledge(), Some(0x0A));
+}
+
+#[test]
+fn interrupt_sequence() {
+    // Setting TF traps after the instruction that follows, and the flags
+    // the trap pushes keep it set for the instruction after the iret.
+    let bytes = [
+        0x9C, // pushf
+        0x58, // pop ax
+        0x0D, 0x00, 0x01, // or ax, 0x100
+        0x50, // push ax
+        0x9D, // popf
+        0x90, // nop
+        0x90, // nop
+    ];
+    let mut cpu = Cpu::new();
+    cpu.memory.load(0x10000, &bytes);
+    cpu.memory.write_byte(0, 0x600, 0xCF); // iret
+    cpu.memory.write_word(0, 4, 0x600);
+    cpu.registers.set_segment(SegmentRegister::CS, 0x1000);
+    cpu.registers.set(Register::SP, 0x400);
+
+    for _ in 0..5 {
+        cpu.step().expect("Failed to step");
+    }
+    assert_eq!(cpu.registers.ip, 7);
+    cpu.step().expect("Failed to step");
+    assert_eq!(cpu.code_address(), 0x600);
+    assert_eq!(cpu.memory.read_word(0, 0x3FA), 8);
+    cpu.step().expect("Failed to step");
+    assert_eq!(cpu.code_address(), 0x10008);
+    cpu.step().expect("Failed to step");
+    assert_eq!(cpu.code_address(), 0x600);
+    assert_eq!(cpu.memory.read_word(0, 0x3FA), 9);
+
+    // Host handlers see where each interrupt is taken: IRQ0 only after the
+    // instruction following sti, and the NMI only after the instruction
+    // following mov ss.
+    let bytes = [
+        0xFB, // sti
+        0x90, // nop
+        0xB8, 0x00, 0x00, // mov ax, 0
+        0x8E, 0xD0, // mov ss, ax
+        0x90, // nop
+        0xCC, // int3
+        0xB0, 0x7F, // mov al, 0x7F
+        0x04, 0x01, // add al, 1
+        0xCE, // into
+        0xF4, // hlt
+    ];
+    let taken = Rc::new(RefCell::new(Vec::new()));
+    let log = taken.clone();
+    let handler = Rc::new(RefCell::new(
+        move |cpu: &mut Cpu, vector: u8| -> Result<bool, Box<dyn Error>> {
+            log.borrow_mut().push((vector, cpu.registers.ip));
+            Ok(true)
+        },
+    ));
+    let mut handlers = Handlers::new();
+    for vector in [2, 3, 4, 8] {
+        handlers.attach(vector, handler.clone());
+    }
+
+    let pic = Rc::new(RefCell::new(Pic::new()));
+    pic.borrow_mut().request(0);
+    let mut cpu = Cpu::new();
+    cpu.memory.load(0x10000, &bytes);
+    cpu.registers.set_segment(SegmentRegister::CS, 0x1000);
+    cpu.registers.set(Register::SP, 0x400);
+    cpu.pic = Some(pic);
+    cpu.handlers = handlers;
+
+    for _ in 0..4 {
+        cpu.step().expect("Failed to step");
+    }
+    cpu.request_nmi();
+    while !cpu.halted {
+        cpu.step().expect("Failed to step");
+    }
+
+    assert_eq!(*taken.borrow(), [(8, 2), (2, 8), (3, 9), (4, 0xE)]);
+    assert_eq!(cpu.registers.get(Register::SP), 0x400);
+}
+
+#[test]
+fn bus_prefetch_queue() {
+    // Patches the instruction `distance` nops past the mov to inc ax.
+    let program = |distance: usize| {
+        let mut bytes = vec![0xC6, 0x06, 5 + distance as u8, 0x00, 0x40]; // mov byte [patch], 0x40
+        bytes.extend(std::iter::repeat_n(0x90, distance + 1)); // nop
+        bytes.push(0xF4); // hlt
+        bytes
+    };
+    let run = |distance: usize, model: CpuModel, bus: Option<BusConfig>| {
+        let options = SimulateOptions {
+            model,
+            bus,
+            ..Default::default()
+        };
+        let (cpu, _) = run_program(program(distance), &options).expect("Failed to run");
+
+        (cpu.registers.get(Register::AX), cpu.clocks)
+    };
+    let bus = Some(BusConfig::default());
+
+    // Without the model the patch always takes effect. With it, bytes already
+    // in the queue run unpatched: the 8088's 4-byte queue reaches fewer bytes
+    // past the mov than the 8086's 6-byte queue.
+    assert_eq!(run(0, CpuModel::I8088, None).0, 1);
+    assert_eq!(run(0, CpuModel::I8088, bus).0, 0);
+    assert_eq!(run(2, CpuModel::I8088, bus).0, 0);
+    assert_eq!(run(5, CpuModel::I8088, bus).0, 1);
+    assert_eq!(run(4, CpuModel::I8086, bus).0, 0);
+    assert_eq!(run(5, CpuModel::I8086, bus).0, 1);
+
+    // Fetching one byte at a time, the 8088 is slower, and wait states slow
+    // every bus cycle.
+    let (_, i8088) = run(5, CpuModel::I8088, bus);
+    let (_, i8086) = run(5, CpuModel::I8086, bus);
+    let (_, waiting) = run(
+        5,
+        CpuModel::I8088,
+        Some(BusConfig {
+            wait_states: 1,
+            ..Default::default()
+        }),
+    );
+    assert!(i8086 < i8088 && i8088 < waiting);
+
+    let options = SimulateOptions {
+        model: CpuModel::I8088,
+        show_clocks: true,
+        bus: Some(BusConfig {
+            trace: true,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let (_, trace) = run_program(program(1), &options).expect("Failed to run");
+
+    assert!(trace.starts_with("mov byte [6], 64 ; Cycles: +39 = 39 | ip:0x0->0x5\r\n"));
+    assert!(trace.contains(";       0 T1 CODE 00000      q:\r\n"));
+    assert!(trace.contains(";      32 T1 MEMW 00006      q:9090F4\r\n"));
+    assert!(trace.contains(";      35 T4 MEMW       40   q:9090F4\r\n"));
+}
+
+#[test]
+fn instruction_statistics() {
+    let bytes = [
+        0x89, 0xD8, // mov ax, bx
+        0x88, 0x08, // mov [bx + si], cl
+        0xA0, 0x34, 0x12, // mov al, [4660]
+        0xF3, 0xA4, // rep movsb
+        0x26, 0x89, 0x46, 0x02, // mov es:[bp + 2], ax
+        0x41, // inc cx
+    ];
+
+    let stats = statistics_with(&bytes, &DisassembleOptions::default());
+
+    assert_eq!(stats.instructions, 6);
+    assert_eq!(stats.bytes, bytes.len());
+    assert_eq!(stats.prefixed, 2);
+    assert_eq!(stats.mnemonics["mov"], 4);
+    assert_eq!(stats.mnemonics["movsb"], 1);
+    assert_eq!(stats.mnemonics["inc"], 1);
+    assert_eq!(
+        stats
+            .lengths
+            .iter()
+            .map(|(l, c)| (*l, *c))
+            .collect::<Vec<_>>(),
+        vec![(1, 1), (2, 3), (3, 1), (4, 1)]
+    );
+    assert_eq!(
+        stats.modes,
+        vec![
+            (Mode::Reg, 1),
+            (Mode::Mem, 1),
+            (Mode::Mem8, 1),
+            (Mode::Mem16, 0),
+            (Mode::DirectAddress, 1),
+        ]
+    );
+    assert_eq!(stats.registers["ax"], 2);
+    assert_eq!(stats.registers["bx"], 2);
+    assert_eq!(stats.registers["es"], 1);
+    assert!(!stats.registers.contains_key("di"));
+
+    let report = stats.to_string();
+    assert!(report.starts_with("instructions: 6 (14 bytes)\r\nprefixed: 2 (33.3%)\r\n"));
+    assert!(report.contains("\r\nmnemonic         count\r\nmov                  4    66.7%\r\n"));
+    assert!(report.contains("DirectAddress        1    16.7%\r\n"));
+}
+
+#[test]
+fn json_round_trip() {
+    let mut bytes = include_bytes!("../listings/listing_56").to_vec();
+    bytes.extend([
+        0x26, 0x89, 0x46, 0x02, // mov [es:bp + 2], ax
+        0xF3, 0xA4, // rep movsb
+        0xEB, 0xFE, // jmp short $+0
+        0x9A, 0x34, 0x12, 0x00, 0xF0, // call 61440:4660
+        0x2E, 0x74, 0x00, // cs je $+2
+        0xD4, 0x10, // aam 16
+    ]);
+
+    let text = dissassemble_json(&bytes, &DisassembleOptions::default()).unwrap();
+    let listing = Listing::from_json(&text).unwrap();
+    assert_eq!(listing.version, 1);
+
+    for instruction in &listing.instructions {
+        let decoded = instruction.instruction().expect("Failed to round-trip");
+        assert_eq!(decoded.to_string().trim_end(), instruction.text);
+    }
+    assert_eq!(listing.to_json().unwrap(), text);
+
+    let [.., mov, rep, jmp, call, je, aam] = listing.instructions.as_slice() else {
+        panic!("Too few instructions");
+    };
+    assert_eq!(mov.prefixes, ["es"]);
+    assert_eq!(
+        mov.operands[0],
+        Operand::Memory {
+            size: Some(16),
+            segment: Some("es".to_string()),
+            base: Some("bp".to_string()),
+            index: None,
+            displacement: Some(2),
+        }
+    );
+    assert_eq!((rep.mnemonic.as_str(), rep.length), ("movsb", 2));
+    assert_eq!(
+        jmp.operands,
+        [Operand::Relative {
+            displacement: 0,
+            target: Some(jmp.address),
+        }]
+    );
+    assert_eq!(
+        call.operands,
+        [Operand::Far {
+            segment: 0xF000,
+            offset: 0x1234,
+        }]
+    );
+    assert_eq!(
+        je.operands,
+        [Operand::Relative {
+            displacement: 3,
+            target: Some(je.address + 3),
+        }]
+    );
+    assert_eq!(
+        aam.operands,
+        [Operand::Immediate {
+            size: Some(8),
+            immediate: 16,
+        }]
+    );
+
+    // Fields that don't describe the bytes, and other versions, are refused.
+    let mut tampered = mov.clone();
+    tampered.mnemonic = "add".to_string();
+    assert!(tampered.instruction().is_err());
+    assert!(Listing::from_json(&text.replacen("\"version\": 1", "\"version\": 2", 1)).is_err());
+
+    // Branches back past address 0 have no target rather than a wrapped one.
+    let bytes = [
+        0x90, // nop
+        0x90, // nop
+        0xEB, 0xFA, // jmp short $-4
+        0xF4, // hlt
+    ];
+
+    let text = dissassemble_json(&bytes, &DisassembleOptions::default()).unwrap();
+    assert!(text.contains("\"target\": null"));
+    let listing = Listing::from_json(&text).unwrap();
+    assert_eq!(
+        listing.instructions[2].operands,
+        [Operand::Relative {
+            displacement: -4,
+            target: None,
+        }]
+    );
+}
+
+#[test]
+fn control_flow_graph_edges() {
+    let bytes = [
+        0xB9, 0x03, 0x00, // mov cx, 3
+        0xE8, 0x05, 0x00, // call $+8
+        0xE2, 0xFB, // loop $-3
+        0xCD, 0x20, // int 32
+        0xF4, // hlt
+        0x01, 0xC3, // add bx, ax
+        0x74, 0x01, // je $+3
+        0xC3, // ret
+        0xC3, // ret
+    ];
+
+    let graph = control_flow_graph(&bytes, &DisassembleOptions::default());
+
+    assert_eq!(
+        graph.blocks.keys().copied().collect::<Vec<_>>(),
+        [0x00, 0x03, 0x06, 0x08, 0x0A, 0x0B, 0x0F, 0x10]
+    );
+    assert_eq!(graph.blocks[&0x0B].end, 0x0F);
+
+    let edge = |from, to, kind| Edge { from, to, kind };
+    assert_eq!(
+        graph.edges.iter().copied().collect::<Vec<_>>(),
+        [
+            edge(0x00, 0x03, EdgeKind::Fallthrough),
+            edge(0x03, 0x06, EdgeKind::Fallthrough),
+            edge(0x03, 0x0B, EdgeKind::Call),
+            edge(0x06, 0x03, EdgeKind::Taken),
+            edge(0x06, 0x08, EdgeKind::Fallthrough),
+            edge(0x08, 0x0A, EdgeKind::Fallthrough),
+            edge(0x0B, 0x0F, EdgeKind::Fallthrough),
+            edge(0x0B, 0x10, EdgeKind::Taken),
+            edge(0x0F, 0x06, EdgeKind::Return),
+            edge(0x10, 0x06, EdgeKind::Return),
+        ]
+    );
+
+    let dot = graph.to_dot();
+    assert!(dot.starts_with("digraph cfg {\r\n"));
+    assert!(dot.contains("    b_000b [label=\"0x000b  add bx, ax\\l0x000d  je $+3\\l\"];\r\n"));
+    assert!(dot.contains("    b_0006 -> b_0003 [label=\"taken\", style=solid];\r\n"));
+}
+
+#[test]
+fn control_flow_past_64k() {
+    let mut bytes = vec![0; 0x10003];
+    bytes[..3].copy_from_slice(&[0xE9, 0xFD, 0x7F]); // jmp $+0x8000
+    bytes[0x8000..0x8003].copy_from_slice(&[0xE9, 0xFA, 0x7F]); // jmp $+0x7ffd
+    bytes[0xFFFD..].copy_from_slice(&[
+        0xE8, 0x02, 0x00, // call $+5
+        0xF4, // hlt
+        0xFF, // data
+        0xC3, // ret
+    ]);
+    let options = DisassembleOptions {
+        recursive: true,
+        ..Default::default()
+    };
+
+    let graph = control_flow_graph(&bytes, &options);
+    assert!(graph.edges.contains(&Edge {
+        from: 0xFFFD,
+        to: 0x10002,
+        kind: EdgeKind::Call,
+    }));
+    assert!(graph.blocks.contains_key(&0x10000));
+
+    let starts: Vec<usize> = functions(&bytes, &options)
+        .iter()
+        .map(|function| function.start)
+        .collect();
+    assert_eq!(starts, [0x10002]);
+}
+
+#[test]
+fn disassemble_recursive_traversal() {
+    let bytes = [
+        0xEB, 0x06, // jmp short $+8
+        b'H', b'e', b'l', b'l', b'o', 0x00, // data
+        0xB4, 0x09, // mov ah, 9
+        0xCD, 0x21, // int 33
+        0xC3, // ret
+        0xFF, 0xFF, // data
+    ];
+    let options = DisassembleOptions {
+        recursive: true,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        dissassemble_with(bytes.to_vec(), &options).unwrap(),
+        "bits 16\r\n\r\n\
+         jmp short $+8\r\n\
+         db 'Hello', 0x00\r\n\
+         mov ah, 9\r\n\
+         int 33\r\n\
+         ret\r\n\
+         db 0xff, 0xff\r\n"
+    );
+
+    // A routine only reached through a pointer, a relocated segment and a
+    // long run of data.
+    let mut bytes = vec![
+        0xC3, // ret
+        0x34, 0x12, // relocated word
+        0xB0, 0x01, // mov al, 1
+        0xC3, // ret
+    ];
+    bytes.extend([0; 20]);
+    let options = DisassembleOptions {
+        recursive: true,
+        entries: BTreeSet::from([3]),
+        relocations: BTreeSet::from([1]),
+        ..Default::default()
+    };
+
+    let res = dissassemble_with(bytes, &options).unwrap();
+    let zeros = |count| vec!["0x00"; count].join(", ");
+    assert_eq!(
+        res,
+        format!(
+            "bits 16\r\n\r\n\
+             ret\r\n\
+             db 0x34, 0x12 ; dw seg 0x1234\r\n\
+             mov al, 1\r\n\
+             ret\r\n\
+             db {}\r\n\
+             db {}\r\n",
+            zeros(16),
+            zeros(4)
+        )
+    );
+
+    // Encodings NASM would assemble differently are listed as bytes.
+    let bytes = [
+        0x8B, 0xC3, // mov ax, bx with the d bit set
+        0x82, 0xC0, 0x05, // add al, 5 with the 0x82 alias
+        0x89, 0x06, 0x00, 0x01, // mov [256], ax without the accumulator form
+        0x8B, 0x47, 0x00, // mov ax, [bx + 0]
+        0x8B, 0x87, 0x10, 0x00, // mov ax, [bx + 16] with a word displacement
+        0x81, 0xC3, 0x05, 0x00, // add bx, 5 with a word immediate
+        0xFF, 0xC0, // inc ax
+        0x26, 0x2E, 0x8B, 0x07, // two segment overrides
+        0x26, 0xF3, 0xA4, // segment override before rep
+        0x2E, 0x74, 0x00, // cs je $+2
+        0x8B, 0x46, 0x00, // mov ax, [bp + 0]
+        0x83, 0xC0, 0x05, // add ax, 5
+        0xF4, // hlt
+    ];
+    let options = DisassembleOptions {
+        recursive: true,
+        ..Default::default()
+    };
+    let res = dissassemble_with(bytes.to_vec(), &options).unwrap();
+    assert_eq!(
+        res,
+        "bits 16\r\n\r\n\
+         db 0x8b, 0xc3 ; mov ax, bx\r\n\
+         db 0x82, 0xc0, 0x05 ; add al, 5\r\n\
+         db 0x89, 0x06, 0x00, 0x01 ; mov [256], ax\r\n\
+         db 0x8b, 0x47, 0x00 ; mov ax, [bx + 0]\r\n\
+         db 0x8b, 0x87, 0x10, 0x00 ; mov ax, [bx + 16]\r\n\
+         db 0x81, 0xc3, 0x05, 0x00 ; add bx, 5\r\n\
+         db 0xff, 0xc0 ; inc ax\r\n\
+         db 0x26, 0x2e, 0x8b, 0x07 ; mov ax, [es:cs:bx]\r\n\
+         db 0x26, 0xf3, 0xa4 ; es rep movsb\r\n\
+         db 0x2e, 0x74, 0x00 ; cs je $+2\r\n\
+         mov ax, [bp + 0]\r\n\
+         add ax, 5\r\n\
+         hlt\r\n"
+    );
+}
+
+#[test]
+fn function_frames() {
+    let bytes = [
+        0x50, // push ax
+        0xE8, 0x04, 0x00, // call $+7
+        0x83, 0xC4, 0x02, // add sp, 2
+        0xF4, // hlt
+        0x55, // push bp
+        0x89, 0xE5, // mov bp, sp
+        0x83, 0xEC, 0x04, // sub sp, 4
+        0x8B, 0x46, 0x04, // mov ax, [bp + 4]
+        0x89, 0x46, 0xFE, // mov [bp - 2], ax
+        0x89, 0xEC, // mov sp, bp
+        0x5D, // pop bp
+        0xC3, // ret
+        0x55, // push bp
+        0x89, 0xE5, // mov bp, sp
+        0x8B, 0x42, 0x06, // mov ax, [bp + si + 6]
+        0x5D, // pop bp
+        0xCB, // retf
+    ];
+    let options = DisassembleOptions {
+        functions: true,
+        ..Default::default()
+    };
+
+    let found = functions(&bytes, &options);
+    assert_eq!(
+        found
+            .iter()
+            .map(|f| (f.start, f.framed, f.locals, f.far))
+            .collect::<Vec<_>>(),
+        [(0x08, true, 4, false), (0x18, true, 0, true)]
+    );
+    assert_eq!(found[0].epilogues, BTreeSet::from([0x14]));
+    assert_eq!(found[1].epilogues, BTreeSet::from([0x1E]));
+
+    let res = dissassemble_with(bytes.to_vec(), &options).unwrap();
+    assert!(res.contains(
+        "\r\n; function 0x0008: bp frame, 4 bytes of locals, 2 bytes of arguments\r\n\
+         %define arg_0 4\r\n\
+         %define local_2 2\r\n\
+         push bp\r\n"
+    ));
+    assert!(res.contains(
+        "mov ax, [bp + arg_0]\r\nmov [bp - local_2], ax\r\n; epilogue\r\nmov sp, bp\r\n"
+    ));
+    // Far functions' arguments start past the return segment too.
+    assert!(res.contains("%define arg_0 6\r\n"));
+    assert!(res.contains("mov ax, [bp + si + arg_0]\r\n; epilogue\r\npop bp\r\nretf\r\n"));
+
+    // Instructions before the header that a backward jump reaches keep their
+    // numbers, since the `%define`s come after them.
+    let bytes = [
+        0x8B, 0x46, 0x04, // mov ax, [bp + 4]
+        0xC3, // ret
+        0x55, // push bp
+        0x89, 0xE5, // mov bp, sp
+        0xEB, 0xF7, // jmp short $-7
+    ];
+
+    let res = dissassemble_with(bytes.to_vec(), &options).unwrap();
+    assert_eq!(
+        res,
+        "bits 16\r\n\r\n\
+         mov ax, [bp + 4]\r\n\
+         ret\r\n\
+         \r\n; function 0x0004: bp frame, 0 bytes of locals\r\n\
+         push bp\r\n\
+         mov bp, sp\r\n\
+         jmp short $-7\r\n"
+    );
+
+    // The prologue is found in its other encoding too.
+    let bytes = [
+        0x55, // push bp
+        0x8B, 0xEC, // mov bp, sp
+        0x81, 0xEC, 0x00, 0x01, // sub sp, 256
+        0x8B, 0xE5, // mov sp, bp
+        0x5D, // pop bp
+        0xC3, // ret
+    ];
+
+    let found = functions(&bytes, &options);
+    assert_eq!(
+        found
+            .iter()
+            .map(|f| (f.start, f.framed, f.locals, f.far))
+            .collect::<Vec<_>>(),
+        [(0, true, 256, false)]
+    );
+    assert_eq!(found[0].epilogues, BTreeSet::from([0x07]));
+}
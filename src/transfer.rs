@@ -0,0 +1,193 @@
+use crate::{mode::Mode, register::Register};
+
+/// `xchg` between a register and a register or memory operand (`1000011w`).
+#[derive(Debug)]
+pub struct Exchange {
+    pub(crate) w: bool,
+    pub(crate) mode: Mode,
+    pub(crate) reg: Register,
+    pub(crate) rm: Register,
+    pub(crate) disp_lo: Option<u8>,
+    pub(crate) disp_hi: Option<u8>,
+}
+
+impl Exchange {
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let w = (bytes[0] & 0b0000_0001) == 0b0000_0001;
+
+        let (mode, rm, disp_lo, disp_hi) = Mode::decode_rm(bytes, w);
+        let reg = Register::decode_reg((bytes[1] & 0b0011_1000) >> 3, w);
+
+        Self {
+            w,
+            mode,
+            reg,
+            rm,
+            disp_lo,
+            disp_hi,
+        }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        2 + self.mode.displacement_len()
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        let rm = self.rm.rm_to_string(self.mode, self.disp_lo, self.disp_hi);
+        let reg = self.reg.register_mode_to_string();
+
+        res.push_str(&format!("xchg {}, {}\r\n", reg, rm));
+        res
+    }
+}
+
+/// `xchg` between AX and a word register (`10010reg`). `xchg ax, ax` is
+/// `nop` and decoded as such.
+#[derive(Debug)]
+pub struct ExchangeAccumulator {
+    pub(crate) reg: Register,
+}
+
+impl ExchangeAccumulator {
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let reg = Register::decode_reg(bytes[0] & 0b0000_0111, true);
+
+        Self { reg }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        1
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        res.push_str(&format!(
+            "xchg ax, {}\r\n",
+            self.reg.register_mode_to_string()
+        ));
+        res
+    }
+}
+
+/// `in`/`out` through a fixed port (`1110010w`, `1110011w`) or the port in
+/// DX (`1110110w`, `1110111w`).
+#[derive(Debug)]
+pub struct Port {
+    pub(crate) out: bool,
+    pub(crate) w: bool,
+    /// The fixed port, or `None` for DX.
+    pub(crate) port: Option<u8>,
+}
+
+impl Port {
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let out = (bytes[0] & 0b0000_0010) == 0b0000_0010;
+        let w = (bytes[0] & 0b0000_0001) == 0b0000_0001;
+        let port = if bytes[0] & 0b0000_1000 == 0 {
+            Some(bytes[1])
+        } else {
+            None
+        };
+
+        Self { out, w, port }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        if self.port.is_some() {
+            2
+        } else {
+            1
+        }
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        let acc = if self.w { Register::AX } else { Register::AL }.register_mode_to_string();
+        let port = match self.port {
+            Some(port) => port.to_string(),
+            None => "dx".to_string(),
+        };
+
+        if self.out {
+            res.push_str(&format!("out {}, {}\r\n", port, acc));
+        } else {
+            res.push_str(&format!("in {}, {}\r\n", acc, port));
+        }
+        res
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadOp {
+    Lea,
+    Lds,
+    Les,
+}
+
+impl LoadOp {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            LoadOp::Lea => "lea",
+            LoadOp::Lds => "lds",
+            LoadOp::Les => "les",
+        }
+    }
+}
+
+/// `lea`, `lds` and `les` (`10001101`, `11000101`, `11000100`), which load
+/// a register from the address of a memory operand or from a far pointer
+/// stored there.
+#[derive(Debug)]
+pub struct LoadAddress {
+    pub(crate) op: LoadOp,
+    pub(crate) mode: Mode,
+    pub(crate) reg: Register,
+    pub(crate) rm: Register,
+    pub(crate) disp_lo: Option<u8>,
+    pub(crate) disp_hi: Option<u8>,
+}
+
+impl LoadAddress {
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let op = match bytes[0] {
+            0x8D => LoadOp::Lea,
+            0xC5 => LoadOp::Lds,
+            0xC4 => LoadOp::Les,
+            _ => unreachable!(),
+        };
+
+        let (mode, rm, disp_lo, disp_hi) = Mode::decode_rm(bytes, true);
+        let reg = Register::decode_reg((bytes[1] & 0b0011_1000) >> 3, true);
+
+        Self {
+            op,
+            mode,
+            reg,
+            rm,
+            disp_lo,
+            disp_hi,
+        }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        2 + self.mode.displacement_len()
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        let rm = self.rm.rm_to_string(self.mode, self.disp_lo, self.disp_hi);
+
+        res.push_str(&format!(
+            "{} {}, {}\r\n",
+            self.op.mnemonic(),
+            self.reg.register_mode_to_string(),
+            rm
+        ));
+        res
+    }
+}
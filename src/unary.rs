@@ -0,0 +1,163 @@
+use crate::{mode::Mode, register::Register};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Inc,
+    Dec,
+    Not,
+    Neg,
+    Mul,
+    Imul,
+    Div,
+    Idiv,
+}
+
+impl UnaryOp {
+    /// Decodes the reg field of `FE`/`FF` (`inc`, `dec`) or of `F6`/`F7`
+    /// (`not` to `idiv`), depending on `opcode`.
+    pub fn decode(opcode: u8, bits: u8) -> Self {
+        match (opcode, bits) {
+            (0xFE | 0xFF, 0b000) => Self::Inc,
+            (0xFE | 0xFF, 0b001) => Self::Dec,
+            (0xF6 | 0xF7, 0b010) => Self::Not,
+            (0xF6 | 0xF7, 0b011) => Self::Neg,
+            (0xF6 | 0xF7, 0b100) => Self::Mul,
+            (0xF6 | 0xF7, 0b101) => Self::Imul,
+            (0xF6 | 0xF7, 0b110) => Self::Div,
+            (0xF6 | 0xF7, 0b111) => Self::Idiv,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            UnaryOp::Inc => "inc",
+            UnaryOp::Dec => "dec",
+            UnaryOp::Not => "not",
+            UnaryOp::Neg => "neg",
+            UnaryOp::Mul => "mul",
+            UnaryOp::Imul => "imul",
+            UnaryOp::Div => "div",
+            UnaryOp::Idiv => "idiv",
+        }
+    }
+}
+
+/// Single-operand arithmetic on a register or memory operand: `inc` and
+/// `dec` (`1111111w`), and `not` to `idiv` (`1111011w`).
+#[derive(Debug)]
+pub struct RegisterMemory {
+    pub(crate) op: UnaryOp,
+    pub(crate) w: bool,
+    pub(crate) mode: Mode,
+    pub(crate) rm: Register,
+    pub(crate) disp_lo: Option<u8>,
+    pub(crate) disp_hi: Option<u8>,
+}
+
+impl RegisterMemory {
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let op = UnaryOp::decode(bytes[0], (bytes[1] & 0b0011_1000) >> 3);
+        let w = (bytes[0] & 0b0000_0001) == 0b0000_0001;
+
+        let (mode, rm, disp_lo, disp_hi) = Mode::decode_rm(bytes, w);
+
+        Self {
+            op,
+            w,
+            mode,
+            rm,
+            disp_lo,
+            disp_hi,
+        }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        2 + self.mode.displacement_len()
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        let size = match (self.mode, self.w) {
+            (Mode::Reg, _) => "",
+            (_, true) => "word ",
+            (_, false) => "byte ",
+        };
+        let rm = self.rm.rm_to_string(self.mode, self.disp_lo, self.disp_hi);
+
+        res.push_str(&format!("{} {}{}\r\n", self.op.mnemonic(), size, rm));
+        res
+    }
+}
+
+/// `inc`/`dec` of a word register encoded in the opcode (`01000reg`,
+/// `01001reg`).
+#[derive(Debug)]
+pub struct WordRegister {
+    pub(crate) op: UnaryOp,
+    pub(crate) reg: Register,
+}
+
+impl WordRegister {
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let op = if bytes[0] & 0b0000_1000 == 0 {
+            UnaryOp::Inc
+        } else {
+            UnaryOp::Dec
+        };
+        let reg = Register::decode_reg(bytes[0] & 0b0000_0111, true);
+
+        Self { op, reg }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        1
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        res.push_str(&format!(
+            "{} {}\r\n",
+            self.op.mnemonic(),
+            self.reg.register_mode_to_string()
+        ));
+        res
+    }
+}
+
+/// `aam` and `aad` (`11010100`, `11010101`), followed by the number base,
+/// which is 10 unless the encoding was written by hand.
+#[derive(Debug)]
+pub struct AsciiAdjust {
+    /// `aam` rather than `aad`.
+    pub(crate) multiply: bool,
+    pub(crate) base: u8,
+}
+
+impl AsciiAdjust {
+    pub(crate) fn decode(bytes: &[u8]) -> Self {
+        let multiply = bytes[0] == 0xD4;
+        let base = bytes[1];
+
+        Self { multiply, base }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        2
+    }
+
+    pub(crate) fn to_string(&self) -> String {
+        let mut res = String::new();
+
+        let mnemonic = if self.multiply { "aam" } else { "aad" };
+
+        if self.base == 10 {
+            res.push_str(&format!("{}\r\n", mnemonic));
+        } else {
+            res.push_str(&format!("{} {}\r\n", mnemonic, self.base));
+        }
+        res
+    }
+}
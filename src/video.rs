@@ -0,0 +1,288 @@
+use crate::{cpu::Cpu, loader::install_service, memory::Memory, register::Register};
+
+pub const COLUMNS: u16 = 80;
+pub const ROWS: u16 = 25;
+
+/// Where the IBM PC BIOS has its INT 10h entry. Execution reaching the stub
+/// placed there hands the call over to [`Video::service`].
+const ENTRY: (u16, u16) = (0xF000, 0xF065);
+
+/// The BIOS data area fields video services keep their state in, so that it
+/// lives in simulated memory with everything else.
+const BDA_SEGMENT: u16 = 0x0040;
+const BDA_MODE: u16 = 0x49;
+const BDA_COLUMNS: u16 = 0x4A;
+const BDA_CURSOR: u16 = 0x50;
+
+const MDA_MODE: u8 = 7;
+const CGA_MODE: u8 = 3;
+
+/// Light grey on black.
+const DEFAULT_ATTRIBUTE: u8 = 0x07;
+
+/// CGA colour numbers, in the order of ANSI colour numbers.
+const ANSI_COLOURS: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+/// Code page 437 glyphs for the control characters and the upper half.
+const CP437_LOW: &str = " ☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼";
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+/// An 80x25 text mode display adapter, CGA with its buffer at B800:0000 or
+/// MDA at B000:0000, and the INT 10h services that write to it: set mode
+/// (00h), set and get the cursor (02h, 03h), scroll (06h, 07h), read and
+/// write characters (08h-0Ah), teletype output (0Eh) and get mode (0Fh).
+/// Programs may also write to the buffer directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Video {
+    mono: bool,
+}
+
+impl Video {
+    pub fn cga() -> Self {
+        Self { mono: false }
+    }
+
+    pub fn mda() -> Self {
+        Self { mono: true }
+    }
+
+    /// Points INT 10h at the service stub and sets the initial text mode.
+    pub fn install(&self, cpu: &mut Cpu) {
+        install_service(&mut cpu.memory, 0x10, ENTRY);
+        set_mode(&mut cpu.memory, if self.mono { MDA_MODE } else { CGA_MODE });
+    }
+
+    /// Whether `addr` is the service stub.
+    pub fn is_entry(&self, addr: usize) -> bool {
+        Memory::physical_address(ENTRY.0, ENTRY.1) == addr
+    }
+
+    /// Performs the INT 10h call in AH. Like the BIOS, functions that aren't
+    /// emulated do nothing.
+    pub fn service(&self, cpu: &mut Cpu) {
+        let registers = &cpu.registers;
+        let (ah, al) = (
+            registers.get(Register::AH) as u8,
+            registers.get(Register::AL) as u8,
+        );
+        let (row, column) = (registers.get(Register::DH), registers.get(Register::DL));
+
+        match ah {
+            0x00 => set_mode(&mut cpu.memory, al & 0x7F),
+            0x02 => set_cursor(&mut cpu.memory, row.min(ROWS - 1), column.min(COLUMNS - 1)),
+            0x03 => {
+                let (row, column) = cursor(&cpu.memory);
+                cpu.registers.set(Register::DH, row);
+                cpu.registers.set(Register::DL, column);
+                cpu.registers.set(Register::CX, 0x0607);
+            }
+            0x06 | 0x07 => {
+                let window = (
+                    cpu.registers.get(Register::CH).min(ROWS - 1),
+                    cpu.registers.get(Register::CL).min(COLUMNS - 1),
+                    row.min(ROWS - 1),
+                    column.min(COLUMNS - 1),
+                );
+                let attribute = cpu.registers.get(Register::BH) as u8;
+
+                scroll(&mut cpu.memory, window, al as u16, ah == 0x06, attribute);
+            }
+            0x08 => {
+                let (row, column) = cursor(&cpu.memory);
+                let cell = cpu.memory.read_word(buffer(&cpu.memory), cell(row, column));
+                cpu.registers.set(Register::AX, cell);
+            }
+            0x09 | 0x0A => {
+                let (row, column) = cursor(&cpu.memory);
+                let count = cpu.registers.get(Register::CX);
+                let attribute = cpu.registers.get(Register::BL) as u8;
+                let segment = buffer(&cpu.memory);
+
+                // Writing doesn't move the cursor, and stops at the end of
+                // the screen.
+                let start = row * COLUMNS + column;
+                let end = (start + count).min(ROWS * COLUMNS);
+                for position in start..end {
+                    cpu.memory.write_byte(segment, 2 * position, al);
+                    if ah == 0x09 {
+                        cpu.memory.write_byte(segment, 2 * position + 1, attribute);
+                    }
+                }
+            }
+            0x0E => teletype(&mut cpu.memory, al),
+            0x0F => {
+                let mode = cpu.memory.read_byte(BDA_SEGMENT, BDA_MODE);
+                cpu.registers.set(Register::AL, mode as u16);
+                cpu.registers.set(Register::AH, COLUMNS);
+                cpu.registers.set(Register::BH, 0);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The screen as text, one line per row with trailing blanks removed.
+pub fn screen_text(memory: &Memory) -> String {
+    let mut res = String::new();
+
+    for row in 0..ROWS {
+        let line: String = (0..COLUMNS)
+            .map(|column| glyph(memory.read_byte(buffer(memory), cell(row, column))))
+            .collect();
+
+        res.push_str(line.trim_end());
+        res.push_str("\r\n");
+    }
+
+    res
+}
+
+/// The screen with its colours as ANSI escape sequences, for a terminal.
+pub fn screen_ansi(memory: &Memory) -> String {
+    let mut res = String::new();
+
+    for row in 0..ROWS {
+        let mut current = None;
+
+        for column in 0..COLUMNS {
+            let segment = buffer(memory);
+            let character = memory.read_byte(segment, cell(row, column));
+            let attribute = memory.read_byte(segment, cell(row, column) + 1);
+
+            if current != Some(attribute) {
+                let foreground = ANSI_COLOURS[(attribute & 0x07) as usize];
+                let background = ANSI_COLOURS[((attribute >> 4) & 0x07) as usize];
+                let bright = if attribute & 0x08 != 0 { 90 } else { 30 };
+
+                res.push_str(&format!(
+                    "\x1b[{};{}m",
+                    bright + foreground,
+                    40 + background
+                ));
+                current = Some(attribute);
+            }
+            res.push(glyph(character));
+        }
+
+        res.push_str("\x1b[0m\r\n");
+    }
+
+    res
+}
+
+/// Segment of the text buffer for the current mode.
+fn buffer(memory: &Memory) -> u16 {
+    if memory.read_byte(BDA_SEGMENT, BDA_MODE) == MDA_MODE {
+        0xB000
+    } else {
+        0xB800
+    }
+}
+
+/// Offset of the character of a cell in the text buffer.
+fn cell(row: u16, column: u16) -> u16 {
+    2 * (row * COLUMNS + column)
+}
+
+fn glyph(character: u8) -> char {
+    match character {
+        0x00 => ' ',
+        0x01..=0x1F => CP437_LOW.chars().nth(character as usize).unwrap(),
+        0x7F => '⌂',
+        0x80..=0xFF => CP437_HIGH.chars().nth(character as usize - 0x80).unwrap(),
+        _ => character as char,
+    }
+}
+
+fn cursor(memory: &Memory) -> (u16, u16) {
+    let column = memory.read_byte(BDA_SEGMENT, BDA_CURSOR) as u16;
+    let row = memory.read_byte(BDA_SEGMENT, BDA_CURSOR + 1) as u16;
+
+    (row, column)
+}
+
+fn set_cursor(memory: &mut Memory, row: u16, column: u16) {
+    memory.write_byte(BDA_SEGMENT, BDA_CURSOR, column as u8);
+    memory.write_byte(BDA_SEGMENT, BDA_CURSOR + 1, row as u8);
+}
+
+/// Sets the mode, clears the screen and homes the cursor.
+fn set_mode(memory: &mut Memory, mode: u8) {
+    memory.write_byte(BDA_SEGMENT, BDA_MODE, mode);
+    memory.write_word(BDA_SEGMENT, BDA_COLUMNS, COLUMNS);
+    set_cursor(memory, 0, 0);
+
+    scroll(
+        memory,
+        (0, 0, ROWS - 1, COLUMNS - 1),
+        0,
+        true,
+        DEFAULT_ATTRIBUTE,
+    );
+}
+
+/// Scrolls the window `(top, left, bottom, right)` up or down by `lines`,
+/// filling the lines that appear with blanks of `attribute`. Zero lines, or
+/// more than the window holds, clear it.
+fn scroll(memory: &mut Memory, window: (u16, u16, u16, u16), lines: u16, up: bool, attribute: u8) {
+    let (top, left, bottom, right) = window;
+    if top > bottom || left > right {
+        return;
+    }
+
+    let height = bottom - top + 1;
+    let lines = if lines == 0 || lines > height {
+        height
+    } else {
+        lines
+    };
+    let segment = buffer(memory);
+    let blank = u16::from_le_bytes([b' ', attribute]);
+
+    let rows: Vec<u16> = if up {
+        (top..=bottom).collect()
+    } else {
+        (top..=bottom).rev().collect()
+    };
+    for (i, row) in rows.iter().enumerate() {
+        let source = rows.get(i + lines as usize);
+
+        for column in left..=right {
+            let value = match source {
+                Some(source) => memory.read_word(segment, cell(*source, column)),
+                None => blank,
+            };
+            memory.write_word(segment, cell(*row, column), value);
+        }
+    }
+}
+
+/// Writes a character at the cursor as a terminal would, handling bell,
+/// backspace, line feed and carriage return, and scrolling the screen up
+/// when the cursor moves past the bottom.
+fn teletype(memory: &mut Memory, character: u8) {
+    let (mut row, mut column) = cursor(memory);
+
+    match character {
+        0x07 => {}
+        0x08 => column = column.saturating_sub(1),
+        0x0A => row += 1,
+        0x0D => column = 0,
+        _ => {
+            memory.write_byte(buffer(memory), cell(row, column), character);
+            column += 1;
+            if column == COLUMNS {
+                column = 0;
+                row += 1;
+            }
+        }
+    }
+
+    if row == ROWS {
+        let attribute = memory.read_byte(buffer(memory), cell(ROWS - 1, COLUMNS - 1) + 1);
+        scroll(memory, (0, 0, ROWS - 1, COLUMNS - 1), 1, true, attribute);
+        row = ROWS - 1;
+    }
+
+    set_cursor(memory, row, column);
+}